@@ -0,0 +1,111 @@
+use super::Catalog;
+use crate::storage::blobs::blob_key;
+use rusqlite::{params, Connection};
+
+/// A catalogued file (or superseded version) whose contents still sit under its own storage
+/// key rather than in a blob.
+#[derive(Debug, Clone)]
+pub struct LegacyObject {
+    pub storage_key: String,
+    pub video_id: i64,
+    /// `None` for the current contents of the video.
+    pub version: Option<i64>,
+}
+
+/// Take a reference to the blob `sha256`, recording it if this is the first.
+///
+/// A new blob has just been hashed on its way in, so it counts as checked.
+pub(super) fn add_ref(
+    conn: &Connection,
+    sha256: &str,
+    md5: Option<&str>,
+    size: u64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO blobs (sha256, md5, size, ref_count, checked_at, fixity)
+         VALUES (?1, ?2, ?3, 1, unixepoch(), 'ok')
+         ON CONFLICT (sha256) DO UPDATE SET
+            ref_count = ref_count + 1,
+            md5 = coalesce(md5, excluded.md5)",
+        params![sha256, md5, size as i64],
+    )
+    .map(drop)
+}
+
+/// Drop a reference to the blob `sha256`. Returns the hash if that was the last one, in which
+/// case the blob itself should be deleted from storage.
+pub(super) fn release_ref(conn: &Connection, sha256: &str) -> rusqlite::Result<Option<String>> {
+    conn.execute(
+        "UPDATE blobs SET ref_count = ref_count - 1 WHERE sha256 = ?1",
+        [sha256],
+    )?;
+    let removed = conn.execute(
+        "DELETE FROM blobs WHERE sha256 = ?1 AND ref_count <= 0",
+        [sha256],
+    )?;
+    Ok((removed > 0).then(|| sha256.to_string()))
+}
+
+impl Catalog {
+    /// Files and versions that have not been moved into blob storage yet.
+    pub async fn legacy_objects(&self) -> anyhow::Result<Vec<LegacyObject>> {
+        self.call(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT file_name, id, NULL FROM videos WHERE sha256 IS NULL
+                 UNION ALL
+                 SELECT storage_key, video_id, version FROM video_versions WHERE sha256 IS NULL",
+            )?;
+            let objects = statement.query_map([], |row| {
+                Ok(LegacyObject {
+                    storage_key: row.get(0)?,
+                    video_id: row.get(1)?,
+                    version: row.get(2)?,
+                })
+            })?;
+            objects.collect()
+        })
+        .await
+    }
+
+    /// Point a legacy object at the blob its contents were moved to.
+    pub async fn attach_blob(
+        &self,
+        object: &LegacyObject,
+        sha256: &str,
+        md5: &str,
+        size: u64,
+    ) -> anyhow::Result<()> {
+        let object = object.clone();
+        let sha256 = sha256.to_string();
+        let md5 = md5.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            add_ref(&tx, &sha256, Some(&md5), size)?;
+            match object.version {
+                Some(version) => tx.execute(
+                    "UPDATE video_versions SET sha256 = ?3, storage_key = ?4
+                     WHERE video_id = ?1 AND version = ?2",
+                    params![object.video_id, version, sha256, blob_key(&sha256)],
+                )?,
+                None => tx.execute(
+                    "UPDATE videos SET sha256 = ?2 WHERE id = ?1",
+                    params![object.video_id, sha256],
+                )?,
+            };
+            tx.commit()
+        })
+        .await
+    }
+
+    /// Forget a superseded version whose contents are gone.
+    pub async fn delete_version(&self, video_id: i64, version: i64) -> anyhow::Result<()> {
+        self.call(move |conn| {
+            conn.execute(
+                "DELETE FROM video_versions WHERE video_id = ?1 AND version = ?2",
+                params![video_id, version],
+            )
+            .map(drop)
+        })
+        .await
+    }
+}
@@ -0,0 +1,122 @@
+use super::{Catalog, LIST_SEPARATOR};
+use crate::models::{Fixity, FlaggedBlob};
+use rusqlite::{params, OptionalExtension};
+use std::collections::BTreeMap;
+
+/// A stored blob and what was recorded about it at ingest.
+#[derive(Debug, Clone)]
+pub struct BlobRecord {
+    pub sha256: String,
+    /// Unknown for blobs stored before MD5s were recorded.
+    pub md5: Option<String>,
+    pub size: u64,
+}
+
+/// Values outside [`Fixity`] can only come from a hand-edited catalog; treat them as unchecked.
+pub(super) fn from_column(value: Option<String>) -> Option<Fixity> {
+    value.and_then(|value| value.parse().ok())
+}
+
+impl Catalog {
+    /// Blobs last checked before `cutoff` (or never), least recently checked first.
+    pub async fn blobs_due(&self, cutoff: i64) -> anyhow::Result<Vec<BlobRecord>> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT sha256, md5, size FROM blobs
+                 WHERE checked_at IS NULL OR checked_at < ?1
+                 ORDER BY checked_at",
+            )?;
+            let blobs = statement.query_map([cutoff], |row| {
+                Ok(BlobRecord {
+                    sha256: row.get(0)?,
+                    md5: row.get(1)?,
+                    size: row.get::<_, i64>(2)? as u64,
+                })
+            })?;
+            blobs.collect()
+        })
+        .await
+    }
+
+    /// The result of the last check of `sha256`, if it has been checked.
+    pub async fn blob_fixity(&self, sha256: &str) -> anyhow::Result<Option<Fixity>> {
+        let sha256 = sha256.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                "SELECT fixity FROM blobs WHERE sha256 = ?1",
+                [sha256],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(|fixity| from_column(fixity.flatten()))
+        })
+        .await
+    }
+
+    /// Record the result of checking `sha256`. Returns `false` if the blob is no longer
+    /// catalogued, e.g. because its last file was deleted while it was being checked.
+    pub async fn record_fixity(
+        &self,
+        sha256: &str,
+        fixity: Fixity,
+        detail: Option<String>,
+        checked_at: i64,
+    ) -> anyhow::Result<bool> {
+        let sha256 = sha256.to_string();
+        self.call(move |conn| {
+            let updated = conn.execute(
+                "UPDATE blobs SET fixity = ?2, fixity_detail = ?3, checked_at = ?4
+                 WHERE sha256 = ?1",
+                params![sha256, fixity.as_str(), detail, checked_at],
+            )?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Blobs whose last check found them damaged, with the files that use them.
+    pub async fn flagged_blobs(&self) -> anyhow::Result<Vec<FlaggedBlob>> {
+        self.call(|conn| {
+            let mut statement = conn.prepare(
+                "SELECT sha256, size, fixity, fixity_detail, checked_at,
+                    (SELECT group_concat(file_name, char(31)) FROM (
+                        SELECT file_name FROM videos WHERE sha256 = b.sha256
+                        UNION
+                        SELECT v.file_name FROM video_versions vv
+                        JOIN videos v ON v.id = vv.video_id
+                        WHERE vv.sha256 = b.sha256
+                    )) AS files
+                 FROM blobs b
+                 WHERE fixity IS NOT NULL AND fixity != 'ok'
+                 ORDER BY checked_at DESC",
+            )?;
+            let blobs = statement.query_map([], |row| {
+                let files: Option<String> = row.get("files")?;
+                Ok(FlaggedBlob {
+                    sha256: row.get("sha256")?,
+                    size: row.get::<_, i64>("size")? as u64,
+                    fixity: from_column(row.get("fixity")?).unwrap_or(Fixity::Mismatch),
+                    detail: row.get("fixity_detail")?,
+                    checked_at: row.get("checked_at")?,
+                    files: files
+                        .map(|files| files.split(LIST_SEPARATOR).map(str::to_string).collect())
+                        .unwrap_or_default(),
+                })
+            })?;
+            blobs.collect()
+        })
+        .await
+    }
+
+    /// Number of blobs by the result of their last check; `unchecked` for those never checked.
+    pub async fn fixity_counts(&self) -> anyhow::Result<BTreeMap<String, u64>> {
+        self.call(|conn| {
+            let mut statement = conn
+                .prepare("SELECT coalesce(fixity, 'unchecked'), count(*) FROM blobs GROUP BY 1")?;
+            let counts =
+                statement.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?;
+            counts.collect()
+        })
+        .await
+    }
+}
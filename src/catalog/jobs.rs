@@ -0,0 +1,339 @@
+use super::Catalog;
+use crate::models::{Job, JobKind, JobLogEntry, JobState};
+use rusqlite::{params, types::Type, OptionalExtension, Row};
+
+const SELECT_JOB: &str = "SELECT j.id, j.kind, j.video_id, v.file_name, j.sha256, j.state,
+        j.priority, j.attempts, j.max_attempts, j.created_at, j.run_after, j.started_at,
+        j.finished_at, j.error, j.log
+    FROM jobs j JOIN videos v ON v.id = j.video_id";
+
+/// A job to queue.
+#[derive(Debug, Clone)]
+pub struct NewJob {
+    pub kind: JobKind,
+    pub video_id: i64,
+    pub sha256: String,
+    pub priority: i64,
+    pub max_attempts: u32,
+    pub created_at: i64,
+}
+
+/// Which jobs to list, newest first.
+#[derive(Debug, Clone, Default)]
+pub struct JobFilter {
+    pub kind: Option<JobKind>,
+    pub state: Option<JobState>,
+    pub video_id: Option<i64>,
+    pub limit: u32,
+}
+
+/// Values outside [`JobState`] can only come from a hand-edited catalog; treat them as none.
+pub(super) fn state_from_column(value: Option<String>) -> Option<JobState> {
+    value.and_then(|value| value.parse().ok())
+}
+
+/// A line of a job's log. Messages are kept to one line each.
+fn log_line(at: i64, message: &str) -> String {
+    format!("{}\t{}\n", at, message.replace(['\n', '\r'], " "))
+}
+
+fn parse_log(log: &str) -> Vec<JobLogEntry> {
+    log.lines()
+        .filter_map(|line| {
+            let (at, message) = line.split_once('\t')?;
+            Some(JobLogEntry {
+                at: at.parse().ok()?,
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn parse_column<T>(row: &Row<'_>, column: &str) -> rusqlite::Result<T>
+where
+    T: std::str::FromStr<Err = String>,
+{
+    let value: String = row.get(column)?;
+    value.parse().map_err(|err: String| {
+        let index = row.as_ref().column_index(column).unwrap_or_default();
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, err.into())
+    })
+}
+
+fn job_from_row(row: &Row<'_>, with_log: bool) -> rusqlite::Result<Job> {
+    let log = match with_log {
+        true => Some(parse_log(&row.get::<_, String>("log")?)),
+        false => None,
+    };
+    Ok(Job {
+        id: row.get("id")?,
+        kind: parse_column(row, "kind")?,
+        video_id: row.get("video_id")?,
+        file_name: row.get("file_name")?,
+        sha256: row.get("sha256")?,
+        state: parse_column(row, "state")?,
+        priority: row.get("priority")?,
+        attempts: row.get("attempts")?,
+        max_attempts: row.get("max_attempts")?,
+        created_at: row.get("created_at")?,
+        run_after: row.get("run_after")?,
+        started_at: row.get("started_at")?,
+        finished_at: row.get("finished_at")?,
+        error: row.get("error")?,
+        log,
+    })
+}
+
+impl Catalog {
+    /// Queue `job`, unless the same work is already queued or running, or the video no longer
+    /// has those contents. Returns the new job's id.
+    pub async fn enqueue_job(&self, job: NewJob) -> anyhow::Result<Option<i64>> {
+        self.call(move |conn| {
+            conn.query_row(
+                "INSERT INTO jobs (kind, video_id, sha256, state, priority, max_attempts,
+                    created_at, run_after, log)
+                 SELECT ?1, ?2, ?3, 'queued', ?4, ?5, ?6, ?6, ?7
+                 WHERE EXISTS (SELECT 1 FROM videos WHERE id = ?2 AND sha256 = ?3)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM jobs WHERE kind = ?1 AND video_id = ?2 AND sha256 = ?3
+                            AND state IN ('queued', 'running')
+                    )
+                 RETURNING id",
+                params![
+                    job.kind.as_str(),
+                    job.video_id,
+                    job.sha256,
+                    job.priority,
+                    job.max_attempts,
+                    job.created_at,
+                    log_line(job.created_at, "queued")
+                ],
+                |row| row.get(0),
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// Queue a `kind` job for every video whose current contents never had one, such as those
+    /// archived before the queue existed. Probes are only queued for files not yet probed.
+    /// Returns how many were queued.
+    pub async fn backfill_jobs(
+        &self,
+        kind: JobKind,
+        priority: i64,
+        max_attempts: u32,
+        now: i64,
+    ) -> anyhow::Result<usize> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO jobs (kind, video_id, sha256, state, priority, max_attempts,
+                    created_at, run_after, log)
+                 SELECT ?1, v.id, v.sha256, 'queued', ?2, ?3, ?4, ?4, ?5 FROM videos v
+                 WHERE v.sha256 IS NOT NULL
+                    AND (?1 != 'probe' OR v.probed_at IS NULL)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM jobs WHERE kind = ?1 AND video_id = v.id
+                            AND sha256 = v.sha256
+                    )
+                 ORDER BY v.id",
+                params![
+                    kind.as_str(),
+                    priority,
+                    max_attempts,
+                    now,
+                    log_line(now, "queued for a video archived before it")
+                ],
+            )
+        })
+        .await
+    }
+
+    /// Start the next `kind` job that is due: the highest priority, then the oldest.
+    pub async fn claim_job(&self, kind: JobKind, now: i64) -> anyhow::Result<Option<Job>> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let id: Option<i64> = tx
+                .query_row(
+                    "UPDATE jobs SET state = 'running', attempts = attempts + 1, started_at = ?2,
+                        log = log || ?2 || char(9) || 'attempt ' || (attempts + 1) || ' started'
+                            || char(10)
+                     WHERE id = (
+                        SELECT id FROM jobs WHERE kind = ?1 AND state = 'queued' AND run_after <= ?2
+                        ORDER BY priority DESC, id LIMIT 1
+                     )
+                     RETURNING id",
+                    params![kind.as_str(), now],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            let Some(id) = id else {
+                return Ok(None);
+            };
+
+            let job = tx.query_row(&format!("{} WHERE j.id = ?1", SELECT_JOB), [id], |row| {
+                job_from_row(row, false)
+            })?;
+            tx.commit()?;
+            Ok(Some(job))
+        })
+        .await
+    }
+
+    /// Record that running job `id` finished its work. Returns `false` if it was cancelled (or
+    /// its video deleted) meanwhile.
+    pub async fn complete_job(&self, id: i64, message: &str, now: i64) -> anyhow::Result<bool> {
+        let line = log_line(now, message);
+        self.call(move |conn| {
+            let updated = conn.execute(
+                "UPDATE jobs SET state = 'succeeded', finished_at = ?2, error = NULL,
+                    log = log || ?3
+                 WHERE id = ?1 AND state = 'running'",
+                params![id, now, line],
+            )?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Record that an attempt of running job `id` failed with `error`: it is queued again for
+    /// `retry_at`, or failed for good if `None`. Returns `false` if it was cancelled (or its video
+    /// deleted) meanwhile.
+    pub async fn fail_job(
+        &self,
+        id: i64,
+        error: &str,
+        retry_at: Option<i64>,
+        now: i64,
+    ) -> anyhow::Result<bool> {
+        let line = match retry_at {
+            Some(retry_at) => log_line(
+                now,
+                &format!("failed, retrying in {}s: {}", retry_at - now, error),
+            ),
+            None => log_line(now, &format!("failed, giving up: {}", error)),
+        };
+        let error = error.to_string();
+        self.call(move |conn| {
+            let updated = conn.execute(
+                "UPDATE jobs SET
+                    state = CASE WHEN ?3 IS NULL THEN 'failed' ELSE 'queued' END,
+                    run_after = coalesce(?3, run_after),
+                    finished_at = CASE WHEN ?3 IS NULL THEN ?4 END,
+                    error = ?2, log = log || ?5
+                 WHERE id = ?1 AND state = 'running'",
+                params![id, error, retry_at, now, line],
+            )?;
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    /// Cancel job `id` if it hasn't finished. Returns the job as it is now, or `None` if there is
+    /// no such job.
+    pub async fn cancel_job(&self, id: i64, now: i64) -> anyhow::Result<Option<Job>> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE jobs SET state = 'cancelled', finished_at = ?2, log = log || ?3
+                 WHERE id = ?1 AND state IN ('queued', 'running')",
+                params![id, now, log_line(now, "cancelled")],
+            )?;
+            conn.query_row(&format!("{} WHERE j.id = ?1", SELECT_JOB), [id], |row| {
+                job_from_row(row, true)
+            })
+            .optional()
+        })
+        .await
+    }
+
+    /// Cancel every unfinished job of video `video_id`. Returns the ids of those cancelled.
+    pub async fn cancel_video_jobs(&self, video_id: i64, now: i64) -> anyhow::Result<Vec<i64>> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(
+                "UPDATE jobs SET state = 'cancelled', finished_at = ?2, log = log || ?3
+                 WHERE video_id = ?1 AND state IN ('queued', 'running')
+                 RETURNING id",
+            )?;
+            let ids = statement.query_map(
+                params![
+                    video_id,
+                    now,
+                    log_line(now, "cancelled, the video is being deleted")
+                ],
+                |row| row.get(0),
+            )?;
+            ids.collect()
+        })
+        .await
+    }
+
+    /// Queue again the jobs that were running when the server last stopped. Returns how many
+    /// there were.
+    pub async fn requeue_interrupted(&self, now: i64) -> anyhow::Result<usize> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE jobs SET state = 'queued', run_after = ?1, log = log || ?2
+                 WHERE state = 'running'",
+                params![now, log_line(now, "interrupted by a restart, queued again")],
+            )
+        })
+        .await
+    }
+
+    /// Job `id` with its log.
+    pub async fn get_job(&self, id: i64) -> anyhow::Result<Option<Job>> {
+        self.call(move |conn| {
+            conn.query_row(&format!("{} WHERE j.id = ?1", SELECT_JOB), [id], |row| {
+                job_from_row(row, true)
+            })
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn list_jobs(&self, filter: JobFilter) -> anyhow::Result<Vec<Job>> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(&format!(
+                "{} WHERE (?1 IS NULL OR j.kind = ?1) AND (?2 IS NULL OR j.state = ?2)
+                    AND (?3 IS NULL OR j.video_id = ?3)
+                 ORDER BY j.id DESC LIMIT ?4",
+                SELECT_JOB
+            ))?;
+            let jobs = statement.query_map(
+                params![
+                    filter.kind.map(JobKind::as_str),
+                    filter.state.map(JobState::as_str),
+                    filter.video_id,
+                    filter.limit
+                ],
+                |row| job_from_row(row, false),
+            )?;
+            jobs.collect()
+        })
+        .await
+    }
+
+    /// Forget jobs that finished before `cutoff`. Returns how many there were.
+    pub async fn prune_jobs(&self, cutoff: i64) -> anyhow::Result<usize> {
+        self.call(move |conn| {
+            conn.execute(
+                "DELETE FROM jobs WHERE state NOT IN ('queued', 'running') AND finished_at < ?1",
+                [cutoff],
+            )
+        })
+        .await
+    }
+
+    /// Number of jobs by kind and state.
+    pub async fn job_counts(&self) -> anyhow::Result<Vec<(String, String, u64)>> {
+        self.call(|conn| {
+            let mut statement =
+                conn.prepare("SELECT kind, state, count(*) FROM jobs GROUP BY kind, state")?;
+            let counts = statement.query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64))
+            })?;
+            counts.collect()
+        })
+        .await
+    }
+}
@@ -0,0 +1,378 @@
+use super::{entry_from_row, Catalog, SELECT_ENTRY};
+use crate::models::{ArchiveEntry, ArchiveSort, SortOrder};
+use rusqlite::{params_from_iter, types::Value};
+use serde::{Deserialize, Serialize};
+
+/// Where a file falls in a sort order: its sort key, then its id.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ArchivePosition {
+    pub key: SortKey,
+    pub id: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SortKey {
+    Integer(i64),
+    Text(String),
+}
+
+/// Which archived files to list, and in what order.
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveQuery {
+    pub sort: ArchiveSort,
+    pub order: SortOrder,
+    /// Only files past this one in that order.
+    pub after: Option<ArchivePosition>,
+    pub limit: u32,
+    /// An exact type, or a whole top-level type as `video/*`.
+    pub content_type: Option<String>,
+    /// Bytes, inclusive.
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Unix seconds, from `uploaded_since` up to but not including `uploaded_before`.
+    pub uploaded_since: Option<i64>,
+    pub uploaded_before: Option<i64>,
+    pub tag: Option<String>,
+}
+
+/// A page of files, with how many match in all.
+#[derive(Debug, Clone)]
+pub struct ArchivePage {
+    pub entries: Vec<ArchiveEntry>,
+    pub total: u64,
+    /// Of the last file, if there are more after it.
+    pub next: Option<ArchivePosition>,
+}
+
+/// The SQL a sort orders by. Unknown durations sort as -1, so they have a place in the order.
+fn sort_expression(sort: ArchiveSort) -> &'static str {
+    match sort {
+        ArchiveSort::Name => "v.file_name",
+        ArchiveSort::Size => "v.size",
+        ArchiveSort::UploadedAt => "v.uploaded_at",
+        ArchiveSort::Duration => "coalesce(v.duration_ms, -1)",
+    }
+}
+
+/// Where `entry` falls when sorting by `sort`.
+fn position(sort: ArchiveSort, entry: &ArchiveEntry) -> ArchivePosition {
+    let key = match sort {
+        ArchiveSort::Name => SortKey::Text(entry.file_name.clone()),
+        ArchiveSort::Size => SortKey::Integer(entry.size as i64),
+        ArchiveSort::UploadedAt => SortKey::Integer(entry.uploaded_at),
+        ArchiveSort::Duration => SortKey::Integer(entry.duration_ms.unwrap_or(-1)),
+    };
+    ArchivePosition { key, id: entry.id }
+}
+
+impl Catalog {
+    /// A page of the files `query` asks for, found by their position in its order rather than an
+    /// offset, so pages stay cheap however deep they go.
+    pub async fn list_page(&self, query: ArchiveQuery) -> anyhow::Result<ArchivePage> {
+        let mut conditions = vec![];
+        let mut values = vec![];
+        if let Some(content_type) = &query.content_type {
+            match content_type.strip_suffix('*') {
+                Some(prefix) => {
+                    conditions.push("substr(v.content_type, 1, ?) = ?");
+                    values.push(Value::Integer(prefix.chars().count() as i64));
+                    values.push(Value::Text(prefix.to_string()));
+                }
+                None => {
+                    conditions.push("v.content_type = ?");
+                    values.push(Value::Text(content_type.clone()));
+                }
+            }
+        }
+        if let Some(min_size) = query.min_size {
+            conditions.push("v.size >= ?");
+            values.push(Value::Integer(i64::try_from(min_size).unwrap_or(i64::MAX)));
+        }
+        if let Some(max_size) = query.max_size {
+            conditions.push("v.size <= ?");
+            values.push(Value::Integer(i64::try_from(max_size).unwrap_or(i64::MAX)));
+        }
+        if let Some(since) = query.uploaded_since {
+            conditions.push("v.uploaded_at >= ?");
+            values.push(Value::Integer(since));
+        }
+        if let Some(before) = query.uploaded_before {
+            conditions.push("v.uploaded_at < ?");
+            values.push(Value::Integer(before));
+        }
+        if let Some(tag) = &query.tag {
+            conditions
+                .push("EXISTS (SELECT 1 FROM video_tags t WHERE t.video_id = v.id AND t.tag = ?)");
+            values.push(Value::Text(tag.clone()));
+        }
+        let filters = match conditions.is_empty() {
+            true => String::from("1"),
+            false => conditions.join(" AND "),
+        };
+
+        let expression = sort_expression(query.sort);
+        let (past, direction) = match query.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+        let mut page_filters = filters.clone();
+        let mut page_values = values.clone();
+        if let Some(after) = &query.after {
+            page_filters.push_str(&format!(
+                " AND ({0} {1} ? OR ({0} = ? AND v.id {1} ?))",
+                expression, past
+            ));
+            let key = match &after.key {
+                SortKey::Integer(key) => Value::Integer(*key),
+                SortKey::Text(key) => Value::Text(key.clone()),
+            };
+            page_values.extend([key.clone(), key, Value::Integer(after.id)]);
+        }
+        // One more than a page, to tell whether there is another
+        page_values.push(Value::Integer(query.limit as i64 + 1));
+
+        let (sort, limit) = (query.sort, query.limit as usize);
+        self.call(move |conn| {
+            let total: i64 = conn.query_row(
+                &format!("SELECT count(*) FROM videos v WHERE {}", filters),
+                params_from_iter(values),
+                |row| row.get(0),
+            )?;
+
+            let mut statement = conn.prepare(&format!(
+                "{} WHERE {} ORDER BY {} {}, v.id {} LIMIT ?",
+                SELECT_ENTRY, page_filters, expression, direction, direction
+            ))?;
+            let mut entries = statement
+                .query_map(params_from_iter(page_values), entry_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            let next = match entries.len() > limit {
+                true => {
+                    entries.truncate(limit);
+                    entries.last().map(|entry| position(sort, entry))
+                }
+                false => None,
+            };
+            Ok(ArchivePage {
+                entries,
+                total: total as u64,
+                next,
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{catalog::NewEntry, models::MediaInfo};
+
+    /// Name, content type, size, upload time and duration; plenty of ties in every sort.
+    const FILES: [(&str, &str, u64, i64, Option<i64>); 7] = [
+        ("a.mp4", "video/mp4", 3, 10, Some(5000)),
+        ("b.webm", "video/webm", 1, 10, None),
+        ("c.mp4", "video/mp4", 3, 20, Some(5000)),
+        ("d.mkv", "video/x-matroska", 3, 10, None),
+        ("e.mp4", "video/mp4", 1, 20, Some(1000)),
+        ("f.txt", "text/plain", 2, 20, Some(5000)),
+        ("g.mp4", "video/mp4", 3, 10, None),
+    ];
+
+    async fn catalog() -> Catalog {
+        let catalog = Catalog::open(":memory:").unwrap();
+        // Inserted out of name order, so ids and names disagree
+        for (i, &(file_name, content_type, size, uploaded_at, duration_ms)) in
+            FILES.iter().enumerate().rev()
+        {
+            let sha256 = format!("{:064x}", i);
+            catalog
+                .insert(NewEntry {
+                    file_name: file_name.to_string(),
+                    content_type: content_type.to_string(),
+                    size,
+                    uploaded_at,
+                    sha256: sha256.clone(),
+                    md5: format!("{:032x}", i),
+                    original_sha256: None,
+                })
+                .await
+                .unwrap();
+            let media = MediaInfo {
+                duration_ms,
+                ..Default::default()
+            };
+            catalog
+                .set_media(file_name, &sha256, Some(media), uploaded_at)
+                .await
+                .unwrap();
+        }
+        catalog
+    }
+
+    /// Every page of `query`, `limit` files at a time, checking each page's total on the way.
+    async fn pages(catalog: &Catalog, query: ArchiveQuery, limit: u32) -> Vec<ArchiveEntry> {
+        let mut entries = vec![];
+        let mut after = None;
+        loop {
+            let page = catalog
+                .list_page(ArchiveQuery {
+                    after,
+                    limit,
+                    ..query.clone()
+                })
+                .await
+                .unwrap();
+            assert!(page.entries.len() <= limit as usize);
+            assert_eq!(page.total, query_total(catalog, &query).await);
+            entries.extend(page.entries);
+            match page.next {
+                Some(next) => after = Some(next),
+                None => return entries,
+            }
+        }
+    }
+
+    async fn query_total(catalog: &Catalog, query: &ArchiveQuery) -> u64 {
+        catalog
+            .list_page(ArchiveQuery {
+                limit: 0,
+                after: None,
+                ..query.clone()
+            })
+            .await
+            .unwrap()
+            .total
+    }
+
+    /// The order `sort` and `order` should give, worked out independently of SQL.
+    fn expected(
+        sort: ArchiveSort,
+        order: SortOrder,
+        mut entries: Vec<ArchiveEntry>,
+    ) -> Vec<String> {
+        entries.sort_by(|a, b| {
+            let key = |entry: &ArchiveEntry| match position(sort, entry).key {
+                SortKey::Integer(key) => (key, String::new()),
+                SortKey::Text(key) => (0, key),
+            };
+            let ordering = key(a).cmp(&key(b)).then(a.id.cmp(&b.id));
+            match order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+        entries.into_iter().map(|entry| entry.file_name).collect()
+    }
+
+    fn names(entries: &[ArchiveEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| entry.file_name.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_continue_across_ties() {
+        let catalog = catalog().await;
+        for sort in [
+            ArchiveSort::Name,
+            ArchiveSort::Size,
+            ArchiveSort::UploadedAt,
+            ArchiveSort::Duration,
+        ] {
+            for order in [SortOrder::Asc, SortOrder::Desc] {
+                let query = ArchiveQuery {
+                    sort,
+                    order,
+                    ..Default::default()
+                };
+                let all = pages(&catalog, query.clone(), 100).await;
+                assert_eq!(all.len(), FILES.len());
+                assert_eq!(names(&all), expected(sort, order, all.clone()));
+
+                for limit in [1, 2, 3] {
+                    let paged = pages(&catalog, query.clone(), limit).await;
+                    assert_eq!(
+                        names(&paged),
+                        names(&all),
+                        "{:?} {:?} by {}",
+                        sort,
+                        order,
+                        limit
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_durations_come_first() {
+        let catalog = catalog().await;
+        let query = ArchiveQuery {
+            sort: ArchiveSort::Duration,
+            ..Default::default()
+        };
+        let all = pages(&catalog, query, 2).await;
+        assert!(all[..3].iter().all(|entry| entry.duration_ms.is_none()));
+        assert_eq!(all[3].file_name, "e.mp4");
+    }
+
+    #[tokio::test]
+    async fn filters_every_page() {
+        let catalog = catalog().await;
+        for (query, expected) in [
+            (
+                ArchiveQuery {
+                    content_type: Some(String::from("video/*")),
+                    ..Default::default()
+                },
+                vec!["a.mp4", "b.webm", "c.mp4", "d.mkv", "e.mp4", "g.mp4"],
+            ),
+            (
+                ArchiveQuery {
+                    content_type: Some(String::from("video/mp4")),
+                    min_size: Some(2),
+                    ..Default::default()
+                },
+                vec!["a.mp4", "c.mp4", "g.mp4"],
+            ),
+            (
+                ArchiveQuery {
+                    sort: ArchiveSort::Size,
+                    order: SortOrder::Desc,
+                    max_size: Some(2),
+                    uploaded_since: Some(20),
+                    ..Default::default()
+                },
+                vec!["f.txt", "e.mp4"],
+            ),
+            (
+                ArchiveQuery {
+                    uploaded_before: Some(20),
+                    content_type: Some(String::from("video/webm")),
+                    ..Default::default()
+                },
+                vec!["b.webm"],
+            ),
+            (
+                ArchiveQuery {
+                    tag: Some(String::from("none")),
+                    ..Default::default()
+                },
+                vec![],
+            ),
+        ] {
+            let entries = pages(&catalog, query.clone(), 2).await;
+            assert_eq!(names(&entries), expected, "{:?}", query);
+            assert_eq!(
+                query_total(&catalog, &query).await,
+                expected.len() as u64,
+                "{:?}",
+                query
+            );
+        }
+    }
+}
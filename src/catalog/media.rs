@@ -0,0 +1,93 @@
+use super::{entry_from_row, Catalog, SELECT_ENTRY};
+use crate::models::{ArchiveEntry, MediaInfo, TrackKind};
+use rusqlite::{params, OptionalExtension};
+
+/// A catalogued file whose contents are to be probed.
+#[derive(Debug, Clone)]
+pub struct ProbeTarget {
+    pub file_name: String,
+    pub sha256: String,
+    pub content_type: String,
+}
+
+impl Catalog {
+    /// Store what probing the contents `sha256` of `file_name` found (`None` for nothing usable),
+    /// unless the file has been replaced meanwhile. Returns the updated entry.
+    pub async fn set_media(
+        &self,
+        file_name: &str,
+        sha256: &str,
+        media: Option<MediaInfo>,
+        probed_at: i64,
+    ) -> anyhow::Result<Option<ArchiveEntry>> {
+        let file_name = file_name.to_string();
+        let sha256 = sha256.to_string();
+        let json = media.as_ref().map(serde_json::to_string).transpose()?;
+
+        self.call(move |conn| {
+            let media = media.unwrap_or_default();
+            let video = media.first_track(TrackKind::Video);
+            let audio = media.first_track(TrackKind::Audio);
+
+            let updated = conn.execute(
+                "UPDATE videos SET
+                    duration_ms = ?3, container = ?4, video_codec = ?5, audio_codec = ?6,
+                    width = ?7, height = ?8, frame_rate = ?9, bit_rate = ?10, rotation = ?11,
+                    recorded_at = ?12, media = ?13, probed_at = ?14
+                 WHERE file_name = ?1 AND sha256 = ?2",
+                params![
+                    file_name,
+                    sha256,
+                    media.duration_ms,
+                    media.container,
+                    video.and_then(|track| track.codec.clone()),
+                    audio.and_then(|track| track.codec.clone()),
+                    video.and_then(|track| track.width),
+                    video.and_then(|track| track.height),
+                    video.and_then(|track| track.frame_rate),
+                    media.bit_rate.map(|rate| rate as i64),
+                    video.and_then(|track| track.rotation),
+                    media.recorded_at,
+                    json,
+                    probed_at
+                ],
+            )?;
+            if updated == 0 {
+                return Ok(None);
+            }
+
+            conn.query_row(
+                &format!("{} WHERE v.file_name = ?1", SELECT_ENTRY),
+                [&file_name],
+                entry_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    /// The full probe result for `file_name`, and when it was taken. `None` if there is no such
+    /// file.
+    pub async fn get_media(
+        &self,
+        file_name: &str,
+    ) -> anyhow::Result<Option<(Option<MediaInfo>, Option<i64>)>> {
+        let file_name = file_name.to_string();
+        let row = self
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT media, probed_at FROM videos WHERE file_name = ?1",
+                    [file_name],
+                    |row| Ok((row.get::<_, Option<String>>(0)?, row.get(1)?)),
+                )
+                .optional()
+            })
+            .await?;
+
+        let Some((json, probed_at)) = row else {
+            return Ok(None);
+        };
+        let media = json.map(|json| serde_json::from_str(&json)).transpose()?;
+        Ok(Some((media, probed_at)))
+    }
+}
@@ -0,0 +1,184 @@
+use super::{Catalog, LIST_SEPARATOR};
+use crate::models::VideoMetadata;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
+
+/// Custom fields that aren't a JSON object of strings can only come from a hand-edited catalog;
+/// treat them as none.
+pub(super) fn custom_from_column(value: Option<String>) -> BTreeMap<String, String> {
+    value
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default()
+}
+
+/// Tags come in the order they were set, which is the order [`Catalog::edit_metadata`] inserts
+/// them in.
+fn read_metadata(conn: &Connection, id: i64) -> rusqlite::Result<Option<(VideoMetadata, i64)>> {
+    conn.query_row(
+        "SELECT title, description, user_recorded_at, source, license, custom, metadata_revision,
+            (SELECT group_concat(tag, char(31) ORDER BY rowid) FROM video_tags
+             WHERE video_id = v.id)
+         FROM videos v WHERE id = ?1",
+        [id],
+        |row| {
+            let tags: Option<String> = row.get(7)?;
+            let metadata = VideoMetadata {
+                title: row.get(0)?,
+                description: row.get(1)?,
+                tags: tags
+                    .map(|tags| tags.split(LIST_SEPARATOR).map(str::to_string).collect())
+                    .unwrap_or_default(),
+                recorded_at: row.get(2)?,
+                source: row.get(3)?,
+                license: row.get(4)?,
+                custom: custom_from_column(row.get(5)?),
+            };
+            Ok((metadata, row.get(6)?))
+        },
+    )
+    .optional()
+}
+
+impl Catalog {
+    /// The metadata of video `id` and its revision.
+    pub async fn get_metadata(&self, id: i64) -> anyhow::Result<Option<(VideoMetadata, i64)>> {
+        self.call(move |conn| read_metadata(conn, id)).await
+    }
+
+    /// Change the metadata of video `id` with `edit`, which is given it as it stands and its
+    /// revision. Both happen in one transaction, so edits never overwrite each other unseen, and
+    /// nothing is written if `edit` fails or changes nothing. Returns `None` if there is no such
+    /// video, or else what `edit` returned, with the metadata and its revision if it succeeded.
+    pub async fn edit_metadata<E, F>(
+        &self,
+        id: i64,
+        edit: F,
+    ) -> anyhow::Result<Option<Result<(VideoMetadata, i64), E>>>
+    where
+        E: Send + 'static,
+        F: FnOnce(&mut VideoMetadata, i64) -> Result<(), E> + Send + 'static,
+    {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let Some((mut metadata, revision)) = read_metadata(&tx, id)? else {
+                return Ok(None);
+            };
+            let unchanged = metadata.clone();
+            if let Err(err) = edit(&mut metadata, revision) {
+                return Ok(Some(Err(err)));
+            }
+            // Clients holding the current revision can go on using it
+            if metadata == unchanged {
+                return Ok(Some(Ok((metadata, revision))));
+            }
+
+            let custom = match metadata.custom.is_empty() {
+                true => None,
+                false => serde_json::to_string(&metadata.custom).ok(),
+            };
+            let revision: i64 = tx.query_row(
+                "UPDATE videos SET title = ?2, description = ?3, user_recorded_at = ?4,
+                    source = ?5, license = ?6, custom = ?7,
+                    metadata_revision = metadata_revision + 1
+                 WHERE id = ?1
+                 RETURNING metadata_revision",
+                params![
+                    id,
+                    metadata.title,
+                    metadata.description,
+                    metadata.recorded_at,
+                    metadata.source,
+                    metadata.license,
+                    custom
+                ],
+                |row| row.get(0),
+            )?;
+            tx.execute("DELETE FROM video_tags WHERE video_id = ?1", [id])?;
+            for tag in &metadata.tags {
+                tx.execute(
+                    "INSERT INTO video_tags (video_id, tag) VALUES (?1, ?2)",
+                    params![id, tag],
+                )?;
+            }
+            tx.commit()?;
+            Ok(Some(Ok((metadata, revision))))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::NewEntry;
+
+    async fn catalog() -> (Catalog, i64) {
+        let catalog = Catalog::open(":memory:").unwrap();
+        let (entry, _) = catalog
+            .insert(NewEntry {
+                file_name: String::from("a.mp4"),
+                content_type: String::from("video/mp4"),
+                size: 1,
+                uploaded_at: 0,
+                sha256: format!("{:064x}", 1),
+                md5: format!("{:032x}", 1),
+                original_sha256: None,
+            })
+            .await
+            .unwrap();
+        (catalog, entry.id)
+    }
+
+    fn set_tags(tags: &[&str]) -> impl FnOnce(&mut VideoMetadata, i64) -> Result<(), ()> {
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        move |metadata, _| {
+            metadata.tags = tags;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_tags_in_order() {
+        let (catalog, id) = catalog().await;
+        for tags in [
+            &["zebra", "apple", "mango"][..],
+            &["mango", "zebra"],
+            &["b", "a", "zebra", "apple"],
+        ] {
+            catalog.edit_metadata(id, set_tags(tags)).await.unwrap();
+            let (metadata, _) = catalog.get_metadata(id).await.unwrap().unwrap();
+            assert_eq!(metadata.tags, tags, "{:?}", tags);
+        }
+    }
+
+    #[tokio::test]
+    async fn revises_only_on_change() {
+        let (catalog, id) = catalog().await;
+        let (_, first) = catalog.get_metadata(id).await.unwrap().unwrap();
+
+        let edited = catalog.edit_metadata(id, set_tags(&["a"])).await.unwrap();
+        let (_, second) = edited.unwrap().unwrap();
+        assert_eq!(second, first + 1);
+
+        let edited = catalog.edit_metadata(id, set_tags(&["a"])).await.unwrap();
+        assert_eq!(edited.unwrap().unwrap().1, second);
+
+        // A failed edit writes nothing
+        let edited = catalog
+            .edit_metadata(id, |metadata, _| {
+                metadata.tags.clear();
+                Err("refused")
+            })
+            .await
+            .unwrap();
+        assert_eq!(edited.unwrap().unwrap_err(), "refused");
+        let (metadata, revision) = catalog.get_metadata(id).await.unwrap().unwrap();
+        assert_eq!((metadata.tags, revision), (vec![String::from("a")], second));
+
+        assert!(catalog
+            .edit_metadata(id + 1, set_tags(&["a"]))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
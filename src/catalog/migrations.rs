@@ -0,0 +1,182 @@
+use rusqlite::Connection;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+///
+/// Never edit a migration that has shipped; append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: one row per archived file
+    "CREATE TABLE videos (
+        id          INTEGER PRIMARY KEY AUTOINCREMENT,
+        file_name   TEXT NOT NULL UNIQUE,
+        title       TEXT,
+        description TEXT,
+        content_type TEXT NOT NULL,
+        size        INTEGER NOT NULL,
+        uploaded_at INTEGER NOT NULL,
+        uploader    TEXT,
+        sha256      TEXT,
+        duration_ms INTEGER
+    );
+    CREATE TABLE video_tags (
+        video_id INTEGER NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+        tag      TEXT NOT NULL,
+        PRIMARY KEY (video_id, tag)
+    );
+    CREATE INDEX video_tags_tag ON video_tags(tag);",
+    // 2: resumable (tus) uploads in progress; the received bytes live on disk
+    "CREATE TABLE tus_uploads (
+        id            TEXT PRIMARY KEY,
+        upload_length INTEGER NOT NULL,
+        metadata      TEXT NOT NULL,
+        file_name     TEXT NOT NULL,
+        created_at    INTEGER NOT NULL,
+        expires_at    INTEGER NOT NULL,
+        completed_at  INTEGER
+    );
+    CREATE INDEX tus_uploads_expires_at ON tus_uploads(expires_at);",
+    // 3: superseded copies kept by the `version` conflict policy
+    "ALTER TABLE videos ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+    CREATE TABLE video_versions (
+        video_id     INTEGER NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+        version      INTEGER NOT NULL,
+        storage_key  TEXT NOT NULL,
+        content_type TEXT NOT NULL,
+        size         INTEGER NOT NULL,
+        uploaded_at  INTEGER NOT NULL,
+        PRIMARY KEY (video_id, version)
+    );",
+    // 4: content-addressed storage; names and versions point at a blob, NULL until migrated
+    "CREATE TABLE blobs (
+        sha256    TEXT PRIMARY KEY,
+        size      INTEGER NOT NULL,
+        ref_count INTEGER NOT NULL
+    );
+    ALTER TABLE video_versions ADD COLUMN sha256 TEXT;
+    CREATE INDEX videos_sha256 ON videos(sha256);",
+    // 5: MD5 of each blob, for clients verifying with Content-MD5
+    "ALTER TABLE blobs ADD COLUMN md5 TEXT;",
+    // 6: fixity checking; blobs are re-hashed periodically and flagged if they no longer match
+    "ALTER TABLE blobs ADD COLUMN checked_at INTEGER;
+    ALTER TABLE blobs ADD COLUMN fixity TEXT;
+    ALTER TABLE blobs ADD COLUMN fixity_detail TEXT;
+    CREATE INDEX blobs_checked_at ON blobs(checked_at);",
+    // 7: media metadata from probing; the full result is kept as JSON in `media`
+    "ALTER TABLE videos ADD COLUMN container TEXT;
+    ALTER TABLE videos ADD COLUMN video_codec TEXT;
+    ALTER TABLE videos ADD COLUMN audio_codec TEXT;
+    ALTER TABLE videos ADD COLUMN width INTEGER;
+    ALTER TABLE videos ADD COLUMN height INTEGER;
+    ALTER TABLE videos ADD COLUMN frame_rate REAL;
+    ALTER TABLE videos ADD COLUMN bit_rate INTEGER;
+    ALTER TABLE videos ADD COLUMN rotation INTEGER;
+    ALTER TABLE videos ADD COLUMN recorded_at INTEGER;
+    ALTER TABLE videos ADD COLUMN media TEXT;
+    ALTER TABLE videos ADD COLUMN probed_at INTEGER;",
+    // 8: SHA-256 of the contents as uploaded, for files rewritten on ingest (MP4 fast start)
+    "ALTER TABLE videos ADD COLUMN original_sha256 TEXT;
+    ALTER TABLE video_versions ADD COLUMN original_sha256 TEXT;",
+    // 9: where each video's poster came from, `generated` or `custom`; NULL while it has none
+    "ALTER TABLE videos ADD COLUMN poster TEXT;",
+    // 10: persistent queue of background processing; `log` holds one `<unix seconds>\t<message>`
+    // line per event
+    "CREATE TABLE jobs (
+        id           INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind         TEXT NOT NULL,
+        video_id     INTEGER NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+        sha256       TEXT NOT NULL,
+        state        TEXT NOT NULL,
+        priority     INTEGER NOT NULL,
+        attempts     INTEGER NOT NULL DEFAULT 0,
+        max_attempts INTEGER NOT NULL,
+        created_at   INTEGER NOT NULL,
+        run_after    INTEGER NOT NULL,
+        started_at   INTEGER,
+        finished_at  INTEGER,
+        error        TEXT,
+        log          TEXT NOT NULL DEFAULT ''
+    );
+    CREATE INDEX jobs_queue ON jobs(kind, state, priority, run_after);
+    CREATE INDEX jobs_video_id ON jobs(video_id, kind);",
+    // 11: full-text search. `videos_fts` copies the searchable metadata of each video, rowid its
+    // id; `subtitle_cues_fts` indexes the cues of attached subtitles in place. Triggers keep both
+    // current.
+    "CREATE TABLE subtitle_cues (
+        id       INTEGER PRIMARY KEY,
+        video_id INTEGER NOT NULL REFERENCES videos(id) ON DELETE CASCADE,
+        language TEXT NOT NULL,
+        start_ms INTEGER NOT NULL,
+        end_ms   INTEGER NOT NULL,
+        text     TEXT NOT NULL
+    );
+    CREATE INDEX subtitle_cues_video_id ON subtitle_cues(video_id, language, start_ms);
+    CREATE VIRTUAL TABLE videos_fts USING fts5(
+        file_name, title, description, tags,
+        tokenize = 'unicode61 remove_diacritics 2', prefix = '2 3'
+    );
+    CREATE VIRTUAL TABLE subtitle_cues_fts USING fts5(
+        text, content = 'subtitle_cues', content_rowid = 'id',
+        tokenize = 'unicode61 remove_diacritics 2', prefix = '2 3'
+    );
+    INSERT INTO videos_fts (rowid, file_name, title, description, tags)
+        SELECT id, file_name, title, description,
+            (SELECT group_concat(tag, ' ') FROM video_tags WHERE video_id = videos.id)
+        FROM videos;
+    CREATE TRIGGER videos_fts_insert AFTER INSERT ON videos BEGIN
+        INSERT INTO videos_fts (rowid, file_name, title, description, tags)
+        VALUES (new.id, new.file_name, new.title, new.description,
+            (SELECT group_concat(tag, ' ') FROM video_tags WHERE video_id = new.id));
+    END;
+    CREATE TRIGGER videos_fts_update AFTER UPDATE OF file_name, title, description ON videos BEGIN
+        UPDATE videos_fts SET file_name = new.file_name, title = new.title,
+            description = new.description
+        WHERE rowid = new.id;
+    END;
+    CREATE TRIGGER videos_fts_delete AFTER DELETE ON videos BEGIN
+        DELETE FROM videos_fts WHERE rowid = old.id;
+    END;
+    CREATE TRIGGER video_tags_fts_insert AFTER INSERT ON video_tags BEGIN
+        UPDATE videos_fts SET
+            tags = (SELECT group_concat(tag, ' ') FROM video_tags WHERE video_id = new.video_id)
+        WHERE rowid = new.video_id;
+    END;
+    CREATE TRIGGER video_tags_fts_delete AFTER DELETE ON video_tags BEGIN
+        UPDATE videos_fts SET
+            tags = (SELECT group_concat(tag, ' ') FROM video_tags WHERE video_id = old.video_id)
+        WHERE rowid = old.video_id;
+    END;
+    CREATE TRIGGER subtitle_cues_fts_insert AFTER INSERT ON subtitle_cues BEGIN
+        INSERT INTO subtitle_cues_fts (rowid, text) VALUES (new.id, new.text);
+    END;
+    CREATE TRIGGER subtitle_cues_fts_delete AFTER DELETE ON subtitle_cues BEGIN
+        INSERT INTO subtitle_cues_fts (subtitle_cues_fts, rowid, text)
+        VALUES ('delete', old.id, old.text);
+    END;",
+    // 12: the rest of the metadata users edit. `user_recorded_at` overrides the probed
+    // `recorded_at`, `custom` is a JSON object of text values, and `metadata_revision` goes up
+    // with every edit, for `If-Match`.
+    "ALTER TABLE videos ADD COLUMN user_recorded_at INTEGER;
+    ALTER TABLE videos ADD COLUMN source TEXT;
+    ALTER TABLE videos ADD COLUMN license TEXT;
+    ALTER TABLE videos ADD COLUMN custom TEXT;
+    ALTER TABLE videos ADD COLUMN metadata_revision INTEGER NOT NULL DEFAULT 1;",
+    // 13: received resumable uploads are archived in the background, and what became of them
+    // is kept for `HEAD /files/:id`. Those completed before were archived by their last request.
+    "ALTER TABLE tus_uploads ADD COLUMN archived_at INTEGER;
+    ALTER TABLE tus_uploads ADD COLUMN archived_as TEXT;
+    ALTER TABLE tus_uploads ADD COLUMN outcome TEXT;
+    ALTER TABLE tus_uploads ADD COLUMN failure TEXT;
+    UPDATE tus_uploads SET archived_at = completed_at WHERE completed_at IS NOT NULL;",
+];
+
+pub fn run(conn: &mut Connection) -> rusqlite::Result<()> {
+    let applied: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index as i64 + 1)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
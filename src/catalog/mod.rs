@@ -0,0 +1,446 @@
+mod blobs;
+mod fixity;
+mod jobs;
+mod listing;
+mod media;
+mod metadata;
+mod migrations;
+mod posters;
+mod search;
+mod subtitles;
+mod uploads;
+
+pub use fixity::BlobRecord;
+pub use jobs::{JobFilter, NewJob};
+pub use listing::{ArchivePosition, ArchiveQuery, SortKey};
+pub use media::ProbeTarget;
+pub use search::SearchQuery;
+pub use uploads::{ArchiveStatus, TusUpload};
+
+use crate::{
+    hls,
+    models::{ArchiveEntry, Fixity},
+    storage::{self, blobs::blob_key, StorageBackend},
+    storyboards,
+    utils::{digest::Algorithm, mime},
+};
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Separates values inside a `group_concat`; tags and file names are free text, so use a control
+/// character.
+const LIST_SEPARATOR: char = '\u{1f}';
+
+const SELECT_ENTRY: &str = "SELECT v.id, v.file_name, v.title, v.description, v.content_type,
+        v.size, v.uploaded_at, v.uploader, v.sha256, v.original_sha256, v.version,
+        v.duration_ms, v.container, v.video_codec, v.audio_codec, v.width, v.height,
+        v.frame_rate, v.bit_rate, v.rotation,
+        coalesce(v.user_recorded_at, v.recorded_at) AS recorded_at, v.poster, v.source, v.license,
+        v.custom,
+        b.md5, b.fixity, b.checked_at AS fixity_checked_at,
+        (SELECT group_concat(tag, char(31) ORDER BY rowid) FROM video_tags
+         WHERE video_id = v.id) AS tags,
+        (SELECT group_concat(language, char(31)) FROM (
+            SELECT DISTINCT language FROM subtitle_cues WHERE video_id = v.id ORDER BY language
+         )) AS subtitles,
+        (SELECT CASE max(CASE state
+                WHEN 'running' THEN 4 WHEN 'queued' THEN 3 WHEN 'failed' THEN 2
+                WHEN 'cancelled' THEN 1 ELSE 0 END)
+            WHEN 4 THEN 'running' WHEN 3 THEN 'queued' WHEN 2 THEN 'failed'
+            WHEN 1 THEN 'cancelled' WHEN 0 THEN 'succeeded' END
+         FROM jobs WHERE id IN (
+            SELECT max(id) FROM jobs WHERE video_id = v.id AND sha256 = v.sha256 GROUP BY kind
+         )) AS processing
+    FROM videos v LEFT JOIN blobs b ON b.sha256 = v.sha256";
+
+/// What is known about a file at the moment it lands in the archive.
+#[derive(Debug, Clone)]
+pub struct NewEntry {
+    pub file_name: String,
+    pub content_type: String,
+    pub size: u64,
+    pub uploaded_at: i64,
+    /// The blob holding the contents.
+    pub sha256: String,
+    pub md5: String,
+    /// The contents as uploaded, if ingest rewrote them.
+    pub original_sha256: Option<String>,
+}
+
+/// The embedded SQLite catalog of archived videos.
+///
+/// rusqlite is synchronous, so every query runs on the blocking pool behind a single connection.
+#[derive(Clone)]
+pub struct Catalog {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Catalog {
+    /// Open (creating if needed) the catalog at `path` and bring its schema up to date.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrations::run(&mut conn)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn call<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| anyhow::anyhow!("catalog connection poisoned"))?;
+            Ok(f(&mut conn)?)
+        })
+        .await?
+    }
+
+    /// Record a newly archived file and take a reference to its blob.
+    ///
+    /// Storing over an existing name keeps its id and user-supplied metadata but replaces
+    /// everything derived from the old contents. Returns the hash of the old blob if that was its
+    /// last reference.
+    pub async fn insert(&self, entry: NewEntry) -> anyhow::Result<(ArchiveEntry, Option<String>)> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            blobs::add_ref(&tx, &entry.sha256, Some(&entry.md5), entry.size)?;
+
+            let previous: Option<String> = tx
+                .query_row(
+                    "SELECT sha256 FROM videos WHERE file_name = ?1",
+                    [&entry.file_name],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .flatten();
+            let id = tx.query_row(
+                "INSERT INTO videos (file_name, content_type, size, uploaded_at, sha256, original_sha256)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (file_name) DO UPDATE SET
+                    content_type = excluded.content_type,
+                    size = excluded.size,
+                    uploaded_at = excluded.uploaded_at,
+                    sha256 = excluded.sha256,
+                    original_sha256 = excluded.original_sha256,
+                    duration_ms = NULL,
+                    container = NULL,
+                    video_codec = NULL,
+                    audio_codec = NULL,
+                    width = NULL,
+                    height = NULL,
+                    frame_rate = NULL,
+                    bit_rate = NULL,
+                    rotation = NULL,
+                    recorded_at = NULL,
+                    media = NULL,
+                    probed_at = NULL,
+                    poster = CASE WHEN poster = 'custom' THEN poster END
+                 RETURNING id",
+                params![
+                    entry.file_name,
+                    entry.content_type,
+                    entry.size as i64,
+                    entry.uploaded_at,
+                    entry.sha256,
+                    entry.original_sha256
+                ],
+                |row| row.get::<_, i64>(0),
+            )?;
+            let released = match previous {
+                Some(previous) => blobs::release_ref(&tx, &previous)?,
+                None => None,
+            };
+
+            let inserted = tx.query_row(
+                &format!("{} WHERE v.id = ?1", SELECT_ENTRY),
+                [id],
+                entry_from_row,
+            )?;
+            tx.commit()?;
+            Ok((inserted, released))
+        })
+        .await
+    }
+
+    /// Keep the current contents of `file_name` as a numbered version, and bump its version so
+    /// the next insert becomes the new current one.
+    pub async fn push_version(&self, file_name: &str) -> anyhow::Result<()> {
+        let file_name = file_name.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let current: Option<(Option<String>, i64)> = tx
+                .query_row(
+                    "SELECT sha256, size FROM videos WHERE file_name = ?1",
+                    [&file_name],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            let Some((Some(sha256), size)) = current else {
+                return Ok(());
+            };
+
+            // The version holds its own reference, so the insert replacing the current
+            // contents won't free the blob
+            blobs::add_ref(&tx, &sha256, None, size as u64)?;
+            tx.execute(
+                "INSERT INTO video_versions (video_id, version, storage_key, content_type, size, uploaded_at, sha256, original_sha256)
+                 SELECT id, version, ?2, content_type, size, uploaded_at, sha256, original_sha256 FROM videos WHERE file_name = ?1",
+                params![file_name, blob_key(&sha256)],
+            )?;
+            tx.execute(
+                "UPDATE videos SET version = version + 1 WHERE file_name = ?1",
+                [&file_name],
+            )?;
+            tx.commit()
+        })
+        .await
+    }
+
+    pub async fn get_by_name(&self, file_name: &str) -> anyhow::Result<Option<ArchiveEntry>> {
+        let file_name = file_name.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("{} WHERE v.file_name = ?1", SELECT_ENTRY),
+                [file_name],
+                entry_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn get_by_id(&self, id: i64) -> anyhow::Result<Option<ArchiveEntry>> {
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("{} WHERE v.id = ?1", SELECT_ENTRY),
+                [id],
+                entry_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn list(&self) -> anyhow::Result<Vec<ArchiveEntry>> {
+        self.call(|conn| {
+            let mut statement = conn.prepare(&format!("{} ORDER BY v.file_name", SELECT_ENTRY))?;
+            let entries = statement.query_map([], entry_from_row)?;
+            entries.collect()
+        })
+        .await
+    }
+
+    /// Names of every archived file.
+    pub async fn file_names(&self) -> anyhow::Result<Vec<String>> {
+        self.call(|conn| {
+            let mut statement = conn.prepare("SELECT file_name FROM videos")?;
+            let names = statement.query_map([], |row| row.get(0))?;
+            names.collect()
+        })
+        .await
+    }
+
+    /// Remove `file_name` and its versions, dropping their blob references. Returns `None` if
+    /// there was no such entry, otherwise the hashes of blobs that are no longer referenced.
+    pub async fn delete_by_name(&self, file_name: &str) -> anyhow::Result<Option<Vec<String>>> {
+        let file_name = file_name.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let hashes: Vec<String> = {
+                let mut statement = tx.prepare(
+                    "SELECT sha256 FROM videos WHERE file_name = ?1 AND sha256 IS NOT NULL
+                     UNION ALL
+                     SELECT sha256 FROM video_versions
+                     WHERE video_id = (SELECT id FROM videos WHERE file_name = ?1)
+                        AND sha256 IS NOT NULL",
+                )?;
+                let hashes = statement.query_map([&file_name], |row| row.get(0))?;
+                hashes.collect::<rusqlite::Result<_>>()?
+            };
+
+            if tx.execute("DELETE FROM videos WHERE file_name = ?1", [&file_name])? == 0 {
+                return Ok(None);
+            }
+            let mut released = vec![];
+            for sha256 in hashes {
+                released.extend(blobs::release_ref(&tx, &sha256)?);
+            }
+            tx.commit()?;
+            Ok(Some(released))
+        })
+        .await
+    }
+
+    /// Bring the catalog in line with what is actually stored: files that predate content
+    /// addressing are moved into blobs, files that predate the catalog are added, and entries
+    /// whose contents have gone are flagged as missing.
+    pub async fn reconcile(&self, storage: &dyn StorageBackend) -> anyhow::Result<()> {
+        for object in self.legacy_objects().await? {
+            let Some(meta) = storage.stat(&object.storage_key).await? else {
+                info!(
+                    "Dropping catalog entry for missing file {}",
+                    object.storage_key
+                );
+                match object.version {
+                    Some(version) => self.delete_version(object.video_id, version).await?,
+                    None => self.remove(storage, &object.storage_key).await?,
+                }
+                continue;
+            };
+
+            info!("Moving {} into blob storage", object.storage_key);
+            let digests = storage::blobs::adopt(storage, &object.storage_key).await?;
+            self.attach_blob(
+                &object,
+                &digests.require_hex(Algorithm::Sha256)?,
+                &digests.require_hex(Algorithm::Md5)?,
+                meta.size,
+            )
+            .await?;
+        }
+
+        for object in storage.list("").await? {
+            info!("Cataloguing existing file {}", object.key);
+            let digests = storage::blobs::adopt(storage, &object.key).await?;
+            let sha256 = digests.require_hex(Algorithm::Sha256)?;
+            let content_type =
+                mime::detect_object(storage, &blob_key(&sha256), &object.key).await?;
+            let (_, released) = self
+                .insert(NewEntry {
+                    file_name: object.key.clone(),
+                    content_type: content_type.to_string(),
+                    size: object.size,
+                    uploaded_at: unix_seconds(object.modified),
+                    sha256,
+                    md5: digests.require_hex(Algorithm::Md5)?,
+                    original_sha256: None,
+                })
+                .await?;
+            delete_blobs(storage, released).await;
+        }
+
+        // Lost contents are flagged rather than forgotten, so the loss stays visible
+        for entry in self.list().await? {
+            let Some(sha256) = &entry.sha256 else {
+                continue;
+            };
+            let key = blob_key(sha256);
+            if entry.fixity != Some(Fixity::Missing) && storage.stat(&key).await?.is_none() {
+                warn!(
+                    "Flagging {} as missing, its blob {} is gone",
+                    entry.file_name, sha256
+                );
+                let now = unix_seconds(SystemTime::now());
+                self.record_fixity(
+                    sha256,
+                    Fixity::Missing,
+                    Some(format!("{} is gone", key)),
+                    now,
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn remove(&self, storage: &dyn StorageBackend, file_name: &str) -> anyhow::Result<()> {
+        if let Some(released) = self.delete_by_name(file_name).await? {
+            delete_blobs(storage, released).await;
+        }
+        Ok(())
+    }
+}
+
+/// Delete blobs whose last reference has gone. Failures only leak space, so they are logged
+/// rather than failing the caller.
+pub async fn delete_blobs(storage: &dyn StorageBackend, hashes: impl IntoIterator<Item = String>) {
+    for sha256 in hashes {
+        let key = blob_key(&sha256);
+        match storage.delete(&key).await {
+            Ok(()) => info!("Deleted unreferenced blob {}", sha256),
+            Err(err) => warn!("Failed to delete unreferenced blob {}: {}", key, err),
+        }
+        if let Err(err) = storyboards::remove(storage, &sha256).await {
+            warn!(
+                "Failed to delete the storyboard of blob {}: {}",
+                sha256, err
+            );
+        }
+        if let Err(err) = hls::remove(storage, &sha256).await {
+            warn!(
+                "Failed to delete the HLS package of blob {}: {}",
+                sha256, err
+            );
+        }
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn entry_from_row(row: &Row<'_>) -> rusqlite::Result<ArchiveEntry> {
+    let tags: Option<String> = row.get("tags")?;
+    let subtitles: Option<String> = row.get("subtitles")?;
+
+    Ok(ArchiveEntry {
+        id: row.get("id")?,
+        file_name: row.get("file_name")?,
+        title: row.get("title")?,
+        description: row.get("description")?,
+        content_type: row.get("content_type")?,
+        size: row.get::<_, i64>("size")? as u64,
+        uploaded_at: row.get("uploaded_at")?,
+        uploader: row.get("uploader")?,
+        sha256: row.get("sha256")?,
+        original_sha256: row.get("original_sha256")?,
+        md5: row.get("md5")?,
+        fixity: fixity::from_column(row.get("fixity")?),
+        fixity_checked_at: row.get("fixity_checked_at")?,
+        duration_ms: row.get("duration_ms")?,
+        container: row.get("container")?,
+        video_codec: row.get("video_codec")?,
+        audio_codec: row.get("audio_codec")?,
+        width: row.get("width")?,
+        height: row.get("height")?,
+        frame_rate: row.get("frame_rate")?,
+        bit_rate: row
+            .get::<_, Option<i64>>("bit_rate")?
+            .map(|rate| rate as u64),
+        rotation: row.get("rotation")?,
+        recorded_at: row.get("recorded_at")?,
+        poster: posters::from_column(row.get("poster")?),
+        processing: jobs::state_from_column(row.get("processing")?),
+        version: row.get("version")?,
+        tags: tags
+            .map(|tags| tags.split(LIST_SEPARATOR).map(str::to_string).collect())
+            .unwrap_or_default(),
+        source: row.get("source")?,
+        license: row.get("license")?,
+        custom: metadata::custom_from_column(row.get("custom")?),
+        subtitles: subtitles
+            .map(|languages| {
+                languages
+                    .split(LIST_SEPARATOR)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
@@ -0,0 +1,38 @@
+use super::{entry_from_row, Catalog, SELECT_ENTRY};
+use crate::models::{ArchiveEntry, Poster};
+use rusqlite::{params, OptionalExtension};
+
+/// Values outside [`Poster`] can only come from a hand-edited catalog; treat them as none.
+pub(super) fn from_column(value: Option<String>) -> Option<Poster> {
+    value.and_then(|value| value.parse().ok())
+}
+
+impl Catalog {
+    /// Record where the poster of video `id` came from, unless its contents changed from
+    /// `sha256` meanwhile. Returns the updated entry.
+    pub async fn set_poster(
+        &self,
+        id: i64,
+        sha256: &str,
+        poster: Option<Poster>,
+    ) -> anyhow::Result<Option<ArchiveEntry>> {
+        let sha256 = sha256.to_string();
+        self.call(move |conn| {
+            let updated = conn.execute(
+                "UPDATE videos SET poster = ?3 WHERE id = ?1 AND sha256 = ?2",
+                params![id, sha256, poster.map(Poster::as_str)],
+            )?;
+            if updated == 0 {
+                return Ok(None);
+            }
+
+            conn.query_row(
+                &format!("{} WHERE v.id = ?1", SELECT_ENTRY),
+                [id],
+                entry_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+}
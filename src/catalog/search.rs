@@ -0,0 +1,201 @@
+use super::{entry_from_row, Catalog, SELECT_ENTRY};
+use crate::models::{SearchResult, SubtitleHit};
+use rusqlite::{params, OptionalExtension};
+use std::collections::{HashMap, HashSet};
+
+/// Weights of `videos_fts` columns in ranking: file name, title, description, tags.
+const COLUMN_WEIGHTS: &str = "2.0, 10.0, 1.0, 5.0";
+
+/// Tokens of context around the matches in a metadata snippet.
+const SNIPPET_TOKENS: u32 = 12;
+
+/// What FTS5 marks the start and end of matches with, to be made into `<mark>` once the text is
+/// escaped. Names and metadata can't contain control characters; subtitles might, which
+/// [`to_html`] copes with.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// What to search for.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    /// Words that must all match, each as a whole word or the start of one.
+    pub terms: Vec<String>,
+    pub limit: u32,
+    /// Most subtitle cues returned for each video.
+    pub hits_per_video: u32,
+}
+
+/// The FTS5 query matching every one of `terms` as a prefix. Each is quoted, so nothing in
+/// them is taken as query syntax.
+fn match_expression(terms: &[String]) -> String {
+    terms
+        .iter()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Text FTS5 has marked matches in, as HTML: escaped, with the matches in `<mark>`. Marks that
+/// don't pair up are dropped, so elements are always balanced.
+fn to_html(marked: &str) -> String {
+    let mut html = String::with_capacity(marked.len() + 16);
+    let mut in_match = false;
+    for c in marked.chars() {
+        match c {
+            MATCH_START if !in_match => {
+                html.push_str("<mark>");
+                in_match = true;
+            }
+            MATCH_END if in_match => {
+                html.push_str("</mark>");
+                in_match = false;
+            }
+            MATCH_START | MATCH_END => {}
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    if in_match {
+        html.push_str("</mark>");
+    }
+    html
+}
+
+impl Catalog {
+    /// The videos best matching `query`, by their metadata or their subtitles, each with where
+    /// it matched.
+    pub async fn search(&self, query: SearchQuery) -> anyhow::Result<Vec<SearchResult>> {
+        let expression = match_expression(&query.terms);
+        self.call(move |conn| {
+            // bm25 ranks are negative, the better matches the lower
+            let mut metadata = HashMap::new();
+            let mut statement = conn.prepare(&format!(
+                "SELECT rowid, bm25(videos_fts, {}),
+                    snippet(videos_fts, -1, ?3, ?4, '…', {})
+                 FROM videos_fts WHERE videos_fts MATCH ?1 ORDER BY 2 LIMIT ?2",
+                COLUMN_WEIGHTS, SNIPPET_TOKENS
+            ))?;
+            let marks = (String::from(MATCH_START), String::from(MATCH_END));
+            let rows =
+                statement.query_map(params![expression, query.limit, marks.0, marks.1], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, f64>(1)?,
+                        to_html(&row.get::<_, String>(2)?),
+                    ))
+                })?;
+            for row in rows {
+                let (id, rank, snippet) = row?;
+                metadata.insert(id, (rank, snippet));
+            }
+
+            // The best `hits_per_video` cues of each of the `limit` videos whose best cue ranks
+            // highest, and at least that one, which ranks the video. FTS5 functions can't be used
+            // alongside a window, hence its `rank` column, which is bm25, and highlighting in a
+            // query of its own.
+            let mut subtitles: HashMap<i64, (f64, Vec<SubtitleHit>)> = HashMap::new();
+            let mut statement = conn.prepare(
+                "WITH hits AS (
+                    SELECT f.rowid AS id, c.video_id, f.rank,
+                        ROW_NUMBER() OVER (PARTITION BY c.video_id ORDER BY f.rank) AS n
+                    FROM subtitle_cues_fts f JOIN subtitle_cues c ON c.id = f.rowid
+                    WHERE subtitle_cues_fts MATCH ?1
+                 ),
+                 best AS (SELECT video_id FROM hits WHERE n = 1 ORDER BY rank LIMIT ?2)
+                 SELECT c.video_id, c.language, c.start_ms, c.end_ms,
+                    highlight(subtitle_cues_fts, 0, ?4, ?5),
+                    subtitle_cues_fts.rank
+                 FROM subtitle_cues_fts JOIN subtitle_cues c ON c.id = subtitle_cues_fts.rowid
+                 WHERE subtitle_cues_fts MATCH ?1 AND subtitle_cues_fts.rowid IN
+                    (SELECT id FROM hits JOIN best USING (video_id) WHERE n <= MAX(?3, 1))
+                 ORDER BY 6",
+            )?;
+            let rows = statement.query_map(
+                params![
+                    expression,
+                    query.limit,
+                    query.hits_per_video,
+                    marks.0,
+                    marks.1
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        SubtitleHit {
+                            language: row.get(1)?,
+                            start_ms: row.get(2)?,
+                            end_ms: row.get(3)?,
+                            snippet: to_html(&row.get::<_, String>(4)?),
+                        },
+                        row.get::<_, f64>(5)?,
+                    ))
+                },
+            )?;
+            // Best first, so a video's first hit is its best
+            for row in rows {
+                let (id, hit, rank) = row?;
+                let (_, hits) = subtitles.entry(id).or_insert((rank, vec![]));
+                if hits.len() < query.hits_per_video as usize {
+                    hits.push(hit);
+                }
+            }
+
+            let mut ranked: Vec<(i64, f64)> = metadata
+                .iter()
+                .map(|(&id, &(rank, _))| (id, rank))
+                .chain(subtitles.iter().map(|(&id, &(rank, _))| (id, rank)))
+                .collect();
+            ranked.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            // A video matching both ways is ranked by the better; keep its first, best entry
+            let mut seen = HashSet::new();
+            ranked.retain(|(id, _)| seen.insert(*id));
+            ranked.truncate(query.limit as usize);
+
+            let mut statement = conn.prepare(&format!("{} WHERE v.id = ?1", SELECT_ENTRY))?;
+            let mut results = vec![];
+            for (id, rank) in ranked {
+                let Some(file) = statement.query_row([id], entry_from_row).optional()? else {
+                    continue;
+                };
+                let mut subtitle_hits = subtitles
+                    .remove(&id)
+                    .map(|(_, hits)| hits)
+                    .unwrap_or_default();
+                subtitle_hits.sort_by_key(|hit| hit.start_ms);
+                results.push(SearchResult {
+                    file,
+                    score: -rank,
+                    snippet: metadata.remove(&id).map(|(_, snippet)| snippet),
+                    subtitle_hits,
+                });
+            }
+            Ok(results)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_everything_but_the_marks() {
+        let marked = "<script>alert('x')</script> & \"\u{2}quoted\u{3}\"";
+        assert_eq!(
+            to_html(marked),
+            "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; &quot;<mark>quoted</mark>&quot;"
+        );
+    }
+
+    #[test]
+    fn balances_stray_marks() {
+        assert_eq!(to_html("a\u{3}b"), "ab");
+        assert_eq!(to_html("\u{2}a\u{2}b\u{3}c\u{3}"), "<mark>ab</mark>c");
+        assert_eq!(to_html("\u{2}<b>"), "<mark>&lt;b&gt;</mark>");
+    }
+}
@@ -0,0 +1,83 @@
+use super::Catalog;
+use crate::subtitles::Cue;
+use rusqlite::params;
+
+impl Catalog {
+    /// Replace the `language` subtitles of video `video_id` with `cues`. Returns `false` if there
+    /// is no such video.
+    pub async fn set_subtitles(
+        &self,
+        video_id: i64,
+        language: &str,
+        cues: Vec<Cue>,
+    ) -> anyhow::Result<bool> {
+        let language = language.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let exists = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM videos WHERE id = ?1)",
+                [video_id],
+                |row| row.get::<_, bool>(0),
+            )?;
+            if !exists {
+                return Ok(false);
+            }
+
+            tx.execute(
+                "DELETE FROM subtitle_cues WHERE video_id = ?1 AND language = ?2",
+                params![video_id, language],
+            )?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO subtitle_cues (video_id, language, start_ms, end_ms, text)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?;
+                for cue in cues {
+                    insert.execute(params![
+                        video_id,
+                        language,
+                        cue.start_ms,
+                        cue.end_ms,
+                        cue.text
+                    ])?;
+                }
+            }
+            tx.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// The `language` subtitles of video `video_id`, in order of time; empty if it has none.
+    pub async fn get_subtitles(&self, video_id: i64, language: &str) -> anyhow::Result<Vec<Cue>> {
+        let language = language.to_string();
+        self.call(move |conn| {
+            let mut statement = conn.prepare(
+                "SELECT start_ms, end_ms, text FROM subtitle_cues
+                 WHERE video_id = ?1 AND language = ?2 ORDER BY start_ms, id",
+            )?;
+            let cues = statement.query_map(params![video_id, language], |row| {
+                Ok(Cue {
+                    start_ms: row.get(0)?,
+                    end_ms: row.get(1)?,
+                    text: row.get(2)?,
+                })
+            })?;
+            cues.collect()
+        })
+        .await
+    }
+
+    /// Remove the `language` subtitles of video `video_id`. Returns `false` if it had none.
+    pub async fn delete_subtitles(&self, video_id: i64, language: &str) -> anyhow::Result<bool> {
+        let language = language.to_string();
+        self.call(move |conn| {
+            let deleted = conn.execute(
+                "DELETE FROM subtitle_cues WHERE video_id = ?1 AND language = ?2",
+                params![video_id, language],
+            )?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+}
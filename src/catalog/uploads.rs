@@ -0,0 +1,191 @@
+use super::Catalog;
+use rusqlite::{params, OptionalExtension, Row};
+
+/// Bookkeeping for one resumable upload.
+#[derive(Debug, Clone)]
+pub struct TusUpload {
+    pub id: String,
+    pub upload_length: u64,
+    /// The `Upload-Metadata` header exactly as the client sent it.
+    pub metadata: String,
+    pub file_name: String,
+    pub expires_at: i64,
+    /// All of it has been received.
+    pub completed: bool,
+    pub archive: ArchiveStatus,
+}
+
+/// What became of an upload once all of it was received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveStatus {
+    /// Not received yet, or still being moved into the archive.
+    Pending,
+    /// In the archive under `file_name`, a clash with another name resolved as `outcome`.
+    /// Neither is known for uploads archived before they were recorded.
+    Archived {
+        file_name: Option<String>,
+        outcome: Option<String>,
+    },
+    /// Couldn't be archived, for the reason given.
+    Failed(String),
+}
+
+const SELECT_UPLOAD: &str = "SELECT id, upload_length, metadata, file_name, expires_at,
+    completed_at, archived_at, archived_as, outcome, failure
+    FROM tus_uploads";
+
+/// Uploads received in full that are neither archived nor failed.
+const UNARCHIVED: &str = "completed_at IS NOT NULL AND archived_at IS NULL AND failure IS NULL";
+
+impl Catalog {
+    pub async fn insert_upload(&self, upload: TusUpload, created_at: i64) -> anyhow::Result<()> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO tus_uploads (id, upload_length, metadata, file_name, created_at, expires_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    upload.id,
+                    upload.upload_length as i64,
+                    upload.metadata,
+                    upload.file_name,
+                    created_at,
+                    upload.expires_at
+                ],
+            )
+            .map(drop)
+        })
+        .await
+    }
+
+    pub async fn get_upload(&self, id: &str) -> anyhow::Result<Option<TusUpload>> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.query_row(
+                &format!("{} WHERE id = ?1", SELECT_UPLOAD),
+                [id],
+                upload_from_row,
+            )
+            .optional()
+        })
+        .await
+    }
+
+    pub async fn extend_upload(&self, id: &str, expires_at: i64) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE tus_uploads SET expires_at = ?2 WHERE id = ?1",
+                params![id, expires_at],
+            )
+            .map(drop)
+        })
+        .await
+    }
+
+    /// Record that all of upload `id` has been received, and keep it until `expires_at`.
+    pub async fn complete_upload(
+        &self,
+        id: &str,
+        completed_at: i64,
+        expires_at: i64,
+    ) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE tus_uploads SET completed_at = ?2, expires_at = ?3 WHERE id = ?1",
+                params![id, completed_at, expires_at],
+            )
+            .map(drop)
+        })
+        .await
+    }
+
+    /// Record that upload `id` was archived as `file_name`, a name clash resolved as `outcome`.
+    pub async fn archive_upload(
+        &self,
+        id: &str,
+        archived_at: i64,
+        file_name: String,
+        outcome: &'static str,
+    ) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE tus_uploads SET archived_at = ?2, archived_as = ?3, outcome = ?4
+                 WHERE id = ?1",
+                params![id, archived_at, file_name, outcome],
+            )
+            .map(drop)
+        })
+        .await
+    }
+
+    /// Record why upload `id` couldn't be archived.
+    pub async fn fail_upload(&self, id: &str, failure: String) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE tus_uploads SET failure = ?2 WHERE id = ?1",
+                params![id, failure],
+            )
+            .map(drop)
+        })
+        .await
+    }
+
+    /// Uploads received in full but not archived yet, e.g. because the server stopped while
+    /// archiving them.
+    pub async fn unarchived_uploads(&self) -> anyhow::Result<Vec<TusUpload>> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(&format!("{} WHERE {}", SELECT_UPLOAD, UNARCHIVED))?;
+            let uploads = statement.query_map([], upload_from_row)?;
+            uploads.collect()
+        })
+        .await
+    }
+
+    pub async fn delete_upload(&self, id: &str) -> anyhow::Result<()> {
+        let id = id.to_string();
+        self.call(move |conn| {
+            conn.execute("DELETE FROM tus_uploads WHERE id = ?1", [id])
+                .map(drop)
+        })
+        .await
+    }
+
+    /// Ids of uploads whose expiry has passed by `now`. Uploads waiting to be archived never
+    /// expire.
+    pub async fn expired_uploads(&self, now: i64) -> anyhow::Result<Vec<String>> {
+        self.call(move |conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT id FROM tus_uploads WHERE expires_at <= ?1 AND NOT ({})",
+                UNARCHIVED
+            ))?;
+            let ids = statement.query_map([now], |row| row.get(0))?;
+            ids.collect()
+        })
+        .await
+    }
+}
+
+fn upload_from_row(row: &Row<'_>) -> rusqlite::Result<TusUpload> {
+    Ok(TusUpload {
+        id: row.get("id")?,
+        upload_length: row.get::<_, i64>("upload_length")? as u64,
+        metadata: row.get("metadata")?,
+        file_name: row.get("file_name")?,
+        expires_at: row.get("expires_at")?,
+        completed: row.get::<_, Option<i64>>("completed_at")?.is_some(),
+        archive: match (
+            row.get::<_, Option<i64>>("archived_at")?,
+            row.get::<_, Option<String>>("failure")?,
+        ) {
+            (Some(_), _) => ArchiveStatus::Archived {
+                file_name: row.get("archived_as")?,
+                outcome: row.get("outcome")?,
+            },
+            (None, Some(failure)) => ArchiveStatus::Failed(failure),
+            (None, None) => ArchiveStatus::Pending,
+        },
+    })
+}
@@ -0,0 +1,1000 @@
+use crate::models::{ConflictPolicy, JobKind};
+use anyhow::Context;
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+/// Used when neither `--config` nor `ARCHIVER_CONFIG` names a file; optional.
+pub const DEFAULT_CONFIG_PATH: &str = "./archiver.toml";
+
+/// Widest thumbnail that may be configured, in pixels.
+pub const MAX_THUMBNAIL_SIZE: u32 = 4096;
+
+/// Largest accepted upload with the local backend, unless configured otherwise.
+pub const DEFAULT_MAX_UPLOAD_SIZE: u64 = 20 * 1024 * 1024 * 1024;
+
+/// Largest object S3 copies in one request, which is how the s3 backend moves a finished upload
+/// into place, so also the largest upload it can take, and its default.
+pub const MAX_S3_UPLOAD_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// Most tiles a storyboard sprite sheet may have across or down.
+pub const MAX_STORYBOARD_TILES: u32 = 32;
+
+/// Tallest HLS rendition that may be configured, in pixels.
+pub const MAX_RENDITION_HEIGHT: u32 = 4320;
+
+/// Most jobs of one kind that may be configured to run at once.
+pub const MAX_JOB_CONCURRENCY: usize = 64;
+
+/// Name of the audio rendition of each HLS package, so no video rendition may take it.
+pub const AUDIO_RENDITION: &str = "audio";
+
+/// Command-line flags. Each one can also be set through the environment variable shown, and
+/// both take precedence over the config file.
+#[derive(Debug, Clone, Parser)]
+#[command(version, about = "Video archive server")]
+pub struct Cli {
+    /// TOML config file
+    #[arg(long, env = "ARCHIVER_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "BIND_ADDR")]
+    pub bind: Option<String>,
+    /// Largest accepted upload, in bytes; by default 20 GiB, or 5 GiB with the s3 backend
+    #[arg(long, env = "MAX_UPLOAD_SIZE")]
+    pub max_upload_size: Option<String>,
+    /// Archive backend: local or s3
+    #[arg(long, env = "ARCHIVE_STORAGE")]
+    pub storage: Option<String>,
+    /// Archive directory for the local backend
+    #[arg(long, env = "ARCHIVE_DIR")]
+    pub archive_dir: Option<PathBuf>,
+    #[arg(long, env = "S3_BUCKET")]
+    pub s3_bucket: Option<String>,
+    #[arg(long, env = "S3_REGION")]
+    pub s3_region: Option<String>,
+    #[arg(long, env = "S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    #[arg(long, env = "S3_PREFIX")]
+    pub s3_prefix: Option<String>,
+    #[arg(long, env = "S3_ACCESS_KEY_ID", hide_env_values = true)]
+    pub s3_access_key_id: Option<String>,
+    #[arg(long, env = "S3_SECRET_ACCESS_KEY", hide_env_values = true)]
+    pub s3_secret_access_key: Option<String>,
+    /// SQLite catalog database
+    #[arg(long, env = "CATALOG_PATH")]
+    pub catalog_path: Option<PathBuf>,
+    /// Where partially received resumable uploads are kept
+    #[arg(long, env = "TUS_DIR")]
+    pub tus_dir: Option<PathBuf>,
+    /// Default conflict policy: reject, rename or version
+    #[arg(long, env = "UPLOAD_CONFLICT_POLICY")]
+    pub conflict_policy: Option<String>,
+    /// Comma-separated allowed CORS origins, or `*`
+    #[arg(long, env = "CORS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub cors_allowed_origins: Option<Vec<String>>,
+    /// ffprobe binary used for formats the built-in parsers can't read; empty to disable
+    #[arg(long, env = "FFPROBE_PATH")]
+    pub ffprobe: Option<String>,
+    /// ffmpeg binary used to grab poster frames; empty to disable
+    #[arg(long, env = "FFMPEG_PATH")]
+    pub ffmpeg: Option<String>,
+    /// Root log level
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub catalog: CatalogConfig,
+    pub tus: TusConfig,
+    pub uploads: UploadsConfig,
+    pub cors: CorsConfig,
+    pub fixity: FixityConfig,
+    pub probe: ProbeConfig,
+    pub thumbnails: ThumbnailsConfig,
+    pub storyboards: StoryboardsConfig,
+    pub hls: HlsConfig,
+    pub jobs: JobsConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    /// Bytes; see [`Config::max_upload_size`].
+    pub max_upload_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Local,
+    S3,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageKind,
+    /// Archive root for the local backend.
+    pub dir: PathBuf,
+    pub s3: S3Config,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    pub bucket: Option<String>,
+    pub region: String,
+    /// Set for MinIO and other self-hosted stores.
+    pub endpoint: Option<String>,
+    pub prefix: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogConfig {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TusConfig {
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadsConfig {
+    /// Applied to uploads that don't pick a policy themselves; never `overwrite`.
+    pub conflict_policy: ConflictPolicy,
+    /// Rewrite MP4 uploads that have their index (`moov`) at the end so it comes first.
+    pub faststart: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Exact origins, or `["*"]` for any.
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FixityConfig {
+    /// Periodic checking; `/verify` and runs started from `/admin/fixity` work regardless.
+    pub enabled: bool,
+    /// Each blob is re-hashed once per this many hours.
+    pub interval_hours: u64,
+    /// Read rate cap for periodic checks in bytes per second, 0 for none.
+    pub max_bytes_per_sec: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProbeConfig {
+    /// Run for formats the built-in MP4 and Matroska parsers can't read; empty to disable.
+    pub ffprobe: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailsConfig {
+    /// Grabs the poster frame of each uploaded video; empty to disable.
+    pub ffmpeg: String,
+    /// Seconds into the video to take the poster frame from; shorter videos use their midpoint.
+    pub offset_secs: f64,
+    /// Widths in pixels each poster is scaled to.
+    pub sizes: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoryboardsConfig {
+    /// Makes scrubbing previews of each uploaded video, with `thumbnails.ffmpeg`.
+    pub enabled: bool,
+    /// Seconds of video between frames.
+    pub interval_secs: f64,
+    /// Width of each frame in pixels.
+    pub tile_width: u32,
+    /// Frames across each sprite sheet.
+    pub columns: u32,
+    /// Frames down each sprite sheet.
+    pub rows: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentFormat {
+    Fmp4,
+    Ts,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HlsConfig {
+    /// Transcodes each uploaded video for adaptive streaming, with `thumbnails.ffmpeg`.
+    pub enabled: bool,
+    pub segment_format: SegmentFormat,
+    /// Target length of each segment.
+    pub segment_secs: u32,
+    /// Where ffmpeg writes a package before it is moved into storage.
+    pub work_dir: PathBuf,
+    /// Audio is packaged once, as its own rendition shared by every rung of the ladder.
+    pub audio_kbps: u32,
+    /// The bitrate ladder. Renditions taller than a video are skipped for it.
+    pub renditions: Vec<Rendition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rendition {
+    /// Names its playlist and segments, e.g. `720p`.
+    pub name: String,
+    /// Pixels; the width follows the video's aspect ratio.
+    pub height: u32,
+    pub video_kbps: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    /// Attempts each job gets before it is given up on.
+    pub max_attempts: u32,
+    /// Wait before the first retry; it doubles with each one after, up to `max_backoff_secs`.
+    pub backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    /// Finished jobs are forgotten after this many days.
+    pub retention_days: u64,
+    pub concurrency: JobConcurrency,
+}
+
+/// Jobs of each kind that run at once; 0 pauses a kind.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConcurrency {
+    pub probe: usize,
+    pub thumbnails: usize,
+    pub storyboard: usize,
+    pub hls: usize,
+}
+
+impl JobConcurrency {
+    pub fn of(&self, kind: JobKind) -> usize {
+        match kind {
+            JobKind::Probe => self.probe,
+            JobKind::Thumbnails => self.thumbnails,
+            JobKind::Storyboard => self.storyboard,
+            JobKind::Hls => self.hls,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+    /// Per-module levels, e.g. `hyper = "info"`.
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            max_upload_size: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageKind::Local,
+            dir: PathBuf::from("./archive"),
+            s3: S3Config::default(),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            bucket: None,
+            region: String::from("us-east-1"),
+            endpoint: None,
+            prefix: None,
+            access_key_id: None,
+            secret_access_key: None,
+        }
+    }
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("./catalog.db"),
+        }
+    }
+}
+
+impl Default for TusConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("./tus-uploads"),
+        }
+    }
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        Self {
+            conflict_policy: ConflictPolicy::Reject,
+            faststart: true,
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![String::from("*")],
+        }
+    }
+}
+
+impl Default for FixityConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 7 * 24,
+            max_bytes_per_sec: 32 * 1024 * 1024, // 32MB/s
+        }
+    }
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            ffprobe: String::from("ffprobe"),
+        }
+    }
+}
+
+impl Default for ThumbnailsConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: String::from("ffmpeg"),
+            offset_secs: 5.0,
+            sizes: vec![160, 320, 640],
+        }
+    }
+}
+
+impl Default for StoryboardsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 10.0,
+            tile_width: 160,
+            columns: 10,
+            rows: 10,
+        }
+    }
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        let renditions = [
+            ("1080p", 1080, 5000),
+            ("720p", 720, 2800),
+            ("480p", 480, 1400),
+            ("360p", 360, 800),
+        ]
+        .into_iter()
+        .map(|(name, height, video_kbps)| Rendition {
+            name: name.to_string(),
+            height,
+            video_kbps,
+        })
+        .collect();
+
+        Self {
+            enabled: true,
+            segment_format: SegmentFormat::Fmp4,
+            segment_secs: 6,
+            work_dir: PathBuf::from("./hls-work"),
+            audio_kbps: 128,
+            renditions,
+        }
+    }
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            backoff_secs: 60,
+            max_backoff_secs: 6 * 60 * 60,
+            retention_days: 30,
+            concurrency: JobConcurrency::default(),
+        }
+    }
+}
+
+impl Default for JobConcurrency {
+    fn default() -> Self {
+        Self {
+            probe: 4,
+            thumbnails: 2,
+            storyboard: 1,
+            hls: 1,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        // Chatty dependencies are kept at info unless configured otherwise
+        let modules = [
+            "h2",
+            "hyper",
+            "mio",
+            "rustls",
+            "tokio_tungstenite",
+            "tungstenite",
+            "want",
+        ]
+        .into_iter()
+        .map(|module| (module.to_string(), String::from("info")))
+        .collect();
+
+        Self {
+            level: String::from("debug"),
+            modules,
+        }
+    }
+}
+
+impl Config {
+    /// Build the effective config: defaults, then the config file, then `cli` (flags and their
+    /// environment variables). The result is validated.
+    pub fn load(cli: &Cli) -> anyhow::Result<Self> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+        config.apply_overrides(cli)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    fn apply_overrides(&mut self, cli: &Cli) -> anyhow::Result<()> {
+        if let Some(bind) = &cli.bind {
+            self.server.bind = bind
+                .parse()
+                .with_context(|| format!("invalid bind address '{}'", bind))?;
+        }
+        if let Some(size) = &cli.max_upload_size {
+            self.server.max_upload_size =
+                Some(size.trim().parse().with_context(|| {
+                    format!("invalid max upload size '{}', expected bytes", size)
+                })?);
+        }
+        if let Some(backend) = &cli.storage {
+            self.storage.backend = match backend.as_str() {
+                "local" => StorageKind::Local,
+                "s3" => StorageKind::S3,
+                other => anyhow::bail!("unknown storage backend '{}', expected local or s3", other),
+            };
+        }
+
+        let s3 = &mut self.storage.s3;
+        override_with(&mut self.storage.dir, &cli.archive_dir);
+        override_with(&mut s3.region, &cli.s3_region);
+        override_option(&mut s3.bucket, &cli.s3_bucket);
+        override_option(&mut s3.endpoint, &cli.s3_endpoint);
+        override_option(&mut s3.prefix, &cli.s3_prefix);
+        override_option(&mut s3.access_key_id, &cli.s3_access_key_id);
+        override_option(&mut s3.secret_access_key, &cli.s3_secret_access_key);
+        override_with(&mut self.catalog.path, &cli.catalog_path);
+        override_with(&mut self.tus.dir, &cli.tus_dir);
+        override_with(&mut self.cors.allowed_origins, &cli.cors_allowed_origins);
+        override_with(&mut self.probe.ffprobe, &cli.ffprobe);
+        override_with(&mut self.thumbnails.ffmpeg, &cli.ffmpeg);
+        override_with(&mut self.log.level, &cli.log_level);
+
+        if let Some(policy) = &cli.conflict_policy {
+            self.uploads.conflict_policy = policy.parse().map_err(anyhow::Error::msg)?;
+        }
+        Ok(())
+    }
+
+    /// Largest accepted upload, in bytes: as configured, or else the most the backend can take
+    /// up to [`DEFAULT_MAX_UPLOAD_SIZE`].
+    pub fn max_upload_size(&self) -> u64 {
+        self.server
+            .max_upload_size
+            .unwrap_or(match self.storage.backend {
+                StorageKind::Local => DEFAULT_MAX_UPLOAD_SIZE,
+                StorageKind::S3 => MAX_S3_UPLOAD_SIZE,
+            })
+    }
+
+    /// Catch mistakes at startup (or reload) rather than on the first request that hits them.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let max_upload_size = self.max_upload_size();
+        if max_upload_size == 0 {
+            anyhow::bail!("server.max_upload_size must be greater than 0");
+        }
+        if usize::try_from(max_upload_size).is_err() {
+            anyhow::bail!("server.max_upload_size is too large for this platform");
+        }
+
+        match self.storage.backend {
+            StorageKind::Local if self.storage.dir.as_os_str().is_empty() => {
+                anyhow::bail!("storage.dir must be set for the local backend")
+            }
+            StorageKind::S3 if self.storage.s3.bucket.as_deref().unwrap_or("").is_empty() => {
+                anyhow::bail!("storage.s3.bucket must be set for the s3 backend")
+            }
+            StorageKind::S3 if max_upload_size > MAX_S3_UPLOAD_SIZE => {
+                anyhow::bail!(
+                    "server.max_upload_size may be at most {} bytes (5 GiB) for the s3 backend; \
+                     leave it unset to use that",
+                    MAX_S3_UPLOAD_SIZE
+                )
+            }
+            _ => {}
+        }
+        if self.catalog.path.as_os_str().is_empty() {
+            anyhow::bail!("catalog.path must be set");
+        }
+        if self.tus.dir.as_os_str().is_empty() {
+            anyhow::bail!("tus.dir must be set");
+        }
+
+        // Overwriting must be asked for by each upload, never assumed
+        if self.uploads.conflict_policy == ConflictPolicy::Overwrite {
+            anyhow::bail!("uploads.conflict_policy may not be 'overwrite'");
+        }
+
+        let origins = &self.cors.allowed_origins;
+        if origins.iter().any(|origin| origin == "*") && origins.len() > 1 {
+            anyhow::bail!("cors.allowed_origins may not mix '*' with specific origins");
+        }
+        if let Some(origin) = origins
+            .iter()
+            .find(|origin| *origin != "*" && http::HeaderValue::from_str(origin).is_err())
+        {
+            anyhow::bail!("cors.allowed_origins: invalid origin '{}'", origin);
+        }
+
+        if self.fixity.interval_hours == 0 {
+            anyhow::bail!("fixity.interval_hours must be greater than 0");
+        }
+
+        let thumbnails = &self.thumbnails;
+        if !thumbnails.offset_secs.is_finite() || thumbnails.offset_secs < 0.0 {
+            anyhow::bail!("thumbnails.offset_secs must be 0 or more");
+        }
+        if thumbnails.sizes.is_empty() {
+            anyhow::bail!("thumbnails.sizes must list at least one width");
+        }
+        if let Some(size) = thumbnails
+            .sizes
+            .iter()
+            .find(|&&size| size == 0 || size > MAX_THUMBNAIL_SIZE)
+        {
+            anyhow::bail!(
+                "thumbnails.sizes: {} is not between 1 and {}",
+                size,
+                MAX_THUMBNAIL_SIZE
+            );
+        }
+
+        let storyboards = &self.storyboards;
+        if !storyboards.interval_secs.is_finite() || storyboards.interval_secs < 1.0 {
+            anyhow::bail!("storyboards.interval_secs must be 1 or more");
+        }
+        if storyboards.tile_width == 0 || storyboards.tile_width > MAX_THUMBNAIL_SIZE {
+            anyhow::bail!(
+                "storyboards.tile_width must be between 1 and {}",
+                MAX_THUMBNAIL_SIZE
+            );
+        }
+        for (name, tiles) in [("columns", storyboards.columns), ("rows", storyboards.rows)] {
+            if tiles == 0 || tiles > MAX_STORYBOARD_TILES {
+                anyhow::bail!(
+                    "storyboards.{} must be between 1 and {}",
+                    name,
+                    MAX_STORYBOARD_TILES
+                );
+            }
+        }
+
+        let hls = &self.hls;
+        if hls.segment_secs == 0 || hls.segment_secs > 60 {
+            anyhow::bail!("hls.segment_secs must be between 1 and 60");
+        }
+        if hls.work_dir.as_os_str().is_empty() {
+            anyhow::bail!("hls.work_dir must be set");
+        }
+        if hls.audio_kbps == 0 {
+            anyhow::bail!("hls.audio_kbps must be greater than 0");
+        }
+        if hls.renditions.is_empty() {
+            anyhow::bail!("hls.renditions must list at least one rendition");
+        }
+        let mut names = HashSet::new();
+        for rendition in &hls.renditions {
+            let name = &rendition.name;
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                anyhow::bail!(
+                    "hls.renditions: name '{}' may only use letters, digits, - and _",
+                    name
+                );
+            }
+            if name == AUDIO_RENDITION {
+                anyhow::bail!("hls.renditions: '{}' names the audio rendition", name);
+            }
+            if !names.insert(name.as_str()) {
+                anyhow::bail!("hls.renditions: '{}' is listed twice", name);
+            }
+            if rendition.height < 2
+                || rendition.height > MAX_RENDITION_HEIGHT
+                || rendition.height % 2 != 0
+            {
+                anyhow::bail!(
+                    "hls.renditions.{}: height must be even and between 2 and {}",
+                    name,
+                    MAX_RENDITION_HEIGHT
+                );
+            }
+            if rendition.video_kbps == 0 {
+                anyhow::bail!("hls.renditions.{}: video_kbps must be greater than 0", name);
+            }
+        }
+
+        let jobs = &self.jobs;
+        if jobs.max_attempts == 0 {
+            anyhow::bail!("jobs.max_attempts must be greater than 0");
+        }
+        if jobs.backoff_secs == 0 || jobs.max_backoff_secs < jobs.backoff_secs {
+            anyhow::bail!("jobs.backoff_secs must be between 1 and jobs.max_backoff_secs");
+        }
+        if jobs.retention_days == 0 {
+            anyhow::bail!("jobs.retention_days must be greater than 0");
+        }
+        if let Some(kind) = JobKind::ALL
+            .into_iter()
+            .find(|&kind| jobs.concurrency.of(kind) > MAX_JOB_CONCURRENCY)
+        {
+            anyhow::bail!(
+                "jobs.concurrency.{} may not be more than {}",
+                kind.as_str(),
+                MAX_JOB_CONCURRENCY
+            );
+        }
+
+        self.log.root_level()?;
+        self.log.module_levels()?;
+        Ok(())
+    }
+
+    /// Take the settings that can change at runtime from `new`, keeping everything else. Returns
+    /// the sections that differ but need a restart.
+    pub fn reloaded(&self, new: Config) -> (Config, Vec<&'static str>) {
+        let mut restart_needed = vec![];
+        if new.server != self.server {
+            restart_needed.push("server");
+        }
+        if new.storage != self.storage {
+            restart_needed.push("storage");
+        }
+        if new.catalog != self.catalog {
+            restart_needed.push("catalog");
+        }
+        if new.tus != self.tus {
+            restart_needed.push("tus");
+        }
+
+        let config = Config {
+            server: self.server.clone(),
+            storage: self.storage.clone(),
+            catalog: self.catalog.clone(),
+            tus: self.tus.clone(),
+            uploads: new.uploads,
+            cors: new.cors,
+            fixity: new.fixity,
+            probe: new.probe,
+            thumbnails: new.thumbnails,
+            storyboards: new.storyboards,
+            hls: new.hls,
+            jobs: new.jobs,
+            log: new.log,
+        };
+        (config, restart_needed)
+    }
+}
+
+impl CorsConfig {
+    pub fn allows_any(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    pub fn allows(&self, origin: &[u8]) -> bool {
+        self.allows_any()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin)
+    }
+}
+
+impl LogConfig {
+    pub fn root_level(&self) -> anyhow::Result<LevelFilter> {
+        parse_level(&self.level).context("invalid log.level")
+    }
+
+    pub fn module_levels(&self) -> anyhow::Result<Vec<(String, LevelFilter)>> {
+        self.modules
+            .iter()
+            .map(|(module, level)| {
+                let level = parse_level(level)
+                    .with_context(|| format!("invalid log.modules.{}", module))?;
+                Ok((module.clone(), level))
+            })
+            .collect()
+    }
+}
+
+fn parse_level(level: &str) -> anyhow::Result<LevelFilter> {
+    level.parse().map_err(|_| {
+        anyhow::anyhow!(
+            "unknown log level '{}', expected off, error, warn, info, debug or trace",
+            level
+        )
+    })
+}
+
+fn override_with<T: Clone>(value: &mut T, cli: &Option<T>) {
+    if let Some(cli) = cli {
+        *value = cli.clone();
+    }
+}
+
+fn override_option<T: Clone>(value: &mut Option<T>, cli: &Option<T>) {
+    if cli.is_some() {
+        value.clone_from(cli);
+    }
+}
+
+/// The running config, swapped wholesale on reload.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        match self.0.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    fn replace(&self, config: Config) {
+        let mut current = match self.0.write() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *current = Arc::new(config);
+    }
+}
+
+/// Re-read the config on SIGHUP and apply whatever can change without a restart: the default
+/// conflict policy, CORS origins, fixity checking, the ffprobe path, thumbnails, storyboards, HLS
+/// packaging, the job queue's limits and log levels.
+/// A config that fails to load or validate is logged and ignored.
+#[cfg(unix)]
+pub fn spawn_reloader(cli: Cli, shared: SharedConfig, logger: crate::utils::logger::Handle) {
+    use log::{error, info, warn};
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                error!("Config reload on SIGHUP is unavailable: {}", err);
+                return;
+            }
+        };
+
+        while hangups.recv().await.is_some() {
+            let new = match Config::load(&cli) {
+                Ok(new) => new,
+                Err(err) => {
+                    error!("Keeping the current config, reload failed: {:#}", err);
+                    continue;
+                }
+            };
+
+            let (config, restart_needed) = shared.current().reloaded(new);
+            if let Err(err) = crate::utils::logger::reconfigure(&logger, &config.log) {
+                error!("Keeping the current config, reload failed: {:#}", err);
+                continue;
+            }
+            shared.replace(config);
+
+            info!("Config reloaded");
+            if !restart_needed.is_empty() {
+                warn!(
+                    "Changes to [{}] take effect after a restart",
+                    restart_needed.join("], [")
+                );
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn accepts_the_defaults() {
+        let config = Config::default();
+        config.validate().unwrap();
+        assert_eq!(config.max_upload_size(), DEFAULT_MAX_UPLOAD_SIZE);
+    }
+
+    #[test]
+    fn defaults_the_upload_size_by_backend() {
+        for (text, expected) in [
+            ("", DEFAULT_MAX_UPLOAD_SIZE),
+            ("[server]\nmax_upload_size = 1024", 1024),
+            (
+                "[storage]\nbackend = \"s3\"\ns3.bucket = \"videos\"",
+                MAX_S3_UPLOAD_SIZE,
+            ),
+            (
+                "[server]\nmax_upload_size = 1024\n[storage]\nbackend = \"s3\"\ns3.bucket = \"videos\"",
+                1024,
+            ),
+        ] {
+            let config = parse(text);
+            config.validate().unwrap();
+            assert_eq!(config.max_upload_size(), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        for (text, message) in [
+            ("[server]\nmax_upload_size = 0", "greater than 0"),
+            (
+                "[server]\nmax_upload_size = 5368709121\n[storage]\nbackend = \"s3\"\ns3.bucket = \"videos\"",
+                "at most 5368709120 bytes",
+            ),
+            ("[storage]\nbackend = \"s3\"", "bucket must be set"),
+            ("[uploads]\nconflict_policy = \"overwrite\"", "may not be 'overwrite'"),
+            (
+                "[cors]\nallowed_origins = [\"https://a.example\\n\"]",
+                "invalid origin",
+            ),
+            (
+                "[cors]\nallowed_origins = [\"*\", \"https://a.example\"]",
+                "may not mix",
+            ),
+            ("[fixity]\ninterval_hours = 0", "interval_hours"),
+            ("[thumbnails]\nsizes = [0]", "not between 1"),
+            ("[storyboards]\ninterval_secs = 0.5", "interval_secs"),
+            ("[hls]\nsegment_secs = 0", "segment_secs"),
+            ("[jobs]\nmax_attempts = 0", "max_attempts"),
+            ("[log]\nlevel = \"loud\"", "invalid log.level"),
+        ] {
+            let err = parse(text).validate().unwrap_err();
+            assert!(err.to_string().contains(message), "{:?}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(toml::from_str::<Config>("[server]\nport = 80").is_err());
+        assert!(toml::from_str::<Config>("[uploads]\nconflict_policy = \"replace\"").is_err());
+    }
+
+    /// The only test setting these variables, so none of the others race with it.
+    #[test]
+    fn flags_beat_environment_beats_file_beats_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("video-archiver-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[server]\nbind = \"127.0.0.1:1000\"\n\
+             [catalog]\npath = \"file.db\"\n\
+             [tus]\ndir = \"file-tus\"\n\
+             [uploads]\nconflict_policy = \"rename\"",
+        )
+        .unwrap();
+        std::env::set_var("BIND_ADDR", "127.0.0.1:2000");
+        std::env::set_var("TUS_DIR", "env-tus");
+
+        let cli = Cli::try_parse_from([
+            "video-archiver",
+            "--config",
+            path.to_str().unwrap(),
+            "--bind",
+            "127.0.0.1:3000",
+            "--max-upload-size",
+            "4096",
+        ]);
+        std::env::remove_var("BIND_ADDR");
+        std::env::remove_var("TUS_DIR");
+        let config = Config::load(&cli.unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Flag over environment and file
+        assert_eq!(config.server.bind, "127.0.0.1:3000".parse().unwrap());
+        assert_eq!(config.max_upload_size(), 4096);
+        // Environment over file
+        assert_eq!(config.tus.dir, PathBuf::from("env-tus"));
+        // File over defaults
+        assert_eq!(config.catalog.path, PathBuf::from("file.db"));
+        assert_eq!(config.uploads.conflict_policy, ConflictPolicy::Rename);
+        // Defaults
+        assert_eq!(config.storage.dir, PathBuf::from("./archive"));
+    }
+
+    #[test]
+    fn validates_overrides() {
+        for args in [
+            ["--bind", "localhost"],
+            ["--max-upload-size", "20GB"],
+            ["--storage", "ftp"],
+            ["--conflict-policy", "replace"],
+            ["--conflict-policy", "overwrite"],
+        ] {
+            let cli = Cli::try_parse_from(
+                ["video-archiver", "--config", "archiver.example.toml"]
+                    .into_iter()
+                    .chain(args),
+            )
+            .unwrap();
+            assert!(Config::load(&cli).is_err(), "{:?}", args);
+        }
+    }
+}
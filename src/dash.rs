@@ -0,0 +1,323 @@
+//! MPEG-DASH manifests for HLS packages in fMP4. The MPD describes the very init and media
+//! segments the HLS playlists list, so nothing is stored twice: one `SegmentTemplate` with a
+//! `SegmentTimeline` per representation, in the ISO BMFF live profile.
+
+use crate::{
+    models::TrackKind,
+    mp4::{self, InitSegment, SegmentTiming, Walker},
+};
+use anyhow::Context;
+use std::{
+    fmt::Write,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+pub const MANIFEST: &str = "manifest.mpd";
+
+pub const CONTENT_TYPE: &str = "application/dash+xml";
+
+const PROFILE: &str = "urn:mpeg:dash:profile:isoff-live:2011";
+
+/// Largest `moof` read to time a segment.
+const MAX_MOOF_SIZE: u64 = 16 * 1024 * 1024;
+
+/// One rendition of a package, as a DASH representation.
+struct Representation {
+    id: String,
+    init: InitSegment,
+    start_number: u64,
+    segments: Vec<SegmentTiming>,
+    /// Peak bit rate of any one segment, so a client that has it never stalls.
+    bandwidth: u64,
+}
+
+impl Representation {
+    fn end(&self) -> u64 {
+        self.segments
+            .last()
+            .map_or(0, |last| last.start + last.duration)
+    }
+
+    fn seconds(&self, time: u64) -> f64 {
+        time as f64 / self.init.timescale as f64
+    }
+
+    /// Frames per second across all segments, as a fraction in lowest terms.
+    fn frame_rate(&self) -> Option<(u64, u64)> {
+        let samples: u64 = self.segments.iter().map(|segment| segment.samples).sum();
+        let duration: u64 = self.segments.iter().map(|segment| segment.duration).sum();
+        reduce(samples.checked_mul(self.init.timescale as u64)?, duration)
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn reduce(numerator: u64, denominator: u64) -> Option<(u64, u64)> {
+    if numerator == 0 || denominator == 0 {
+        return None;
+    }
+    let divisor = gcd(numerator, denominator);
+    Some((numerator / divisor, denominator / divisor))
+}
+
+fn fraction((numerator, denominator): (u64, u64)) -> String {
+    match denominator {
+        1 => numerator.to_string(),
+        _ => format!("{}/{}", numerator, denominator),
+    }
+}
+
+/// An `xs:duration` of `secs` seconds.
+fn duration(secs: f64) -> String {
+    format!("PT{:.3}S", secs)
+}
+
+/// The `moof` boxes of the segment at `path`, leaving out its media data.
+fn read_moofs(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut walker = Walker::new(file.metadata()?.len());
+    let mut moofs = vec![];
+    while let Some(offset) = walker.offset() {
+        let mut head = vec![];
+        file.seek(SeekFrom::Start(offset))?;
+        (&mut file).take(mp4::HEADER_LEN).read_to_end(&mut head)?;
+        let top = walker.next(&head)?;
+        if &top.kind != b"moof" {
+            continue;
+        }
+
+        anyhow::ensure!(top.size() <= MAX_MOOF_SIZE, "moof of {} bytes", top.size());
+        file.seek(SeekFrom::Start(top.offset))?;
+        (&mut file).take(top.size()).read_to_end(&mut moofs)?;
+    }
+    Ok(moofs)
+}
+
+/// Rendition `name` of the package in `dir`: `<name>_init.mp4` and `<name>_<number>.m4s`.
+fn representation(dir: &Path, name: &str) -> anyhow::Result<Representation> {
+    let init = std::fs::read(dir.join(format!("{}_init.mp4", name)))?;
+    let init = mp4::parse_init(&init).context("unreadable init segment")?;
+
+    let prefix = format!("{}_", name);
+    let mut numbers = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(number) = file_name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(".m4s"))
+            .and_then(|number| number.parse::<u64>().ok())
+        {
+            numbers.push(number);
+        }
+    }
+    numbers.sort_unstable();
+    let start_number = *numbers.first().context("no segments")?;
+    anyhow::ensure!(
+        numbers
+            .iter()
+            .zip(start_number..)
+            .all(|(&n, expected)| n == expected),
+        "segments are not numbered consecutively"
+    );
+
+    let mut segments = vec![];
+    let mut bandwidth = 0;
+    for number in numbers {
+        let path = dir.join(format!("{}{:05}.m4s", prefix, number));
+        let timing = mp4::segment_timing(&read_moofs(&path)?, init.default_sample_duration)
+            .with_context(|| format!("untimed segment {}", number))?;
+        anyhow::ensure!(timing.duration > 0, "empty segment {}", number);
+
+        let bits = path.metadata()?.len() * 8;
+        let rate = bits as u128 * init.timescale as u128 / timing.duration as u128;
+        bandwidth = bandwidth.max(rate as u64 + 1);
+        segments.push(timing);
+    }
+
+    Ok(Representation {
+        id: name.to_string(),
+        init,
+        start_number,
+        segments,
+        bandwidth,
+    })
+}
+
+/// `<SegmentTemplate>` with a timeline of `representation`'s segments, runs of equal durations
+/// folded into one `S` with a repeat count.
+fn segment_template(representation: &Representation, xml: &mut String) {
+    let _ = writeln!(
+        xml,
+        r#"        <SegmentTemplate timescale="{}" initialization="$RepresentationID$_init.mp4" media="$RepresentationID$_$Number%05d$.m4s" startNumber="{}">"#,
+        representation.init.timescale, representation.start_number
+    );
+    xml.push_str("          <SegmentTimeline>\n");
+
+    let mut runs: Vec<(u64, u64, u64)> = vec![];
+    let mut expected = None;
+    for segment in &representation.segments {
+        match runs.last_mut() {
+            Some((_, duration, repeat))
+                if *duration == segment.duration && expected == Some(segment.start) =>
+            {
+                *repeat += 1;
+            }
+            _ => runs.push((segment.start, segment.duration, 0)),
+        }
+        expected = Some(segment.start + segment.duration);
+    }
+
+    let mut expected = None;
+    for (start, duration, repeat) in runs {
+        xml.push_str("            <S");
+        if expected != Some(start) {
+            let _ = write!(xml, r#" t="{}""#, start);
+        }
+        let _ = write!(xml, r#" d="{}""#, duration);
+        if repeat > 0 {
+            let _ = write!(xml, r#" r="{}""#, repeat);
+        }
+        xml.push_str("/>\n");
+        expected = Some(start + duration * (repeat + 1));
+    }
+
+    xml.push_str("          </SegmentTimeline>\n");
+    xml.push_str("        </SegmentTemplate>\n");
+}
+
+fn video_set(id: usize, representations: &[&Representation], xml: &mut String) {
+    let largest = representations
+        .iter()
+        .max_by_key(|representation| representation.init.track.height)
+        .map(|representation| &representation.init);
+    let size = largest.and_then(|init| Some((init.track.width?, init.track.height?)));
+    let _ = write!(
+        xml,
+        r#"    <AdaptationSet id="{}" contentType="video" mimeType="video/mp4" segmentAlignment="true" startWithSAP="1""#,
+        id
+    );
+    if let (Some((width, height)), Some(init)) = (size, largest) {
+        let (h_spacing, v_spacing) = init.sample_aspect;
+        let _ = write!(xml, r#" maxWidth="{}" maxHeight="{}""#, width, height);
+        if let Some((x, y)) = reduce(
+            width as u64 * h_spacing as u64,
+            height as u64 * v_spacing as u64,
+        ) {
+            let _ = write!(xml, r#" par="{}:{}""#, x, y);
+        }
+    }
+    xml.push_str(">\n");
+
+    for representation in representations {
+        let init = &representation.init;
+        let _ = write!(
+            xml,
+            r#"      <Representation id="{}" codecs="{}" bandwidth="{}""#,
+            representation.id, init.codecs, representation.bandwidth
+        );
+        if let (Some(width), Some(height)) = (init.track.width, init.track.height) {
+            let _ = write!(xml, r#" width="{}" height="{}""#, width, height);
+        }
+        if let Some(frame_rate) = representation.frame_rate() {
+            let _ = write!(xml, r#" frameRate="{}""#, fraction(frame_rate));
+        }
+        let (h_spacing, v_spacing) = init.sample_aspect;
+        let _ = writeln!(xml, r#" sar="{}:{}">"#, h_spacing, v_spacing);
+        segment_template(representation, xml);
+        xml.push_str("      </Representation>\n");
+    }
+    xml.push_str("    </AdaptationSet>\n");
+}
+
+fn audio_set(id: usize, representation: &Representation, xml: &mut String) {
+    let init = &representation.init;
+    let _ = write!(
+        xml,
+        r#"    <AdaptationSet id="{}" contentType="audio" mimeType="audio/mp4" segmentAlignment="true" startWithSAP="1""#,
+        id
+    );
+    if let Some(language) = &init.track.language {
+        let _ = write!(xml, r#" lang="{}""#, language);
+    }
+    xml.push_str(">\n");
+    xml.push_str(r#"      <Role schemeIdUri="urn:mpeg:dash:role:2011" value="main"/>"#);
+    xml.push('\n');
+
+    let _ = write!(
+        xml,
+        r#"      <Representation id="{}" codecs="{}" bandwidth="{}""#,
+        representation.id, init.codecs, representation.bandwidth
+    );
+    if let Some(sample_rate) = init.track.sample_rate {
+        let _ = write!(xml, r#" audioSamplingRate="{}""#, sample_rate);
+    }
+    xml.push_str(">\n");
+    if let Some(channels) = init.track.channels {
+        let _ = writeln!(
+            xml,
+            r#"        <AudioChannelConfiguration schemeIdUri="urn:mpeg:dash:23003:3:audio_channel_configuration:2011" value="{}"/>"#,
+            channels
+        );
+    }
+    segment_template(representation, xml);
+    xml.push_str("      </Representation>\n");
+    xml.push_str("    </AdaptationSet>\n");
+}
+
+/// The MPD for renditions `names` of the package ffmpeg wrote into `dir`, with segments under
+/// `base_url`. Video renditions make up one adaptation set, audio another.
+pub fn manifest(dir: &Path, names: &[&str], base_url: &str) -> anyhow::Result<String> {
+    let representations = names
+        .iter()
+        .map(|name| representation(dir, name).with_context(|| format!("rendition {}", name)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let video: Vec<_> = representations
+        .iter()
+        .filter(|representation| representation.init.track.kind == TrackKind::Video)
+        .collect();
+    let audio: Vec<_> = representations
+        .iter()
+        .filter(|representation| representation.init.track.kind == TrackKind::Audio)
+        .collect();
+    anyhow::ensure!(!video.is_empty(), "no video rendition");
+
+    let end = representations
+        .iter()
+        .map(|representation| representation.seconds(representation.end()))
+        .fold(0.0, f64::max);
+    let longest_segment = representations
+        .iter()
+        .flat_map(|representation| {
+            representation
+                .segments
+                .iter()
+                .map(|segment| representation.seconds(segment.duration))
+        })
+        .fold(0.0, f64::max);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" profiles="{}" type="static" mediaPresentationDuration="{}" minBufferTime="{}">"#,
+        PROFILE,
+        duration(end),
+        duration(longest_segment.ceil())
+    );
+    let _ = writeln!(xml, "  <BaseURL>{}</BaseURL>", base_url);
+    xml.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+    video_set(0, &video, &mut xml);
+    for (n, representation) in audio.into_iter().enumerate() {
+        audio_set(n + 1, representation, &mut xml);
+    }
+    xml.push_str("  </Period>\n");
+    xml.push_str("</MPD>\n");
+    Ok(xml)
+}
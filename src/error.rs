@@ -0,0 +1,252 @@
+use crate::{
+    models::{ResponseError, StorageKeyError, UploadConflict},
+    thumbnails::InvalidPoster,
+    utils::request_id,
+};
+use axum::{
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{BytesRejection, JsonRejection, PathRejection, QueryRejection},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::error;
+use std::{fmt, io};
+
+pub type AppResult<T> = Result<T, AppError>;
+
+/// Everything a handler can fail with. Each variant has a fixed status and a stable `code` that
+/// clients can match on; the message is for humans.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    InvalidFileName(StorageKeyError),
+    /// A `Content-Digest`, `Repr-Digest` or `Content-MD5` header we can't use.
+    InvalidDigest(String),
+    /// The content doesn't hash to the digest the client sent.
+    DigestMismatch(String),
+    NotFound(String),
+    /// The upload's name is taken and its conflict policy doesn't allow resolving that.
+    FileExists(String),
+    Conflict(String),
+    Gone(String),
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    Locked(String),
+    InsufficientStorage,
+    Internal(anyhow::Error),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_)
+            | Self::InvalidFileName(_)
+            | Self::InvalidDigest(_)
+            | Self::DigestMismatch(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::FileExists(_) | Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Gone(_) => StatusCode::GONE,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Locked(_) => StatusCode::LOCKED,
+            Self::InsufficientStorage => StatusCode::INSUFFICIENT_STORAGE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::InvalidFileName(_) => "invalid_file_name",
+            Self::InvalidDigest(_) => "invalid_digest",
+            Self::DigestMismatch(_) => "digest_mismatch",
+            Self::NotFound(_) => "not_found",
+            Self::FileExists(_) => "file_exists",
+            Self::Conflict(_) => "conflict",
+            Self::Gone(_) => "gone",
+            Self::PreconditionFailed(_) => "precondition_failed",
+            Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::Locked(_) => "locked",
+            Self::InsufficientStorage => "insufficient_storage",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn not_found(what: &str) -> Self {
+        Self::NotFound(format!("{} not found", what))
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(message)
+            | Self::InvalidDigest(message)
+            | Self::DigestMismatch(message)
+            | Self::NotFound(message)
+            | Self::FileExists(message)
+            | Self::Conflict(message)
+            | Self::Gone(message)
+            | Self::PreconditionFailed(message)
+            | Self::PayloadTooLarge(message)
+            | Self::UnsupportedMediaType(message)
+            | Self::Locked(message) => f.write_str(message),
+            Self::InvalidFileName(err) => err.fmt(f),
+            Self::InsufficientStorage => f.write_str("the archive is out of space"),
+            // Internals stay in the log
+            Self::Internal(_) => f.write_str("internal server error"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let request_id = request_id::current();
+        if let Self::Internal(err) = &self {
+            error!(
+                "Request {}: {:#}",
+                request_id.as_deref().unwrap_or("-"),
+                err
+            );
+        }
+
+        let body = ResponseError {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            request_id,
+        };
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+impl From<StorageKeyError> for AppError {
+    fn from(err: StorageKeyError) -> Self {
+        Self::InvalidFileName(err)
+    }
+}
+
+impl From<UploadConflict> for AppError {
+    fn from(err: UploadConflict) -> Self {
+        Self::FileExists(err.0)
+    }
+}
+
+impl From<InvalidPoster> for AppError {
+    fn from(err: InvalidPoster) -> Self {
+        Self::UnsupportedMediaType(err.0)
+    }
+}
+
+impl From<MultipartError> for AppError {
+    fn from(err: MultipartError) -> Self {
+        match err.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge(err.body_text()),
+            status if status.is_client_error() => Self::BadRequest(err.body_text()),
+            _ => Self::Internal(err.into()),
+        }
+    }
+}
+
+impl From<MultipartRejection> for AppError {
+    fn from(_: MultipartRejection) -> Self {
+        Self::UnsupportedMediaType(String::from("expected a multipart/form-data body"))
+    }
+}
+
+impl From<BytesRejection> for AppError {
+    fn from(err: BytesRejection) -> Self {
+        match err.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge(err.body_text()),
+            status if status.is_client_error() => Self::BadRequest(err.body_text()),
+            _ => Self::Internal(anyhow::anyhow!(err.body_text())),
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(err: JsonRejection) -> Self {
+        match err.status() {
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => Self::UnsupportedMediaType(err.body_text()),
+            StatusCode::PAYLOAD_TOO_LARGE => Self::PayloadTooLarge(err.body_text()),
+            status if status.is_client_error() => Self::BadRequest(err.body_text()),
+            _ => Self::Internal(anyhow::anyhow!(err.body_text())),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(err: QueryRejection) -> Self {
+        Self::BadRequest(err.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(err: PathRejection) -> Self {
+        Self::BadRequest(err.body_text())
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    /// Recover the specific error from wherever it surfaced: client mistakes and full disks
+    /// usually arrive wrapped in an I/O or storage error.
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<AppError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<StorageKeyError>() {
+            Ok(err) => return err.into(),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<UploadConflict>() {
+            Ok(err) => return err.into(),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<InvalidPoster>() {
+            Ok(err) => return err.into(),
+            Err(err) => err,
+        };
+
+        for cause in err.chain() {
+            if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+                if io_err.kind() == io::ErrorKind::StorageFull {
+                    return Self::InsufficientStorage;
+                }
+                if let Some(multipart) = io_err
+                    .get_ref()
+                    .and_then(|inner| inner.downcast_ref::<MultipartError>())
+                {
+                    return match multipart.status() {
+                        StatusCode::PAYLOAD_TOO_LARGE => {
+                            Self::PayloadTooLarge(multipart.body_text())
+                        }
+                        _ => Self::BadRequest(multipart.body_text()),
+                    };
+                }
+            }
+            if let Some(rusqlite::Error::SqliteFailure(sqlite_err, _)) =
+                cause.downcast_ref::<rusqlite::Error>()
+            {
+                if sqlite_err.code == rusqlite::ErrorCode::DiskFull {
+                    return Self::InsufficientStorage;
+                }
+            }
+        }
+
+        Self::Internal(err)
+    }
+}
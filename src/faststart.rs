@@ -0,0 +1,107 @@
+//! Fast start for MP4 uploads: many cameras write `moov`, the index players need before they can
+//! start, after the media data. Such files are rewritten on ingest with `moov` first, so playback
+//! from `/stream` can begin before the whole file has arrived. No media is re-encoded.
+
+use crate::{
+    mp4::{self, Walker},
+    probe::Source,
+    storage::{blobs::BLOB_DIGESTS, staging::StagedUpload, ByteReader, StorageBackend},
+    utils::{
+        digest::{DigestingReader, Digests},
+        mime,
+        range::ByteRange,
+    },
+};
+use log::debug;
+use std::{io::Cursor, sync::Arc};
+use tokio::io::AsyncReadExt;
+
+/// A rewritten copy of an upload, in staging.
+pub struct Rewritten {
+    pub staged: StagedUpload,
+    pub size: u64,
+    pub digests: Digests,
+}
+
+/// Rewrite the staged MP4 at `key` with `moov` first, into a new staged object. Returns `None`
+/// when there is nothing to do: the file isn't an MP4, or is already laid out for streaming.
+pub async fn relocate_moov(
+    storage: &Arc<dyn StorageBackend>,
+    key: &str,
+) -> anyhow::Result<Option<Rewritten>> {
+    let source = Source::open(storage.as_ref(), key).await?;
+    let head = source.read(0, mime::SNIFF_LEN as u64).await?;
+    if !mime::sniff(&head).is_some_and(|content_type| mp4::CONTENT_TYPES.contains(&content_type)) {
+        return Ok(None);
+    }
+
+    let mut walker = Walker::new(source.size());
+    let mut top_level = vec![];
+    while let Some(offset) = walker.offset() {
+        let head = source.read(offset, mp4::HEADER_LEN).await?;
+        match walker.next(&head) {
+            Ok(top) => top_level.push(top),
+            Err(err) => {
+                debug!("Leaving {} as it is: {}", key, err);
+                return Ok(None);
+            }
+        }
+    }
+    let Some(relocation) = mp4::plan_faststart(&top_level) else {
+        return Ok(None);
+    };
+
+    let moov = relocation.moov;
+    anyhow::ensure!(
+        moov.size() <= mp4::MAX_MOOV_SIZE,
+        "moov box of {} bytes is too large",
+        moov.size()
+    );
+    let mut moov_box = source.read(moov.offset, moov.size()).await?;
+    let header_len = (moov.body_offset - moov.offset) as usize;
+    mp4::shift_chunk_offsets(&mut moov_box[header_len..], &relocation)?;
+
+    // Everything before the media data, then `moov`, then the rest minus the old `moov`
+    let mut reader = range_reader(storage.as_ref(), key, 0, relocation.insert_at).await?;
+    let pieces = [
+        (relocation.insert_at, moov.offset),
+        (moov.end(), source.size()),
+    ];
+    reader = Box::new(reader.chain(Cursor::new(moov_box)));
+    for (start, end) in pieces {
+        reader = Box::new(reader.chain(range_reader(storage.as_ref(), key, start, end).await?));
+    }
+
+    let staged = StagedUpload::new(storage.clone());
+    let mut reader = DigestingReader::new(reader, BLOB_DIGESTS)?;
+    let size = storage.put_stream(staged.key(), &mut reader).await?;
+    anyhow::ensure!(
+        size == source.size(),
+        "rewrote {} bytes into {}",
+        source.size(),
+        size
+    );
+
+    Ok(Some(Rewritten {
+        staged,
+        size,
+        digests: reader.finish()?,
+    }))
+}
+
+/// Bytes `start..end` of the object at `key`.
+async fn range_reader(
+    storage: &dyn StorageBackend,
+    key: &str,
+    start: u64,
+    end: u64,
+) -> anyhow::Result<ByteReader> {
+    if start >= end {
+        return Ok(Box::new(tokio::io::empty()));
+    }
+    let range = ByteRange {
+        start,
+        end: end - 1,
+    };
+    storage.get_range_stream(key, Some(range)).await
+}
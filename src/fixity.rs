@@ -0,0 +1,313 @@
+use crate::{
+    catalog::{self, BlobRecord, Catalog},
+    config::SharedConfig,
+    models::{Fixity, ScrubReport},
+    storage::{
+        blobs::{blob_key, BLOB_DIGESTS},
+        StorageBackend,
+    },
+    utils::digest::{Algorithm, DigestingReader},
+};
+use log::{error, info, warn};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{io::AsyncReadExt, sync::Notify};
+
+/// How often the scrubber looks for blobs that are due for a check.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// The outcome of checking one blob.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub fixity: Fixity,
+    pub detail: Option<String>,
+    pub checked_at: i64,
+}
+
+/// Running totals since startup, for `/metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub runs: AtomicU64,
+    pub ok: AtomicU64,
+    pub mismatched: AtomicU64,
+    pub missing: AtomicU64,
+    pub errors: AtomicU64,
+    pub bytes_read: AtomicU64,
+    /// Unix seconds; 0 until a pass has finished.
+    pub last_finished_at: AtomicI64,
+}
+
+#[derive(Default)]
+struct Reports {
+    current: Option<ScrubReport>,
+    last: Option<ScrubReport>,
+}
+
+/// Re-hashes stored blobs in the background and flags those that no longer match the digests
+/// recorded at ingest.
+///
+/// When each blob was last checked is kept in the catalog, so the schedule survives restarts:
+/// every [`POLL_INTERVAL`] the scrubber checks whatever has gone unchecked for longer than
+/// `fixity.interval_hours`, reading no faster than `fixity.max_bytes_per_sec`.
+pub struct Scrubber {
+    storage: Arc<dyn StorageBackend>,
+    catalog: Catalog,
+    config: SharedConfig,
+    reports: Mutex<Reports>,
+    trigger: Notify,
+    forced: AtomicBool,
+    pub metrics: Metrics,
+}
+
+impl Scrubber {
+    pub fn new(storage: Arc<dyn StorageBackend>, catalog: Catalog, config: SharedConfig) -> Self {
+        Self {
+            storage,
+            catalog,
+            config,
+            reports: Mutex::default(),
+            trigger: Notify::new(),
+            forced: AtomicBool::new(false),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// The pass in progress, if any, and the last one to finish.
+    pub fn reports(&self) -> (Option<ScrubReport>, Option<ScrubReport>) {
+        let reports = self.reports.lock().unwrap_or_else(PoisonError::into_inner);
+        (reports.current.clone(), reports.last.clone())
+    }
+
+    /// Start a pass over every blob as soon as the current one (if any) finishes.
+    pub fn trigger(&self) {
+        self.forced.store(true, Ordering::SeqCst);
+        self.trigger.notify_one();
+    }
+
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                let forced = self.forced.swap(false, Ordering::SeqCst);
+                if forced || self.config.current().fixity.enabled {
+                    if let Err(err) = self.run(forced).await {
+                        warn!("Fixity check pass failed: {:#}", err);
+                    }
+                    self.update(|reports| {
+                        if let Some(report) = reports.current.take() {
+                            reports.last = Some(report);
+                        }
+                    });
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    _ = self.trigger.notified() => {}
+                }
+            }
+        });
+    }
+
+    /// Check every blob that is due, or all of them if `forced`.
+    async fn run(&self, forced: bool) -> anyhow::Result<()> {
+        let config = self.config.current().fixity.clone();
+        let now = catalog::unix_seconds(SystemTime::now());
+        let cutoff = match forced {
+            true => i64::MAX,
+            false => now.saturating_sub(config.interval_hours as i64 * 60 * 60),
+        };
+
+        let due = self.catalog.blobs_due(cutoff).await?;
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        info!("Fixity check of {} blob(s) started", due.len());
+        self.update(|reports| {
+            reports.current = Some(ScrubReport {
+                started_at: now,
+                finished_at: None,
+                forced,
+                due: due.len() as u64,
+                ok: 0,
+                mismatched: 0,
+                missing: 0,
+                errors: 0,
+                bytes_read: 0,
+            })
+        });
+
+        let mut throttle = Throttle::new(config.max_bytes_per_sec);
+        for blob in &due {
+            let bytes_before = throttle.bytes;
+            let result = self.check(blob, &mut throttle).await;
+            let bytes_read = throttle.bytes - bytes_before;
+            self.metrics
+                .bytes_read
+                .fetch_add(bytes_read, Ordering::Relaxed);
+
+            let fixity = match result {
+                Ok(check) => self.record(blob, check).await?,
+                Err(err) => {
+                    warn!("Fixity check of blob {} failed: {:#}", blob.sha256, err);
+                    self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            };
+
+            self.update(|reports| {
+                if let Some(report) = &mut reports.current {
+                    report.bytes_read += bytes_read;
+                    match fixity {
+                        Some(Fixity::Ok) => report.ok += 1,
+                        Some(Fixity::Mismatch) => report.mismatched += 1,
+                        Some(Fixity::Missing) => report.missing += 1,
+                        None => report.errors += 1,
+                    }
+                }
+            });
+        }
+
+        let finished_at = catalog::unix_seconds(SystemTime::now());
+        self.metrics.runs.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .last_finished_at
+            .store(finished_at, Ordering::Relaxed);
+        self.update(|reports| {
+            if let Some(report) = &mut reports.current {
+                report.finished_at = Some(finished_at);
+                info!(
+                    "Fixity check finished: {} ok, {} mismatched, {} missing, {} failed",
+                    report.ok, report.mismatched, report.missing, report.errors
+                );
+            }
+        });
+        Ok(())
+    }
+
+    /// Check one blob right away, without the rate limit, and record the result.
+    pub async fn verify(&self, blob: &BlobRecord) -> anyhow::Result<Check> {
+        let mut throttle = Throttle::new(0);
+        let check = self.check(blob, &mut throttle).await?;
+        self.metrics
+            .bytes_read
+            .fetch_add(throttle.bytes, Ordering::Relaxed);
+        self.record(blob, check.clone()).await?;
+        Ok(check)
+    }
+
+    /// Re-read `blob` and compare it with what was recorded at ingest.
+    async fn check(&self, blob: &BlobRecord, throttle: &mut Throttle) -> anyhow::Result<Check> {
+        let key = blob_key(&blob.sha256);
+        let damaged = |fixity, detail: String| Check {
+            fixity,
+            detail: Some(detail),
+            checked_at: catalog::unix_seconds(SystemTime::now()),
+        };
+
+        let Some(meta) = self.storage.stat(&key).await? else {
+            return Ok(damaged(Fixity::Missing, format!("{} is gone", key)));
+        };
+        if meta.size != blob.size {
+            return Ok(damaged(
+                Fixity::Mismatch,
+                format!("size is {} bytes, expected {}", meta.size, blob.size),
+            ));
+        }
+
+        let reader = self.storage.get_range_stream(&key, None).await?;
+        let mut reader = DigestingReader::new(reader, BLOB_DIGESTS)?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            throttle.consume(read).await;
+        }
+        let digests = reader.finish()?;
+
+        let sha256 = digests.require_hex(Algorithm::Sha256)?;
+        if sha256 != blob.sha256 {
+            return Ok(damaged(Fixity::Mismatch, format!("sha-256 is {}", sha256)));
+        }
+        let md5 = digests.require_hex(Algorithm::Md5)?;
+        if blob.md5.as_ref().is_some_and(|expected| *expected != md5) {
+            return Ok(damaged(Fixity::Mismatch, format!("md5 is {}", md5)));
+        }
+
+        Ok(Check {
+            fixity: Fixity::Ok,
+            detail: None,
+            checked_at: catalog::unix_seconds(SystemTime::now()),
+        })
+    }
+
+    /// Store the result of a check in the catalog. Returns `None` if the blob was deleted
+    /// meanwhile, making the result meaningless.
+    async fn record(&self, blob: &BlobRecord, check: Check) -> anyhow::Result<Option<Fixity>> {
+        let recorded = self
+            .catalog
+            .record_fixity(
+                &blob.sha256,
+                check.fixity,
+                check.detail.clone(),
+                check.checked_at,
+            )
+            .await?;
+        if !recorded {
+            return Ok(None);
+        }
+
+        let counter = match check.fixity {
+            Fixity::Ok => &self.metrics.ok,
+            Fixity::Mismatch => &self.metrics.mismatched,
+            Fixity::Missing => &self.metrics.missing,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(detail) = &check.detail {
+            error!(
+                "Blob {} failed its fixity check ({}): {}",
+                blob.sha256,
+                check.fixity.as_str(),
+                detail
+            );
+        }
+        Ok(Some(check.fixity))
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Reports)) {
+        f(&mut self.reports.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+/// Paces reads to at most `rate` bytes per second on average; 0 means no limit.
+struct Throttle {
+    rate: u64,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            started: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    async fn consume(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        if self.rate > 0 {
+            let due = Duration::from_secs_f64(self.bytes as f64 / self.rate as f64);
+            tokio::time::sleep_until((self.started + due).into()).await;
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{
+    catalog::{ArchivePosition, ArchiveQuery, SortKey},
+    error::{AppError, AppResult},
+    models::{self, ArchiveSort, SortOrder},
+    state::AppState,
+};
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    http::StatusCode,
+    Json,
+};
+use openssl::base64;
+use serde::Deserialize;
+
+/// Files listed when a request doesn't say how many.
+const DEFAULT_LIMIT: u32 = 100;
+
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveParams {
+    #[serde(default)]
+    pub sort: ArchiveSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// e.g. `video/mp4`, or `video/*` for any video.
+    pub content_type: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Unix seconds; uploaded at or after.
+    pub uploaded_since: Option<i64>,
+    /// Unix seconds; uploaded before.
+    pub uploaded_before: Option<i64>,
+    pub tag: Option<String>,
+}
+
+/// What a cursor holds: the sort it was made for, and the last file of its page.
+type Cursor = (ArchiveSort, SortOrder, SortKey, i64);
+
+/// Cursors are base64url JSON, so they pass through a query string as they are.
+fn encode_cursor(sort: ArchiveSort, order: SortOrder, position: ArchivePosition) -> String {
+    let cursor: Cursor = (sort, order, position.key, position.id);
+    let json = serde_json::to_vec(&cursor).unwrap_or_default();
+    base64::encode_block(&json)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn decode_cursor(cursor: &str, sort: ArchiveSort, order: SortOrder) -> AppResult<ArchivePosition> {
+    let invalid = || AppError::BadRequest(String::from("invalid cursor"));
+    let mut encoded = cursor.replace('-', "+").replace('_', "/");
+    while !encoded.len().is_multiple_of(4) {
+        encoded.push('=');
+    }
+    let json = base64::decode_block(&encoded).map_err(|_| invalid())?;
+    let (cursor_sort, cursor_order, key, id): Cursor =
+        serde_json::from_slice(&json).map_err(|_| invalid())?;
+    if (cursor_sort, cursor_order) != (sort, order) {
+        return Err(AppError::BadRequest(String::from(
+            "cursor is for a different sort or order",
+        )));
+    }
+    Ok(ArchivePosition { key, id })
+}
+
+/// A page of archived files, in the order asked for and optionally filtered. Follow
+/// `next_cursor`, with the same parameters, for the next page.
+pub async fn archive_handler(
+    State(state): State<AppState>,
+    params: Result<Query<ArchiveParams>, QueryRejection>,
+) -> AppResult<(StatusCode, Json<models::ArchiveResponse>)> {
+    let Query(params) = params?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    let after = params
+        .cursor
+        .as_deref()
+        .map(|cursor| decode_cursor(cursor, params.sort, params.order))
+        .transpose()?;
+
+    let page = state
+        .catalog
+        .list_page(ArchiveQuery {
+            sort: params.sort,
+            order: params.order,
+            after,
+            limit,
+            content_type: params.content_type,
+            min_size: params.min_size,
+            max_size: params.max_size,
+            uploaded_since: params.uploaded_since,
+            uploaded_before: params.uploaded_before,
+            tag: params.tag,
+        })
+        .await?;
+    let next_cursor = page
+        .next
+        .map(|position| encode_cursor(params.sort, params.order, position));
+    Ok((
+        StatusCode::OK,
+        Json(models::ArchiveResponse {
+            files: page.entries,
+            total: page.total,
+            next_cursor,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        for (sort, order, key) in [
+            (
+                ArchiveSort::Name,
+                SortOrder::Asc,
+                SortKey::Text(String::from("a?b/c+d.mp4")),
+            ),
+            (
+                ArchiveSort::Name,
+                SortOrder::Desc,
+                SortKey::Text(String::from("été.mkv")),
+            ),
+            (
+                ArchiveSort::Size,
+                SortOrder::Asc,
+                SortKey::Integer(i64::MAX),
+            ),
+            (
+                ArchiveSort::UploadedAt,
+                SortOrder::Desc,
+                SortKey::Integer(1_700_000_000),
+            ),
+            (ArchiveSort::Duration, SortOrder::Asc, SortKey::Integer(-1)),
+        ] {
+            let position = ArchivePosition { key, id: 42 };
+            let cursor = encode_cursor(sort, order, position.clone());
+            // Safe in a query string as it is
+            assert!(
+                cursor
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'),
+                "{:?}",
+                cursor
+            );
+            assert_eq!(
+                decode_cursor(&cursor, sort, order).unwrap(),
+                position,
+                "{:?}",
+                cursor
+            );
+        }
+    }
+
+    #[test]
+    fn cursors_only_fit_their_sort() {
+        let position = ArchivePosition {
+            key: SortKey::Integer(1024),
+            id: 7,
+        };
+        let cursor = encode_cursor(ArchiveSort::Size, SortOrder::Asc, position);
+        for (sort, order) in [
+            (ArchiveSort::Size, SortOrder::Desc),
+            (ArchiveSort::Name, SortOrder::Asc),
+            (ArchiveSort::Duration, SortOrder::Asc),
+        ] {
+            assert!(
+                matches!(
+                    decode_cursor(&cursor, sort, order),
+                    Err(AppError::BadRequest(message)) if message.contains("different sort")
+                ),
+                "{:?} {:?}",
+                sort,
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        let encode = |json: &str| {
+            base64::encode_block(json.as_bytes())
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_")
+        };
+        for cursor in [
+            String::new(),
+            String::from("!!!!"),
+            String::from("a"),
+            encode("not json"),
+            encode("{}"),
+            encode("[\"size\",\"asc\",1024]"),
+            encode("[\"size\",\"asc\",1024,\"7\"]"),
+            encode("[\"size\",\"sideways\",1024,7]"),
+            encode("[\"colour\",\"asc\",1024,7]"),
+            encode("[\"size\",\"asc\",1024,7,8]"),
+        ] {
+            assert!(
+                matches!(
+                    decode_cursor(&cursor, ArchiveSort::Size, SortOrder::Asc),
+                    Err(AppError::BadRequest(message)) if message == "invalid cursor"
+                ),
+                "{:?}",
+                cursor
+            );
+        }
+        // The same thing, well-formed
+        assert!(decode_cursor(
+            &encode("[\"size\",\"asc\",1024,7]"),
+            ArchiveSort::Size,
+            SortOrder::Asc
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{
+    catalog,
+    error::{AppError, AppResult},
+    models::KeyPath,
+    state::AppState,
+    thumbnails,
+};
+use axum::{extract::State, http::StatusCode};
+use log::{info, warn};
+
+// New function to delete a file
+pub async fn delete_file_handler(
+    State(state): State<AppState>,
+    KeyPath(key): KeyPath,
+) -> AppResult<(StatusCode, String)> {
+    let file_name = key.as_str();
+
+    // Releasing blobs must not interleave with an upload that is about to reuse one
+    let _commit = state.commit_lock.lock().await;
+    let entry = state.catalog.get_by_name(file_name).await?;
+    if let Some(entry) = &entry {
+        state.jobs.cancel_video(entry.id).await?;
+    }
+    let Some(released) = state.catalog.delete_by_name(file_name).await? else {
+        return Err(AppError::not_found("file"));
+    };
+
+    // Contents shared with other names (or kept versions) stay
+    catalog::delete_blobs(state.storage.as_ref(), released).await;
+    if let Some(entry) = entry {
+        if let Err(err) = thumbnails::remove(state.storage.as_ref(), entry.id).await {
+            warn!(
+                "Failed to delete the thumbnails of {}: {:#}",
+                file_name, err
+            );
+        }
+    }
+    info!("File {} deleted successfully", file_name);
+    Ok((StatusCode::OK, "File deleted successfully".to_string()))
+}
@@ -0,0 +1,66 @@
+use crate::{
+    catalog::BlobRecord,
+    error::{AppError, AppResult},
+    models::{FixityResponse, KeyPath, VerifyResponse},
+    state::AppState,
+};
+use axum::{extract::State, http::StatusCode, Json};
+
+async fn fixity_response(state: &AppState) -> AppResult<FixityResponse> {
+    let (current, last) = state.fixity.reports();
+    Ok(FixityResponse {
+        running: current.is_some(),
+        current,
+        last,
+        flagged: state.catalog.flagged_blobs().await?,
+    })
+}
+
+/// Progress of the scrubber and every blob it has flagged.
+pub async fn fixity_status_handler(
+    State(state): State<AppState>,
+) -> AppResult<Json<FixityResponse>> {
+    Ok(Json(fixity_response(&state).await?))
+}
+
+/// Check every blob now, whatever the schedule says.
+pub async fn fixity_run_handler(
+    State(state): State<AppState>,
+) -> AppResult<(StatusCode, Json<FixityResponse>)> {
+    state.fixity.trigger();
+    Ok((StatusCode::ACCEPTED, Json(fixity_response(&state).await?)))
+}
+
+/// Re-hash one file's contents right away.
+pub async fn verify_file_handler(
+    State(state): State<AppState>,
+    KeyPath(key): KeyPath,
+) -> AppResult<Json<VerifyResponse>> {
+    let entry = state
+        .catalog
+        .get_by_name(key.as_str())
+        .await?
+        .ok_or_else(|| AppError::not_found("file"))?;
+    let Some(sha256) = entry.sha256 else {
+        return Err(AppError::Conflict(String::from(
+            "file has not been moved into blob storage yet",
+        )));
+    };
+
+    let check = state
+        .fixity
+        .verify(&BlobRecord {
+            sha256: sha256.clone(),
+            md5: entry.md5,
+            size: entry.size,
+        })
+        .await?;
+
+    Ok(Json(VerifyResponse {
+        file_name: entry.file_name,
+        sha256,
+        fixity: check.fixity,
+        detail: check.detail,
+        checked_at: check.checked_at,
+    }))
+}
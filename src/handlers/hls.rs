@@ -0,0 +1,73 @@
+use crate::{
+    dash,
+    error::{AppError, AppResult},
+    hls::{self, MASTER_PLAYLIST},
+    state::AppState,
+};
+use axum::{
+    extract::{rejection::PathRejection, Path, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+
+/// Files other than the master playlist are addressed by the contents they were made from, so
+/// they never change.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+async fn find_contents(state: &AppState, id: i64) -> AppResult<String> {
+    state
+        .catalog
+        .get_by_id(id)
+        .await?
+        .and_then(|entry| entry.sha256)
+        .ok_or_else(|| AppError::not_found("video"))
+}
+
+/// Master playlist of video `id`, listing a rendition per rung of the ladder.
+pub async fn hls_master_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Path(id) = id?;
+    let sha256 = find_contents(&state, id).await?;
+    let key = hls::object_key(&sha256, MASTER_PLAYLIST);
+    let content_type = hls::content_type(MASTER_PLAYLIST).unwrap_or_default();
+    super::serve_derived(state, key, content_type, &headers, "HLS package").await
+}
+
+/// DASH manifest of video `id`, for packages with fMP4 segments. It shares them with HLS.
+pub async fn dash_manifest_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Path(id) = id?;
+    let sha256 = find_contents(&state, id).await?;
+    let key = hls::object_key(&sha256, dash::MANIFEST);
+    super::serve_derived(state, key, dash::CONTENT_TYPE, &headers, "DASH manifest").await
+}
+
+/// A rendition playlist, init segment or media segment of video `id`, at the path the master
+/// playlist or DASH manifest gives it.
+pub async fn hls_file_handler(
+    State(state): State<AppState>,
+    params: Result<Path<(i64, String, String)>, PathRejection>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Path((id, contents, file_name)) = params?;
+    // Packages of contents the video had before are not served under it
+    if find_contents(&state, id).await? != contents {
+        return Err(AppError::not_found("HLS package"));
+    }
+    let content_type = hls::content_type(&file_name)
+        .filter(|_| file_name != MASTER_PLAYLIST && file_name != dash::MANIFEST)
+        .ok_or_else(|| AppError::not_found("HLS file"))?;
+
+    let key = hls::object_key(&contents, &file_name);
+    let mut response = super::serve_derived(state, key, content_type, &headers, "HLS file").await?;
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    Ok(response)
+}
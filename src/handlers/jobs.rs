@@ -0,0 +1,139 @@
+use crate::{
+    catalog::JobFilter,
+    error::{AppError, AppResult},
+    jobs::DEFAULT_PRIORITY,
+    models::{Job, JobKind, JobState, JobsResponse},
+    state::AppState,
+};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    Json,
+};
+use serde::Deserialize;
+
+/// Jobs listed when a request doesn't say how many.
+const DEFAULT_LIMIT: u32 = 100;
+
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct JobsParams {
+    pub kind: Option<JobKind>,
+    pub state: Option<JobState>,
+    pub video_id: Option<i64>,
+    pub limit: Option<u32>,
+}
+
+/// What to queue, and how urgently; uploads queue at the default priority.
+#[derive(Debug, Deserialize)]
+pub struct CreateJob {
+    pub kind: JobKind,
+    pub video_id: i64,
+    pub priority: Option<i64>,
+}
+
+/// Jobs, newest first, optionally only those of one kind, state or video.
+pub async fn jobs_handler(
+    State(state): State<AppState>,
+    params: Result<Query<JobsParams>, QueryRejection>,
+) -> AppResult<Json<JobsResponse>> {
+    let Query(params) = params?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+
+    let jobs = state
+        .catalog
+        .list_jobs(JobFilter {
+            kind: params.kind,
+            state: params.state,
+            video_id: params.video_id,
+            limit,
+        })
+        .await?;
+    Ok(Json(JobsResponse { jobs }))
+}
+
+/// One job, with its log.
+pub async fn job_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> AppResult<Json<Job>> {
+    let Path(id) = id?;
+    let job = state
+        .catalog
+        .get_job(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("job"))?;
+    Ok(Json(job))
+}
+
+/// Queue a job by hand, e.g. to run one that failed again.
+pub async fn job_create_handler(
+    State(state): State<AppState>,
+    body: Result<Json<CreateJob>, JsonRejection>,
+) -> AppResult<(StatusCode, Json<Job>)> {
+    let Json(request) = body?;
+    let entry = state
+        .catalog
+        .get_by_id(request.video_id)
+        .await?
+        .ok_or_else(|| AppError::not_found("video"))?;
+    if entry.sha256.is_none() {
+        return Err(AppError::Conflict(String::from(
+            "file has not been moved into blob storage yet",
+        )));
+    }
+
+    let priority = request.priority.unwrap_or(DEFAULT_PRIORITY);
+    let id = state
+        .jobs
+        .enqueue(request.kind, &entry, priority)
+        .await?
+        .ok_or_else(|| {
+            AppError::Conflict(format!(
+                "a {} job for this video is already queued or running",
+                request.kind.as_str()
+            ))
+        })?;
+    let job = state
+        .catalog
+        .get_job(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("job"))?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// Cancel a job, stopping it if it is running.
+pub async fn job_cancel_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> AppResult<Json<Job>> {
+    let Path(id) = id?;
+    let job = state
+        .catalog
+        .get_job(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("job"))?;
+    if job.state.is_finished() {
+        return Err(AppError::Conflict(format!(
+            "job has already {}",
+            job.state.as_str()
+        )));
+    }
+
+    let job = state
+        .jobs
+        .cancel(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("job"))?;
+    Ok(Json(job))
+}
@@ -0,0 +1,30 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::{KeyPath, MetadataResponse},
+    state::AppState,
+};
+use axum::{extract::State, Json};
+
+/// Everything probing found about a file, including each of its tracks.
+pub async fn metadata_handler(
+    State(state): State<AppState>,
+    KeyPath(key): KeyPath,
+) -> AppResult<Json<MetadataResponse>> {
+    let entry = state
+        .catalog
+        .get_by_name(key.as_str())
+        .await?
+        .ok_or_else(|| AppError::not_found("file"))?;
+    let (media, probed_at) = state
+        .catalog
+        .get_media(key.as_str())
+        .await?
+        .ok_or_else(|| AppError::not_found("file"))?;
+
+    Ok(Json(MetadataResponse {
+        file_name: entry.file_name,
+        sha256: entry.sha256,
+        probed_at,
+        media,
+    }))
+}
@@ -0,0 +1,106 @@
+use crate::{error::AppResult, state::AppState};
+use axum::{
+    extract::State,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use std::{fmt::Write, sync::atomic::Ordering};
+
+/// Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+fn describe(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub async fn metrics_handler(State(state): State<AppState>) -> AppResult<Response> {
+    let metrics = &state.fixity.metrics;
+    let mut out = String::new();
+
+    describe(
+        &mut out,
+        "archiver_fixity_checks_total",
+        "counter",
+        "Blob fixity checks since startup, by result.",
+    );
+    for (result, counter) in [
+        ("ok", &metrics.ok),
+        ("mismatch", &metrics.mismatched),
+        ("missing", &metrics.missing),
+        ("error", &metrics.errors),
+    ] {
+        let _ = writeln!(
+            out,
+            "archiver_fixity_checks_total{{result=\"{}\"}} {}",
+            result,
+            counter.load(Ordering::Relaxed)
+        );
+    }
+
+    describe(
+        &mut out,
+        "archiver_fixity_bytes_read_total",
+        "counter",
+        "Bytes re-read by fixity checks since startup.",
+    );
+    let _ = writeln!(
+        out,
+        "archiver_fixity_bytes_read_total {}",
+        metrics.bytes_read.load(Ordering::Relaxed)
+    );
+
+    describe(
+        &mut out,
+        "archiver_fixity_runs_total",
+        "counter",
+        "Scrubber passes completed since startup.",
+    );
+    let _ = writeln!(
+        out,
+        "archiver_fixity_runs_total {}",
+        metrics.runs.load(Ordering::Relaxed)
+    );
+
+    describe(
+        &mut out,
+        "archiver_fixity_last_run_timestamp_seconds",
+        "gauge",
+        "When the last scrubber pass finished, 0 if none has.",
+    );
+    let _ = writeln!(
+        out,
+        "archiver_fixity_last_run_timestamp_seconds {}",
+        metrics.last_finished_at.load(Ordering::Relaxed)
+    );
+
+    describe(
+        &mut out,
+        "archiver_blobs",
+        "gauge",
+        "Stored blobs, by the result of their last fixity check.",
+    );
+    for (fixity, count) in state.catalog.fixity_counts().await? {
+        let _ = writeln!(out, "archiver_blobs{{fixity=\"{}\"}} {}", fixity, count);
+    }
+
+    describe(
+        &mut out,
+        "archiver_jobs",
+        "gauge",
+        "Background jobs remembered, by kind and state.",
+    );
+    for (kind, job_state, count) in state.catalog.job_counts().await? {
+        let _ = writeln!(
+            out,
+            "archiver_jobs{{kind=\"{}\",state=\"{}\"}} {}",
+            kind, job_state, count
+        );
+    }
+
+    Ok((
+        [(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE))],
+        out,
+    )
+        .into_response())
+}
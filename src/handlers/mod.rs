@@ -0,0 +1,58 @@
+pub mod archive;
+pub mod delete;
+pub mod fixity;
+pub mod hls;
+pub mod jobs;
+pub mod metadata;
+pub mod metrics;
+pub mod search;
+pub mod storyboard;
+pub mod stream;
+pub mod subtitles;
+pub mod thumbnail;
+pub mod tus;
+pub mod upload;
+pub mod videos;
+
+use crate::{
+    error::{AppError, AppResult},
+    state::AppState,
+    utils::{conditional::Validators, range},
+};
+use axum::{
+    body::StreamBody,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+pub async fn fallback_func() -> AppError {
+    AppError::NotFound(String::from("page not found"))
+}
+
+/// The object at `key`, made from a video rather than uploaded, with validators so clients can
+/// cache it. `what` names it when it isn't there.
+async fn serve_derived(
+    state: AppState,
+    key: String,
+    content_type: &'static str,
+    headers: &HeaderMap,
+    what: &str,
+) -> AppResult<Response> {
+    let meta = state
+        .storage
+        .stat(&key)
+        .await?
+        .ok_or_else(|| AppError::not_found(what))?;
+
+    let validators = Validators::from_meta(&meta);
+    let mut response_headers = HeaderMap::new();
+    validators.insert_headers(&mut response_headers);
+    if validators.is_not_modified(headers) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(meta.size));
+    let body = StreamBody::new(range::object_range_stream(state.storage, key, None));
+    Ok((StatusCode::OK, response_headers, body).into_response())
+}
@@ -0,0 +1,79 @@
+use crate::{
+    catalog::SearchQuery,
+    error::{AppError, AppResult},
+    models::SearchResponse,
+    state::AppState,
+};
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    Json,
+};
+use serde::Deserialize;
+
+/// Results returned when a request doesn't say how many.
+const DEFAULT_LIMIT: u32 = 20;
+
+const MAX_LIMIT: u32 = 100;
+
+/// Words of a query beyond this are ignored.
+const MAX_TERMS: usize = 16;
+
+/// Subtitle cues shown for each video when a request doesn't say how many.
+const DEFAULT_HITS_PER_VIDEO: u32 = 5;
+
+const MAX_HITS_PER_VIDEO: u32 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    /// Words to find; the last may be partial, as each matches the start of a word.
+    pub q: String,
+    pub limit: Option<u32>,
+    pub hits_per_video: Option<u32>,
+}
+
+/// Videos whose name, title, description, tags or subtitles contain every word of `q`, best
+/// first, with the matches highlighted and subtitle matches timed.
+pub async fn search_handler(
+    State(state): State<AppState>,
+    params: Result<Query<SearchParams>, QueryRejection>,
+) -> AppResult<Json<SearchResponse>> {
+    let Query(params) = params?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    let hits_per_video = params.hits_per_video.unwrap_or(DEFAULT_HITS_PER_VIDEO);
+    if hits_per_video > MAX_HITS_PER_VIDEO {
+        return Err(AppError::BadRequest(format!(
+            "hits_per_video must be at most {}",
+            MAX_HITS_PER_VIDEO
+        )));
+    }
+
+    // The index splits text the same way, on anything that isn't a letter or digit
+    let terms: Vec<String> = params
+        .q
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .take(MAX_TERMS)
+        .map(str::to_string)
+        .collect();
+    if terms.is_empty() {
+        return Err(AppError::BadRequest(String::from(
+            "q must contain a word to search for",
+        )));
+    }
+
+    let results = state
+        .catalog
+        .search(SearchQuery {
+            terms,
+            limit,
+            hits_per_video,
+        })
+        .await?;
+    Ok(Json(SearchResponse { results }))
+}
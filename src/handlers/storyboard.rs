@@ -0,0 +1,55 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::{KeyPath, StorageKey},
+    state::AppState,
+    storyboards::{sheet_key, track_key},
+};
+use axum::{
+    extract::{rejection::PathRejection, Path, State},
+    http::HeaderMap,
+    response::Response,
+};
+
+async fn find_contents(state: &AppState, key: &StorageKey) -> AppResult<String> {
+    state
+        .catalog
+        .get_by_name(key.as_str())
+        .await?
+        .and_then(|entry| entry.sha256)
+        .ok_or_else(|| AppError::not_found("file"))
+}
+
+/// WebVTT track of scrubbing previews for a video, with cues pointing into its sprite sheets.
+pub async fn storyboard_track_handler(
+    State(state): State<AppState>,
+    KeyPath(key): KeyPath,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let sha256 = find_contents(&state, &key).await?;
+    super::serve_derived(
+        state,
+        track_key(&sha256),
+        "text/vtt",
+        &headers,
+        "storyboard",
+    )
+    .await
+}
+
+/// A sprite sheet of a video's storyboard, as referenced by its track: `<sheet>.jpg`.
+pub async fn storyboard_sheet_handler(
+    State(state): State<AppState>,
+    params: Result<Path<(String, String)>, PathRejection>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Path((file_name, sheet)) = params?;
+    let key = StorageKey::parse(&file_name)?;
+    let sheet: usize = sheet
+        .strip_suffix(".jpg")
+        .and_then(|sheet| sheet.parse().ok())
+        .ok_or_else(|| AppError::not_found("sprite sheet"))?;
+
+    let sha256 = find_contents(&state, &key).await?;
+    let key = sheet_key(&sha256, sheet);
+    super::serve_derived(state, key, "image/jpeg", &headers, "sprite sheet").await
+}
@@ -0,0 +1,146 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::KeyPath,
+    state::AppState,
+    storage::blobs::blob_key,
+    utils::{
+        conditional::Validators,
+        digest, mime,
+        range::{self, RangeRequest},
+    },
+};
+use axum::{
+    body::StreamBody,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+pub async fn video_stream_handler(
+    State(state): State<AppState>,
+    method: Method,
+    KeyPath(key): KeyPath,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    // Check if the file exists
+    let entry = state
+        .catalog
+        .get_by_name(key.as_str())
+        .await?
+        .ok_or_else(|| AppError::not_found("file"))?;
+    let object_key = entry
+        .sha256
+        .as_deref()
+        .map_or_else(|| entry.file_name.clone(), blob_key);
+    let meta = state
+        .storage
+        .stat(&object_key)
+        .await?
+        .ok_or_else(|| AppError::not_found("file"))?;
+    let file_size = meta.size;
+    let content_type = entry.content_type;
+    let validators = Validators::from_meta(&meta);
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    validators.insert_headers(&mut response_headers);
+    // Digest of the whole file, whatever range is sent, so clients can verify what they fetched
+    if let Some(value) = entry
+        .sha256
+        .as_deref()
+        .and_then(digest::repr_digest)
+        .and_then(|value| HeaderValue::from_str(&value).ok())
+    {
+        response_headers.insert(digest::REPR_DIGEST, value);
+    }
+
+    if validators.is_not_modified(&headers) {
+        return Ok((StatusCode::NOT_MODIFIED, response_headers).into_response());
+    }
+
+    // HEAD gets the headers of a full GET without touching the file contents
+    if method == Method::HEAD {
+        response_headers.insert(header::CONTENT_TYPE, content_type_value(&content_type));
+        response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(file_size));
+        return Ok((StatusCode::OK, response_headers).into_response());
+    }
+
+    let range_request = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| validators.if_range_matches(&headers))
+        .map_or(RangeRequest::Full, |value| range::parse(value, file_size));
+
+    match range_request {
+        RangeRequest::Full => {
+            // Stream the whole file in chunks
+            let body = StreamBody::new(range::object_range_stream(
+                state.storage,
+                object_key.clone(),
+                None,
+            ));
+
+            response_headers.insert(header::CONTENT_TYPE, content_type_value(&content_type));
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(file_size));
+
+            Ok((StatusCode::OK, response_headers, body).into_response())
+        }
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            let body = StreamBody::new(range::object_range_stream(
+                state.storage,
+                object_key.clone(),
+                Some(range),
+            ));
+
+            response_headers.insert(header::CONTENT_TYPE, content_type_value(&content_type));
+            response_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(range.len()));
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&range.content_range(file_size))
+                    .map_err(anyhow::Error::from)?,
+            );
+
+            Ok((StatusCode::PARTIAL_CONTENT, response_headers, body).into_response())
+        }
+        RangeRequest::Partial(ranges) => {
+            let multipart = range::multipart_byteranges(
+                state.storage,
+                &object_key,
+                &ranges,
+                &content_type,
+                file_size,
+            );
+
+            response_headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_str(&multipart.content_type).map_err(anyhow::Error::from)?,
+            );
+            response_headers.insert(
+                header::CONTENT_LENGTH,
+                HeaderValue::from(multipart.content_length),
+            );
+
+            Ok((
+                StatusCode::PARTIAL_CONTENT,
+                response_headers,
+                multipart.body,
+            )
+                .into_response())
+        }
+        RangeRequest::Unsatisfiable => {
+            response_headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", file_size))
+                    .map_err(anyhow::Error::from)?,
+            );
+
+            Ok((StatusCode::RANGE_NOT_SATISFIABLE, response_headers).into_response())
+        }
+    }
+}
+
+fn content_type_value(content_type: &str) -> HeaderValue {
+    HeaderValue::from_str(content_type)
+        .unwrap_or_else(|_| HeaderValue::from_static(mime::OCTET_STREAM))
+}
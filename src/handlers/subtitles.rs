@@ -0,0 +1,80 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::ArchiveEntry,
+    state::AppState,
+    subtitles,
+};
+use axum::{
+    body::Bytes,
+    extract::{
+        rejection::{BytesRejection, PathRejection},
+        Path, State,
+    },
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+
+fn check_language(language: &str) -> AppResult<()> {
+    match subtitles::valid_language(language) {
+        true => Ok(()),
+        false => Err(AppError::BadRequest(String::from(
+            "language must be a BCP 47 tag, e.g. en or pt-BR",
+        ))),
+    }
+}
+
+/// The `language` subtitles of video `id`, as WebVTT.
+pub async fn subtitles_handler(
+    State(state): State<AppState>,
+    params: Result<Path<(i64, String)>, PathRejection>,
+) -> AppResult<impl IntoResponse> {
+    let Path((id, language)) = params?;
+    check_language(&language)?;
+    let cues = state.catalog.get_subtitles(id, &language).await?;
+    if cues.is_empty() {
+        return Err(AppError::not_found("subtitles"));
+    }
+    Ok((
+        [(header::CONTENT_TYPE, "text/vtt; charset=utf-8")],
+        subtitles::to_webvtt(&cues),
+    ))
+}
+
+/// Attach the WebVTT or SubRip track in the request body as the `language` subtitles of video
+/// `id`, replacing any it had, and index it for search.
+pub async fn subtitles_upload_handler(
+    State(state): State<AppState>,
+    params: Result<Path<(i64, String)>, PathRejection>,
+    body: Result<Bytes, BytesRejection>,
+) -> AppResult<Json<ArchiveEntry>> {
+    let Path((id, language)) = params?;
+    let body = body?;
+    check_language(&language)?;
+    let track = std::str::from_utf8(&body)
+        .map_err(|_| AppError::BadRequest(String::from("subtitles must be UTF-8")))?;
+    let cues = subtitles::parse(track).map_err(AppError::BadRequest)?;
+
+    if !state.catalog.set_subtitles(id, &language, cues).await? {
+        return Err(AppError::not_found("video"));
+    }
+    let entry = state
+        .catalog
+        .get_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("video"))?;
+    Ok(Json(entry))
+}
+
+/// Detach the `language` subtitles of video `id`.
+pub async fn subtitles_delete_handler(
+    State(state): State<AppState>,
+    params: Result<Path<(i64, String)>, PathRejection>,
+) -> AppResult<StatusCode> {
+    let Path((id, language)) = params?;
+    check_language(&language)?;
+    match state.catalog.delete_subtitles(id, &language).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(AppError::not_found("subtitles")),
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::{ArchiveEntry, ThumbnailFormat},
+    state::AppState,
+    thumbnails::{self, thumbnail_key},
+};
+use axum::{
+    body::Bytes,
+    extract::{
+        rejection::{BytesRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::{header, HeaderMap},
+    response::Response,
+    Json,
+};
+use serde::Deserialize;
+
+/// Largest custom poster accepted, in bytes.
+pub const MAX_POSTER_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailParams {
+    /// Width in pixels, one of `thumbnails.sizes`; the largest when absent.
+    pub size: Option<u32>,
+    /// Chosen from `Accept` when absent.
+    pub format: Option<ThumbnailFormat>,
+}
+
+/// WebP for clients that say they take it, JPEG for everyone else.
+fn negotiate(headers: &HeaderMap) -> ThumbnailFormat {
+    let accepts_webp = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_range| media_range.split(';').next().map(str::trim) == Some("image/webp"));
+    if accepts_webp {
+        ThumbnailFormat::Webp
+    } else {
+        ThumbnailFormat::Jpeg
+    }
+}
+
+async fn find_video(state: &AppState, id: i64) -> AppResult<ArchiveEntry> {
+    state
+        .catalog
+        .get_by_id(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("video"))
+}
+
+/// The poster of video `id`, scaled to the requested width.
+pub async fn thumbnail_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    params: Result<Query<ThumbnailParams>, QueryRejection>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let Path(id) = id?;
+    let Query(params) = params?;
+    let sizes = state.config.current().thumbnails.sizes.clone();
+    let size = params
+        .size
+        .or_else(|| sizes.iter().max().copied())
+        .unwrap_or_default();
+    if !sizes.contains(&size) {
+        let sizes: Vec<String> = sizes.iter().map(u32::to_string).collect();
+        return Err(AppError::BadRequest(format!(
+            "size must be one of {}",
+            sizes.join(", ")
+        )));
+    }
+    let format = params.format.unwrap_or_else(|| negotiate(&headers));
+
+    let entry = find_video(&state, id).await?;
+    if entry.poster.is_none() {
+        return Err(AppError::not_found("thumbnail"));
+    }
+    let key = thumbnail_key(entry.id, size, format);
+    // Vary: Accept is set for every response by the CORS layer
+    super::serve_derived(state, key, format.content_type(), &headers, "thumbnail").await
+}
+
+/// Replace the poster of video `id` with the image in the request body; thumbnails are made
+/// from it and it is kept when the video's contents change.
+pub async fn poster_upload_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    body: Result<Bytes, BytesRejection>,
+) -> AppResult<Json<ArchiveEntry>> {
+    let Path(id) = id?;
+    let body = body?;
+    let entry = find_video(&state, id).await?;
+
+    let updated = thumbnails::set_custom(&state, &entry, &body)
+        .await?
+        // Replaced or deleted while its thumbnails were being made
+        .ok_or_else(|| {
+            AppError::Conflict(String::from("the video changed meanwhile, try again"))
+        })?;
+    Ok(Json(updated))
+}
@@ -0,0 +1,724 @@
+use super::upload::archive_stream;
+use crate::{
+    catalog::{self, ArchiveStatus, TusUpload},
+    error::AppError,
+    models::{check_conflict, ConflictPolicy, StorageKey, StorageKeyError, UploadConflict},
+    state::AppState,
+    tus::{self, TusStore, UploadLock, TUS_EXTENSIONS, TUS_VERSION},
+};
+use axum::{
+    extract::{BodyStream, Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures_util::TryStreamExt;
+use log::{info, warn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tokio_util::io::StreamReader;
+
+pub const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+pub const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+pub const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+pub const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+pub const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+pub const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+pub const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+pub const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+const UPLOAD_DEFER_LENGTH: HeaderName = HeaderName::from_static("upload-defer-length");
+/// Set once an upload has been received in full: `pending` while it is moved into the archive,
+/// then `archived` or `failed`.
+pub const ARCHIVE_STATUS: HeaderName = HeaderName::from_static("archive-status");
+/// Set once an upload has been archived: how a name clash was resolved, if at all.
+pub const UPLOAD_OUTCOME: HeaderName = HeaderName::from_static("upload-outcome");
+/// Set once an upload has been archived: the name it was archived under.
+pub const ARCHIVE_FILE_NAME: HeaderName = HeaderName::from_static("archive-file-name");
+/// Set if an upload couldn't be archived: why.
+pub const ARCHIVE_ERROR: HeaderName = HeaderName::from_static("archive-error");
+
+const OFFSET_OCTET_STREAM: &str = "application/offset+octet-stream";
+
+type TusResult = Result<Response, TusError>;
+
+/// Error responses carry `Tus-Resumable` like every other response.
+pub struct TusError(AppError);
+
+impl IntoResponse for TusError {
+    fn into_response(self) -> Response {
+        let mut response = self.0.into_response();
+        let version_mismatch = response.status() == StatusCode::PRECONDITION_FAILED;
+        let headers = response.headers_mut();
+        headers.extend(tus_headers());
+        if version_mismatch {
+            headers.insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+        }
+        response
+    }
+}
+
+impl From<AppError> for TusError {
+    fn from(err: AppError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<StorageKeyError> for TusError {
+    fn from(err: StorageKeyError) -> Self {
+        Self(err.into())
+    }
+}
+
+impl From<UploadConflict> for TusError {
+    fn from(err: UploadConflict) -> Self {
+        Self(err.into())
+    }
+}
+
+fn tus_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    headers
+}
+
+fn internal_error(err: impl Into<anyhow::Error>) -> TusError {
+    TusError(err.into().into())
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_u64(headers: &HeaderMap, name: HeaderName) -> Result<Option<u64>, TusError> {
+    match header_str(headers, name.clone()) {
+        Some(value) => {
+            value.trim().parse().map(Some).map_err(|_| {
+                AppError::BadRequest(format!("invalid {} header", name.as_str())).into()
+            })
+        }
+        None => Ok(None),
+    }
+}
+
+/// Every request except OPTIONS must speak the protocol version we implement.
+fn check_resumable(headers: &HeaderMap) -> Result<(), TusError> {
+    if header_str(headers, TUS_RESUMABLE) == Some(TUS_VERSION) {
+        return Ok(());
+    }
+    Err(AppError::PreconditionFailed(String::from("unsupported Tus-Resumable version")).into())
+}
+
+fn insert_expires(headers: &mut HeaderMap, expires_at: i64) {
+    let expires = UNIX_EPOCH + Duration::from_secs(expires_at.max(0) as u64);
+    if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(expires)) {
+        headers.insert(UPLOAD_EXPIRES, value);
+    }
+}
+
+fn insert_archive_status(headers: &mut HeaderMap, archive: &ArchiveStatus) {
+    let status = match archive {
+        ArchiveStatus::Pending => "pending",
+        ArchiveStatus::Archived { file_name, outcome } => {
+            if let Some(Ok(outcome)) = outcome.as_deref().map(HeaderValue::from_str) {
+                headers.insert(UPLOAD_OUTCOME, outcome);
+            }
+            if let Some(Ok(file_name)) = file_name
+                .as_deref()
+                .map(str::as_bytes)
+                .map(HeaderValue::from_bytes)
+            {
+                headers.insert(ARCHIVE_FILE_NAME, file_name);
+            }
+            "archived"
+        }
+        ArchiveStatus::Failed(failure) => {
+            if let Ok(failure) = HeaderValue::from_bytes(failure.as_bytes()) {
+                headers.insert(ARCHIVE_ERROR, failure);
+            }
+            "failed"
+        }
+    };
+    headers.insert(ARCHIVE_STATUS, HeaderValue::from_static(status));
+}
+
+fn now() -> i64 {
+    catalog::unix_seconds(SystemTime::now())
+}
+
+fn next_expiry() -> i64 {
+    now() + tus::EXPIRE_AFTER.as_secs() as i64
+}
+
+/// Look up a live upload; expired ones answer 410 until the sweeper removes them.
+async fn find_upload(state: &AppState, id: &str) -> Result<TusUpload, TusError> {
+    if !TusStore::is_valid_id(id) {
+        return Err(AppError::NotFound(String::from("upload not found")).into());
+    }
+
+    match state.catalog.get_upload(id).await {
+        Ok(Some(upload)) if upload.expires_at <= now() => {
+            Err(AppError::Gone(String::from("upload has expired")).into())
+        }
+        Ok(Some(upload)) => Ok(upload),
+        Ok(None) => Err(AppError::NotFound(String::from("upload not found")).into()),
+        Err(err) => Err(internal_error(err)),
+    }
+}
+
+async fn current_offset(state: &AppState, upload: &TusUpload) -> Result<u64, TusError> {
+    if upload.completed {
+        return Ok(upload.upload_length);
+    }
+    state.tus.offset(&upload.id).await.map_err(internal_error)
+}
+
+/// Append the request body to the upload, never past `Upload-Length`. Returns the new offset.
+async fn append(
+    state: &AppState,
+    upload: &TusUpload,
+    offset: u64,
+    headers: &HeaderMap,
+    body: BodyStream,
+) -> Result<u64, TusError> {
+    let remaining = upload.upload_length - offset;
+    if header_u64(headers, header::CONTENT_LENGTH)?.is_some_and(|len| len > remaining) {
+        return Err(
+            AppError::PayloadTooLarge(String::from("request body exceeds Upload-Length")).into(),
+        );
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(state.tus.path(&upload.id))
+        .await
+        .map_err(internal_error)?;
+    let mut reader = StreamReader::new(body.map_err(std::io::Error::other));
+    let copied = tokio::io::copy(&mut (&mut reader).take(remaining), &mut file).await;
+    let mut extra = [0u8; 1];
+    let overrun = copied.is_ok() && reader.read(&mut extra).await.unwrap_or_default() > 0;
+
+    // Keep whatever did arrive, even if the client went away mid-request, but none of a body
+    // longer than the upload: that request failed, so it mustn't be the one completing it
+    file.flush().await.map_err(internal_error)?;
+    if overrun {
+        file.set_len(offset).await.map_err(internal_error)?;
+    }
+    file.sync_data().await.map_err(internal_error)?;
+    if let Err(err) = copied {
+        warn!("Resumable upload {} interrupted: {}", upload.id, err);
+        return Err(AppError::BadRequest(String::from("upload interrupted")).into());
+    }
+    if overrun {
+        return Err(
+            AppError::PayloadTooLarge(String::from("request body exceeds Upload-Length")).into(),
+        );
+    }
+
+    state.tus.offset(&upload.id).await.map_err(internal_error)
+}
+
+/// Look up a single `Upload-Metadata` value.
+fn metadata_value(metadata: &str, name: &str) -> Result<Option<String>, TusError> {
+    Ok(tus::parse_metadata(metadata)
+        .ok_or_else(|| AppError::BadRequest(String::from("invalid Upload-Metadata header")))?
+        .into_iter()
+        .find_map(|(key, value)| (key == name).then_some(value).flatten()))
+}
+
+/// The `on_conflict` metadata value, falling back to the server default.
+fn conflict_policy(state: &AppState, metadata: &str) -> Result<ConflictPolicy, TusError> {
+    match metadata_value(metadata, "on_conflict")? {
+        Some(value) => value
+            .parse()
+            .map_err(|message| AppError::BadRequest(message).into()),
+        None => Ok(state.config.current().uploads.conflict_policy),
+    }
+}
+
+/// Record that all of `upload` has been received, and start moving it into the archive. For a
+/// large upload that takes far longer than a request should, so it happens in the background,
+/// holding `lock` until done; `HEAD /files/:id` reports how it went.
+async fn complete(
+    state: &AppState,
+    upload: &TusUpload,
+    lock: UploadLock,
+    headers: &mut HeaderMap,
+) -> Result<(), TusError> {
+    state
+        .catalog
+        .complete_upload(&upload.id, now(), next_expiry())
+        .await
+        .map_err(internal_error)?;
+    info!("Resumable upload {} received", upload.id);
+
+    spawn_archive(state.clone(), upload.clone(), lock);
+    insert_archive_status(headers, &ArchiveStatus::Pending);
+    Ok(())
+}
+
+fn spawn_archive(state: AppState, upload: TusUpload, lock: UploadLock) {
+    tokio::spawn(async move {
+        let _lock = lock;
+        let Err(err) = archive(&state, &upload).await else {
+            return;
+        };
+        let err = AppError::from(err);
+        match &err {
+            AppError::Internal(err) => {
+                warn!(
+                    "Failed to archive resumable upload {}: {:#}",
+                    upload.id, err
+                )
+            }
+            err => warn!("Failed to archive resumable upload {}: {}", upload.id, err),
+        }
+        // The received bytes stay until the upload expires
+        if let Err(err) = state.catalog.fail_upload(&upload.id, err.to_string()).await {
+            warn!("Failed to record failure of upload {}: {}", upload.id, err);
+        }
+    });
+}
+
+/// Move a received upload into the archive, exactly as a multipart upload would be.
+async fn archive(state: &AppState, upload: &TusUpload) -> anyhow::Result<()> {
+    let key = StorageKey::parse(&upload.file_name)?;
+    let policy = conflict_policy(state, &upload.metadata).map_err(|TusError(err)| err)?;
+    let mut file = File::open(state.tus.path(&upload.id)).await?;
+
+    let uploaded = archive_stream(state, &key, policy, &mut file).await?;
+
+    state
+        .catalog
+        .archive_upload(
+            &upload.id,
+            now(),
+            uploaded.file.file_name.clone(),
+            uploaded.outcome.as_str(),
+        )
+        .await?;
+    state.tus.remove(&upload.id).await?;
+    info!(
+        "Resumable upload {} archived as {}",
+        upload.id, uploaded.file.file_name
+    );
+    Ok(())
+}
+
+/// Go on archiving the uploads that were received in full before the server last stopped.
+pub async fn resume_archiving(state: &AppState) -> anyhow::Result<()> {
+    for upload in state.catalog.unarchived_uploads().await? {
+        let Some(lock) = state.tus.lock(&upload.id) else {
+            continue;
+        };
+        info!("Resuming archiving of resumable upload {}", upload.id);
+        spawn_archive(state.clone(), upload, lock);
+    }
+    Ok(())
+}
+
+/// `OPTIONS /files`: advertise protocol support.
+pub async fn tus_options(state: &AppState) -> Response {
+    let max_size = state.config.current().max_upload_size();
+    let mut headers = tus_headers();
+    headers.insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+    headers.insert(TUS_EXTENSION, HeaderValue::from_static(TUS_EXTENSIONS));
+    headers.insert(TUS_MAX_SIZE, HeaderValue::from(max_size));
+    (StatusCode::NO_CONTENT, headers).into_response()
+}
+
+/// `/files` or an upload under it; not whatever else happens to start with the same letters.
+fn is_tus_path(path: &str) -> bool {
+    path == "/files" || path.starts_with("/files/")
+}
+
+/// tower-http's CORS layer answers every OPTIONS request as a preflight, so plain tus
+/// discovery requests are picked off before they reach it.
+pub async fn tus_discovery<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let is_discovery = request.method() == Method::OPTIONS
+        && is_tus_path(request.uri().path())
+        && !request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+    if is_discovery {
+        return tus_options(&state).await;
+    }
+    next.run(request).await
+}
+
+/// `POST /files`: create an upload (creation, creation-with-upload).
+pub async fn tus_create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> TusResult {
+    check_resumable(&headers)?;
+
+    if headers.contains_key(UPLOAD_DEFER_LENGTH) {
+        return Err(
+            AppError::BadRequest(String::from("Upload-Defer-Length is not supported")).into(),
+        );
+    }
+    let upload_length = header_u64(&headers, UPLOAD_LENGTH)?
+        .ok_or_else(|| AppError::BadRequest(String::from("missing Upload-Length header")))?;
+    if upload_length > state.config.current().max_upload_size() {
+        return Err(
+            AppError::PayloadTooLarge(String::from("Upload-Length exceeds Tus-Max-Size")).into(),
+        );
+    }
+
+    let metadata = header_str(&headers, UPLOAD_METADATA).unwrap_or_default();
+    let file_name = metadata_value(metadata, "filename")?.ok_or_else(|| {
+        AppError::BadRequest(String::from("Upload-Metadata must include filename"))
+    })?;
+    let key = StorageKey::parse(&file_name)?;
+
+    // Refuse a doomed upload before the client sends any of it
+    let policy = conflict_policy(&state, metadata)?;
+    let existing = state.catalog.file_names().await.map_err(internal_error)?;
+    check_conflict(&key, policy, &existing)?;
+
+    let upload = TusUpload {
+        id: TusStore::new_id().map_err(internal_error)?,
+        upload_length,
+        metadata: metadata.to_string(),
+        file_name: key.to_string(),
+        expires_at: next_expiry(),
+        completed: false,
+        archive: ArchiveStatus::Pending,
+    };
+    state.tus.create(&upload.id).await.map_err(internal_error)?;
+    state
+        .catalog
+        .insert_upload(upload.clone(), now())
+        .await
+        .map_err(internal_error)?;
+    info!(
+        "Created resumable upload {} for {} ({} bytes)",
+        upload.id, upload.file_name, upload_length
+    );
+
+    let mut response_headers = tus_headers();
+    let location = format!("/files/{}", upload.id);
+    response_headers.insert(
+        header::LOCATION,
+        HeaderValue::from_str(&location).map_err(internal_error)?,
+    );
+    insert_expires(&mut response_headers, upload.expires_at);
+
+    // creation-with-upload: the body may already carry the first chunk
+    let has_body = header_str(&headers, header::CONTENT_TYPE) == Some(OFFSET_OCTET_STREAM);
+    let Some(lock) = state.tus.lock(&upload.id) else {
+        return Err(internal_error(anyhow::anyhow!(
+            "new upload is already locked"
+        )));
+    };
+    let offset = if has_body {
+        append(&state, &upload, 0, &headers, body).await?
+    } else {
+        0
+    };
+    response_headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+    if offset == upload_length {
+        complete(&state, &upload, lock, &mut response_headers).await?;
+    }
+
+    Ok((StatusCode::CREATED, response_headers).into_response())
+}
+
+/// `HEAD /files/:id`: report how much of the upload has been received.
+pub async fn tus_head(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> TusResult {
+    check_resumable(&headers)?;
+    let upload = find_upload(&state, &id).await?;
+    let offset = current_offset(&state, &upload).await?;
+
+    let mut response_headers = tus_headers();
+    response_headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+    response_headers.insert(UPLOAD_LENGTH, HeaderValue::from(upload.upload_length));
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if !upload.metadata.is_empty() {
+        if let Ok(metadata) = HeaderValue::from_str(&upload.metadata) {
+            response_headers.insert(UPLOAD_METADATA, metadata);
+        }
+    }
+    if upload.completed {
+        insert_archive_status(&mut response_headers, &upload.archive);
+    } else {
+        insert_expires(&mut response_headers, upload.expires_at);
+    }
+
+    Ok((StatusCode::OK, response_headers).into_response())
+}
+
+/// `PATCH /files/:id`: append a chunk at the client's `Upload-Offset`.
+pub async fn tus_patch(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> TusResult {
+    check_resumable(&headers)?;
+    if header_str(&headers, header::CONTENT_TYPE) != Some(OFFSET_OCTET_STREAM) {
+        return Err(AppError::UnsupportedMediaType(String::from(
+            "Content-Type must be application/offset+octet-stream",
+        ))
+        .into());
+    }
+    let client_offset = header_u64(&headers, UPLOAD_OFFSET)?
+        .ok_or_else(|| AppError::BadRequest(String::from("missing Upload-Offset header")))?;
+
+    let upload = find_upload(&state, &id).await?;
+    let Some(lock) = state.tus.lock(&upload.id) else {
+        return Err(
+            AppError::Locked(String::from("upload is being written by another request")).into(),
+        );
+    };
+
+    let offset = current_offset(&state, &upload).await?;
+    if client_offset != offset {
+        return Err(AppError::Conflict(String::from(
+            "Upload-Offset does not match the current offset",
+        ))
+        .into());
+    }
+
+    let mut response_headers = tus_headers();
+    let offset = if upload.completed {
+        insert_archive_status(&mut response_headers, &upload.archive);
+        offset
+    } else {
+        let offset = append(&state, &upload, offset, &headers, body).await?;
+        if offset == upload.upload_length {
+            complete(&state, &upload, lock, &mut response_headers).await?;
+        } else {
+            state
+                .catalog
+                .extend_upload(&upload.id, next_expiry())
+                .await
+                .map_err(internal_error)?;
+        }
+        offset
+    };
+
+    response_headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+    if offset < upload.upload_length {
+        insert_expires(&mut response_headers, next_expiry());
+    }
+
+    Ok((StatusCode::NO_CONTENT, response_headers).into_response())
+}
+
+/// `DELETE /files/:id`: abandon an upload (termination).
+pub async fn tus_delete(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> TusResult {
+    check_resumable(&headers)?;
+    let upload = find_upload(&state, &id).await?;
+    let Some(_lock) = state.tus.lock(&upload.id) else {
+        return Err(
+            AppError::Locked(String::from("upload is being written by another request")).into(),
+        );
+    };
+
+    state.tus.remove(&upload.id).await.map_err(internal_error)?;
+    state
+        .catalog
+        .delete_upload(&upload.id)
+        .await
+        .map_err(internal_error)?;
+    info!("Terminated resumable upload {}", upload.id);
+
+    Ok((StatusCode::NO_CONTENT, tus_headers()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        middleware,
+        routing::{head, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn app(state: &AppState) -> Router {
+        Router::new()
+            .route("/files", post(tus_create))
+            .route(
+                "/files/:id",
+                head(tus_head).patch(tus_patch).delete(tus_delete),
+            )
+            .with_state(state.clone())
+            .layer(middleware::from_fn_with_state(state.clone(), tus_discovery))
+    }
+
+    fn request(
+        method: Method,
+        uri: &str,
+        headers: &[(HeaderName, &str)],
+        body: &str,
+    ) -> Request<Body> {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(TUS_RESUMABLE, TUS_VERSION);
+        for (name, value) in headers {
+            builder = builder.header(name, *value);
+        }
+        builder.body(Body::from(body.to_string())).unwrap()
+    }
+
+    /// Create a 10 byte upload, returning its path.
+    async fn create(state: &AppState) -> String {
+        let response = app(state)
+            .oneshot(request(
+                Method::POST,
+                "/files",
+                &[
+                    (UPLOAD_LENGTH, "10"),
+                    // video.mp4
+                    (UPLOAD_METADATA, "filename dmlkZW8ubXA0"),
+                ],
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    async fn patch(state: &AppState, location: &str, offset: &str, body: &str) -> Response {
+        app(state)
+            .oneshot(request(
+                Method::PATCH,
+                location,
+                &[
+                    (header::CONTENT_TYPE, OFFSET_OCTET_STREAM),
+                    (UPLOAD_OFFSET, offset),
+                ],
+                body,
+            ))
+            .await
+            .unwrap()
+    }
+
+    async fn offset(state: &AppState, location: &str) -> String {
+        let response = app(state)
+            .oneshot(request(Method::HEAD, location, &[], ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers()[UPLOAD_OFFSET]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn discovers_only_tus_paths() {
+        for (path, expected) in [
+            ("/files", true),
+            ("/files/0123456789abcdef0123456789abcdef", true),
+            ("/files/", true),
+            ("/filesystem", false),
+            ("/files.json", false),
+            ("/", false),
+            ("/upload", false),
+        ] {
+            assert_eq!(is_tus_path(path), expected, "{:?}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_discovery_requests() {
+        let state = AppState::for_tests("tus-discovery").unwrap();
+        for (path, expected) in [
+            ("/files", StatusCode::NO_CONTENT),
+            ("/filesystem", StatusCode::NOT_FOUND),
+        ] {
+            let response = app(&state)
+                .oneshot(request(Method::OPTIONS, path, &[], ""))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{:?}", path);
+            assert_eq!(
+                response.headers().contains_key(TUS_EXTENSION),
+                expected == StatusCode::NO_CONTENT,
+                "{:?}",
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_the_wrong_offset() {
+        let state = AppState::for_tests("tus-offset").unwrap();
+        let location = create(&state).await;
+
+        let response = patch(&state, &location, "0", "abcd").await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[UPLOAD_OFFSET], "4");
+
+        for stale in ["0", "2", "5"] {
+            let response = patch(&state, &location, stale, "efgh").await;
+            assert_eq!(response.status(), StatusCode::CONFLICT, "{:?}", stale);
+            assert_eq!(response.headers()[TUS_RESUMABLE], TUS_VERSION);
+        }
+        assert_eq!(offset(&state, &location).await, "4");
+    }
+
+    #[tokio::test]
+    async fn rolls_back_an_overrun() {
+        let state = AppState::for_tests("tus-overrun").unwrap();
+        let location = create(&state).await;
+        assert_eq!(
+            patch(&state, &location, "0", "abcd").await.status(),
+            StatusCode::NO_CONTENT
+        );
+
+        // Two bytes too many, and no Content-Length to refuse it by up front
+        let response = patch(&state, &location, "4", "efghijkl").await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(offset(&state, &location).await, "4");
+
+        let id = location.trim_start_matches("/files/");
+        let data = tokio::fs::read(state.tus.path(id)).await.unwrap();
+        assert_eq!(data, b"abcd");
+        let upload = state.catalog.get_upload(id).await.unwrap().unwrap();
+        assert!(!upload.completed);
+
+        // A declared overrun is refused before anything is written
+        let response = app(&state)
+            .oneshot(request(
+                Method::PATCH,
+                &location,
+                &[
+                    (header::CONTENT_TYPE, OFFSET_OCTET_STREAM),
+                    (UPLOAD_OFFSET, "4"),
+                    (header::CONTENT_LENGTH, "8"),
+                ],
+                "efghijkl",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(offset(&state, &location).await, "4");
+    }
+}
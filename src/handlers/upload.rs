@@ -0,0 +1,347 @@
+use crate::{
+    catalog::{self, NewEntry},
+    error::{AppError, AppResult},
+    faststart, jobs,
+    models::{
+        check_conflict, resolve_conflict, ConflictPolicy, Fixity, JobKind, MetadataPatch,
+        StorageKey, UploadOutcome, UploadResponse, UploadedFile, VideoMetadata,
+    },
+    state::AppState,
+    storage::{
+        blobs::{blob_key, BLOB_DIGESTS},
+        staging::StagedUpload,
+        StorageBackend,
+    },
+    utils::{
+        digest::{Algorithm, DigestingBody, DigestingReader, Digests, Expected},
+        mime,
+    },
+};
+use axum::{
+    body::Body,
+    extract::{multipart::Field, rejection::QueryRejection, FromRequest, Multipart, Query, State},
+    http::Request,
+    Json,
+};
+use futures_util::TryStreamExt;
+use log::{info, warn};
+use serde::Deserialize;
+use std::{io, sync::Arc, time::SystemTime};
+use tokio::io::AsyncRead;
+use tokio_util::io::StreamReader;
+
+#[derive(Debug, Deserialize)]
+pub struct UploadParams {
+    pub on_conflict: Option<ConflictPolicy>,
+}
+
+/// Most a client may send after the closing multipart boundary.
+const MAX_EPILOGUE: usize = 64 * 1024;
+
+/// Largest metadata field of a multipart upload, in bytes.
+const MAX_FORM_FIELD_SIZE: usize = 64 * 1024;
+
+/// An upload that has been streamed into staging, with its digests.
+struct SavedFile {
+    staged: StagedUpload,
+    size: u64,
+    /// Of the contents as received.
+    digests: Digests,
+    /// Algorithms of the client-supplied digests that matched.
+    verified: Vec<Algorithm>,
+    /// Of the contents as staged now, if they were rewritten since.
+    rewritten: Option<Digests>,
+}
+
+impl SavedFile {
+    /// Digests of what will be stored.
+    fn stored_digests(&self) -> &Digests {
+        self.rewritten.as_ref().unwrap_or(&self.digests)
+    }
+}
+
+/// Stream `reader` into staging, digesting it on the way and checking it against `expected`.
+async fn save_file(
+    storage: &Arc<dyn StorageBackend>,
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+    expected: &Expected,
+) -> anyhow::Result<SavedFile> {
+    let staged = StagedUpload::new(storage.clone());
+    let mut reader = DigestingReader::new(
+        reader,
+        BLOB_DIGESTS.into_iter().chain(expected.algorithms()),
+    )?;
+    let size = storage.put_stream(staged.key(), &mut reader).await?;
+    let digests = reader.finish()?;
+
+    // On a mismatch the staged object is dropped, and with it discarded
+    let verified = expected.verify("upload", &digests)?;
+
+    Ok(SavedFile {
+        staged,
+        size,
+        digests,
+        verified,
+        rewritten: None,
+    })
+}
+
+/// Swap a staged MP4 whose `moov` comes last for a fast-start copy. The upload is kept as it was
+/// if that fails.
+async fn fast_start(state: &AppState, key: &StorageKey, saved: &mut SavedFile) {
+    match faststart::relocate_moov(&state.storage, saved.staged.key()).await {
+        Ok(Some(rewritten)) => {
+            info!("Moved the moov of {} to the front for streaming", key);
+            // The previous staged object is discarded as it drops
+            saved.staged = rewritten.staged;
+            saved.size = rewritten.size;
+            saved.rewritten = Some(rewritten.digests);
+        }
+        Ok(None) => {}
+        Err(err) => warn!("Failed to move the moov of {} to the front: {:#}", key, err),
+    }
+}
+
+/// An upload sitting in staging, not yet part of the archive.
+pub struct PendingUpload {
+    key: StorageKey,
+    policy: ConflictPolicy,
+    saved: SavedFile,
+}
+
+/// Stream everything `reader` yields into staging, verifying it against `expected`.
+pub async fn stage_upload(
+    state: &AppState,
+    key: &StorageKey,
+    policy: ConflictPolicy,
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+    expected: &Expected,
+) -> anyhow::Result<PendingUpload> {
+    check_conflict(key, policy, &state.catalog.file_names().await?)?;
+
+    info!("Uploading file: {}", key);
+    let mut saved = save_file(&state.storage, reader, expected).await?;
+    if state.config.current().uploads.faststart {
+        fast_start(state, key, &mut saved).await;
+    }
+
+    Ok(PendingUpload {
+        key: key.clone(),
+        policy,
+        saved,
+    })
+}
+
+/// Store a staged upload under its name (or wherever its policy resolves a clash to) and record
+/// it in the catalog.
+///
+/// Contents are stored once per distinct SHA-256; uploading a file that is already archived
+/// under another name only adds a reference to the existing blob.
+pub async fn commit_upload(
+    state: &AppState,
+    upload: PendingUpload,
+) -> anyhow::Result<UploadedFile> {
+    let PendingUpload { key, policy, saved } = upload;
+    let storage = &state.storage;
+    let sha256 = saved.stored_digests().require_hex(Algorithm::Sha256)?;
+    let md5 = saved.stored_digests().require_hex(Algorithm::Md5)?;
+    let original_sha256 = match saved.rewritten {
+        Some(_) => Some(saved.digests.require_hex(Algorithm::Sha256)?),
+        None => None,
+    };
+
+    // Deciding where the upload goes and moving it there must not interleave with another commit
+    let commit = state.commit_lock.lock().await;
+    let existing = state.catalog.file_names().await?;
+    let (target, outcome) = resolve_conflict(&key, policy, &existing)?;
+    if outcome == UploadOutcome::Versioned {
+        state.catalog.push_version(key.as_str()).await?;
+        info!("Kept previous {} as a numbered version", key);
+    }
+
+    // A blob the scrubber found damaged is replaced by the fresh copy
+    let blob = blob_key(&sha256);
+    let damaged = state
+        .catalog
+        .blob_fixity(&sha256)
+        .await?
+        .is_some_and(Fixity::is_damaged);
+    if !damaged && storage.stat(&blob).await?.is_some() {
+        info!("{} has the same contents as blob {}", key, sha256);
+        saved.staged.discard().await?;
+    } else {
+        saved.staged.commit(&blob).await?;
+    }
+
+    let file_name = target.as_str();
+
+    // Record what was actually uploaded so /stream and /archive don't have to guess
+    let content_type = mime::detect_object(storage.as_ref(), &blob, file_name).await?;
+    let (entry, released) = state
+        .catalog
+        .insert(NewEntry {
+            file_name: file_name.to_string(),
+            content_type: content_type.to_string(),
+            size: saved.size,
+            uploaded_at: catalog::unix_seconds(SystemTime::now()),
+            sha256: sha256.clone(),
+            md5,
+            original_sha256,
+        })
+        .await?;
+    catalog::delete_blobs(storage.as_ref(), released).await;
+    if damaged {
+        let now = catalog::unix_seconds(SystemTime::now());
+        state
+            .catalog
+            .record_fixity(&sha256, Fixity::Ok, None, now)
+            .await?;
+        info!("Repaired blob {} from the upload of {}", sha256, key);
+    }
+    info!(
+        "File {} uploaded successfully ({}, {:?})",
+        file_name, content_type, outcome
+    );
+    drop(commit);
+
+    // Probing and everything made from the contents happen in the background; a file whose
+    // probe can't be queued is still archived, and queued at the next startup
+    let entry = match state
+        .jobs
+        .enqueue(JobKind::Probe, &entry, jobs::DEFAULT_PRIORITY)
+        .await
+    {
+        Ok(_) => state.catalog.get_by_id(entry.id).await?.unwrap_or(entry),
+        Err(err) => {
+            warn!(
+                "Failed to queue the probe of {}: {:#}",
+                entry.file_name, err
+            );
+            entry
+        }
+    };
+
+    Ok(UploadedFile {
+        requested_name: key.to_string(),
+        outcome,
+        file: entry,
+        digests: saved.digests.to_hex_map(),
+        verified: names(&saved.verified),
+    })
+}
+
+/// Stage and commit in one go, for ingest paths with no client digests to check.
+pub async fn archive_stream(
+    state: &AppState,
+    key: &StorageKey,
+    policy: ConflictPolicy,
+    reader: &mut (dyn AsyncRead + Send + Unpin),
+) -> anyhow::Result<UploadedFile> {
+    let upload = stage_upload(state, key, policy, reader, &Expected::default()).await?;
+    commit_upload(state, upload).await
+}
+
+fn names(algorithms: &[Algorithm]) -> Vec<&'static str> {
+    algorithms
+        .iter()
+        .map(|algorithm| algorithm.name())
+        .collect()
+}
+
+/// The value of a multipart text field.
+async fn read_text(mut field: Field<'_>) -> AppResult<String> {
+    let name = field.name().unwrap_or_default().to_string();
+    let mut value = vec![];
+    while let Some(chunk) = field.chunk().await? {
+        if value.len() + chunk.len() > MAX_FORM_FIELD_SIZE {
+            return Err(AppError::PayloadTooLarge(format!(
+                "field '{}' is larger than {} bytes",
+                name, MAX_FORM_FIELD_SIZE
+            )));
+        }
+        value.extend_from_slice(&chunk);
+    }
+    String::from_utf8(value)
+        .map_err(|_| AppError::BadRequest(format!("field '{}' is not UTF-8", name)))
+}
+
+/// Upload files as the parts of a multipart form. Text fields besides them set metadata, as
+/// `PATCH /videos/:id` would, of every file uploaded.
+pub async fn video_upload_handler(
+    State(state): State<AppState>,
+    params: Result<Query<UploadParams>, QueryRejection>,
+    request: Request<Body>,
+) -> AppResult<Json<UploadResponse>> {
+    let Query(params) = params?;
+    let policy = params
+        .on_conflict
+        .unwrap_or(state.config.current().uploads.conflict_policy);
+
+    // A digest of the whole request body can only be checked once all of it has been read, so
+    // every part is staged first and only committed after that
+    let expected = Expected::from_headers(request.headers())?;
+    let (parts, body) = request.into_parts();
+    let body = DigestingBody::new(body, expected.algorithms())?;
+    let request = Request::from_parts(parts, Body::wrap_stream(body.stream()));
+    let mut multipart = Multipart::from_request(request, &state).await?;
+
+    let mut pending = vec![];
+    let mut metadata = MetadataPatch::default();
+    while let Some(field) = multipart.next_field().await? {
+        let Some(file_name) = field.file_name() else {
+            let name = field
+                .name()
+                .ok_or_else(|| AppError::BadRequest(String::from("missing file name")))?
+                .to_string();
+            let value = read_text(field).await?;
+            metadata
+                .set_form_field(&name, value)
+                .map_err(AppError::BadRequest)?;
+            continue;
+        };
+        let key = StorageKey::parse(file_name)?;
+        let part_expected = Expected::from_headers(field.headers())?;
+
+        let mut reader = StreamReader::new(field.map_err(io::Error::other));
+        pending.push(stage_upload(&state, &key, policy, &mut reader, &part_expected).await?);
+    }
+    drop(multipart);
+
+    let verified = expected.verify("request body", &body.finish(MAX_EPILOGUE).await?)?;
+    // Checked before anything is committed; only the number of custom fields depends on what a
+    // file already has
+    metadata
+        .clone()
+        .apply(&mut VideoMetadata::default())
+        .map_err(AppError::BadRequest)?;
+
+    let mut files = vec![];
+    for upload in pending {
+        let mut uploaded = commit_upload(&state, upload).await?;
+        if !metadata.is_empty() {
+            let id = uploaded.file.id;
+            let patch = metadata.clone();
+            match state
+                .catalog
+                .edit_metadata(id, move |metadata, _| patch.apply(metadata))
+                .await?
+            {
+                Some(Err(err)) => warn!(
+                    "Failed to set the metadata of {}: {}",
+                    uploaded.file.file_name, err
+                ),
+                _ => {
+                    if let Some(entry) = state.catalog.get_by_id(id).await? {
+                        uploaded.file = entry;
+                    }
+                }
+            }
+        }
+        files.push(uploaded);
+    }
+
+    Ok(Json(UploadResponse {
+        files,
+        verified: names(&verified),
+    }))
+}
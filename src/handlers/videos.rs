@@ -0,0 +1,145 @@
+use crate::{
+    error::{AppError, AppResult},
+    models::{MetadataPatch, VideoMetadata},
+    state::AppState,
+    utils::conditional,
+};
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection},
+        Path, State,
+    },
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Json,
+};
+
+/// Strong entity tag of revision `revision` of a video's metadata.
+fn etag(revision: i64) -> String {
+    format!("\"{}\"", revision)
+}
+
+fn respond(metadata: VideoMetadata, revision: i64) -> impl IntoResponse {
+    ([(header::ETAG, etag(revision))], Json(metadata))
+}
+
+/// What users have said about video `id`, with its revision as the `ETag`.
+pub async fn video_metadata_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> AppResult<impl IntoResponse> {
+    let Path(id) = id?;
+    let (metadata, revision) = state
+        .catalog
+        .get_metadata(id)
+        .await?
+        .ok_or_else(|| AppError::not_found("video"))?;
+    Ok(respond(metadata, revision))
+}
+
+/// Change the metadata of video `id` as a merge patch. With `If-Match`, only if nobody has
+/// changed it since the client read it.
+pub async fn video_metadata_patch_handler(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    headers: HeaderMap,
+    patch: Result<Json<MetadataPatch>, JsonRejection>,
+) -> AppResult<impl IntoResponse> {
+    let Path(id) = id?;
+    let Json(patch) = patch?;
+
+    let (metadata, revision) = state
+        .catalog
+        .edit_metadata(id, move |metadata, revision| {
+            if !conditional::if_match(&headers, &etag(revision)) {
+                return Err(AppError::PreconditionFailed(String::from(
+                    "the video's metadata has changed since",
+                )));
+            }
+            patch.apply(metadata).map_err(AppError::BadRequest)
+        })
+        .await?
+        .ok_or_else(|| AppError::not_found("video"))??;
+    Ok(respond(metadata, revision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::NewEntry;
+    use axum::{
+        body::Body,
+        http::{Method, Request, StatusCode},
+        response::Response,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn patch(state: &AppState, id: i64, if_match: Option<&str>, body: &str) -> Response {
+        let mut request = Request::builder()
+            .method(Method::PATCH)
+            .uri(format!("/videos/{}", id))
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(if_match) = if_match {
+            request = request.header(header::IF_MATCH, if_match);
+        }
+        Router::new()
+            .route(
+                "/videos/:id",
+                get(video_metadata_handler).patch(video_metadata_patch_handler),
+            )
+            .with_state(state.clone())
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn patches_only_the_expected_revision() {
+        let state = AppState::for_tests("video-metadata").unwrap();
+        let (entry, _) = state
+            .catalog
+            .insert(NewEntry {
+                file_name: String::from("a.mp4"),
+                content_type: String::from("video/mp4"),
+                size: 1,
+                uploaded_at: 0,
+                sha256: format!("{:064x}", 1),
+                md5: format!("{:032x}", 1),
+                original_sha256: None,
+            })
+            .await
+            .unwrap();
+        let (_, revision) = state.catalog.get_metadata(entry.id).await.unwrap().unwrap();
+        let current = etag(revision);
+
+        let response = patch(&state, entry.id, Some(&current), r#"{"title": "First"}"#).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let next = etag(revision + 1);
+        assert_eq!(response.headers()[header::ETAG], next.as_str());
+
+        // Someone else's edit came first
+        for stale in [current.as_str(), "W/\"1\"", "\"999\""] {
+            let response = patch(&state, entry.id, Some(stale), r#"{"title": "Second"}"#).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::PRECONDITION_FAILED,
+                "{}",
+                stale
+            );
+        }
+        let (metadata, _) = state.catalog.get_metadata(entry.id).await.unwrap().unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("First"));
+
+        for if_match in [Some(next.as_str()), Some("*"), None] {
+            let response = patch(&state, entry.id, if_match, r#"{"tags": ["a"]}"#).await;
+            assert_eq!(response.status(), StatusCode::OK, "{:?}", if_match);
+        }
+
+        let response = patch(&state, entry.id, None, r#"{"title": "two\nlines"}"#).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = patch(&state, entry.id + 1, None, r#"{"title": "x"}"#).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,369 @@
+//! HLS packaging: each video is transcoded with ffmpeg into a ladder of renditions plus a master
+//! playlist, so players can adapt to the connection instead of pulling the original in full.
+//! The original is only ever read.
+//!
+//! Like storyboards, packages are kept per blob, flat under `.hls/<sha256>/`: the master playlist
+//! and, for each rendition, `<name>.m3u8` with its segments and (for fMP4) `<name>_init.mp4`.
+//! Audio is a rendition of its own. fMP4 packages also get a DASH manifest, see [`dash`].
+
+use crate::{
+    config::{HlsConfig, Rendition, SegmentFormat, SharedConfig, AUDIO_RENDITION},
+    dash,
+    models::ArchiveEntry,
+    storage::{blobs::blob_key, StorageBackend},
+    thumbnails::{feed, ffmpeg_command, spawn_ffmpeg},
+};
+use log::warn;
+use std::{
+    fmt::Write,
+    io::{Cursor, ErrorKind},
+    path::Path,
+    time::Duration,
+};
+
+pub const HLS_PREFIX: &str = ".hls/";
+
+pub const MASTER_PLAYLIST: &str = "master.m3u8";
+
+/// Transcoding runs at a few times real time at best.
+const TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+
+fn package_dir(sha256: &str) -> String {
+    format!("{}{}/", HLS_PREFIX, sha256)
+}
+
+/// Storage key of `file_name` in the package of the contents `sha256`.
+pub fn object_key(sha256: &str, file_name: &str) -> String {
+    format!("{}{}", package_dir(sha256), file_name)
+}
+
+/// Delete the package of the contents `sha256`.
+pub async fn remove(storage: &dyn StorageBackend, sha256: &str) -> anyhow::Result<()> {
+    for object in storage.list(&package_dir(sha256)).await? {
+        storage.delete(&object.key).await?;
+    }
+    Ok(())
+}
+
+/// Content type of a file in a package, or `None` if packages have no such file.
+pub fn content_type(file_name: &str) -> Option<&'static str> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if stem.is_empty()
+        || !stem
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    match extension {
+        "m3u8" => Some("application/vnd.apple.mpegurl"),
+        "m4s" => Some("video/iso.segment"),
+        "mp4" => Some("video/mp4"),
+        "ts" => Some("video/mp2t"),
+        "mpd" => Some(dash::CONTENT_TYPE),
+        _ => None,
+    }
+}
+
+/// Height of the video of `entry` as it displays, or `None` if it isn't a video.
+fn display_height(entry: &ArchiveEntry) -> Option<u32> {
+    let height = match entry.rotation.unwrap_or_default() % 180 {
+        0 => entry.height?,
+        _ => entry.width?,
+    };
+    (height > 0).then_some(height)
+}
+
+/// The renditions of `ladder` for a video `height` pixels tall: those no taller than it, or if
+/// there are none, the shortest scaled down to it. Videos are never scaled up.
+fn select(ladder: &[Rendition], height: u32) -> Vec<Rendition> {
+    let selected: Vec<_> = ladder
+        .iter()
+        .filter(|rendition| rendition.height <= height)
+        .cloned()
+        .collect();
+    if !selected.is_empty() {
+        return selected;
+    }
+
+    let shortest = ladder.iter().min_by_key(|rendition| rendition.height);
+    shortest
+        .map(|rendition| Rendition {
+            // libx264 wants even dimensions
+            height: (height & !1).max(2),
+            ..rendition.clone()
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Arguments after the input that make ffmpeg write the package of `renditions` into `out`.
+fn output_args(
+    config: &HlsConfig,
+    renditions: &[Rendition],
+    has_audio: bool,
+    out: &Path,
+) -> Vec<String> {
+    let mut filter = format!("[0:v]split={}", renditions.len());
+    for n in 0..renditions.len() {
+        let _ = write!(filter, "[s{}]", n);
+    }
+    for (n, rendition) in renditions.iter().enumerate() {
+        let _ = write!(filter, ";[s{}]scale=-2:{}[v{}]", n, rendition.height, n);
+    }
+
+    let mut args = vec![String::from("-filter_complex"), filter];
+    for n in 0..renditions.len() {
+        args.extend([String::from("-map"), format!("[v{}]", n)]);
+    }
+    if has_audio {
+        args.extend([String::from("-map"), String::from("0:a:0")]);
+    }
+
+    args.extend(
+        [
+            "-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p",
+        ]
+        .map(String::from),
+    );
+    for (n, rendition) in renditions.iter().enumerate() {
+        let kbps = rendition.video_kbps;
+        args.extend([
+            format!("-b:v:{}", n),
+            format!("{}k", kbps),
+            format!("-maxrate:v:{}", n),
+            format!("{}k", kbps + kbps / 10),
+            format!("-bufsize:v:{}", n),
+            format!("{}k", kbps * 2),
+        ]);
+    }
+    if has_audio {
+        args.extend(["-c:a", "aac", "-ac", "2", "-b:a"].map(String::from));
+        args.push(format!("{}k", config.audio_kbps));
+    }
+
+    // Keyframes on segment boundaries, so every rendition switches at the same points
+    let secs = config.segment_secs;
+    args.extend([
+        String::from("-force_key_frames"),
+        format!("expr:gte(t,n_forced*{})", secs),
+    ]);
+    args.extend(["-f", "hls", "-hls_playlist_type", "vod"].map(String::from));
+    args.extend([
+        String::from("-hls_time"),
+        secs.to_string(),
+        String::from("-hls_flags"),
+        String::from("independent_segments"),
+    ]);
+    let extension = match config.segment_format {
+        SegmentFormat::Fmp4 => {
+            args.extend(
+                [
+                    "-hls_segment_type",
+                    "fmp4",
+                    "-hls_fmp4_init_filename",
+                    "%v_init.mp4",
+                ]
+                .map(String::from),
+            );
+            "m4s"
+        }
+        SegmentFormat::Ts => {
+            args.extend(["-hls_segment_type", "mpegts"].map(String::from));
+            "ts"
+        }
+    };
+    args.extend([
+        String::from("-hls_segment_filename"),
+        out.join(format!("%v_%05d.{}", extension))
+            .to_string_lossy()
+            .into_owned(),
+        String::from("-master_pl_name"),
+        String::from(MASTER_PLAYLIST),
+    ]);
+
+    // Audio gets a rendition of its own, as DASH wants one kind of media per representation
+    let group = if has_audio { ",agroup:audio" } else { "" };
+    let mut streams: Vec<String> = renditions
+        .iter()
+        .enumerate()
+        .map(|(n, rendition)| format!("v:{}{},name:{}", n, group, rendition.name))
+        .collect();
+    if has_audio {
+        streams.push(format!("a:0{},name:{}", group, AUDIO_RENDITION));
+    }
+    args.extend([String::from("-var_stream_map"), streams.join(" ")]);
+    args.push(out.join("%v.m3u8").to_string_lossy().into_owned());
+    args
+}
+
+/// `master` with each rendition's playlist addressed under `<sha256>/`. Everything in a package
+/// but the master playlist is then at a URL unique to its contents, which clients may cache for
+/// good.
+fn address_renditions(master: &str, sha256: &str) -> String {
+    master
+        .lines()
+        .map(|line| {
+            if line.starts_with("#EXT-X-MEDIA:") {
+                // The audio rendition, as a tag attribute
+                format!(
+                    "{}\n",
+                    line.replacen("URI=\"", &format!("URI=\"{}/", sha256), 1)
+                )
+            } else if line.is_empty() || line.starts_with('#') {
+                format!("{}\n", line)
+            } else {
+                format!("{}/{}\n", sha256, line)
+            }
+        })
+        .collect()
+}
+
+/// Move the package ffmpeg wrote into `out` to storage, along with its DASH `manifest` if it has
+/// one. The master playlist goes last, so that a package with one is complete.
+async fn store(
+    storage: &dyn StorageBackend,
+    sha256: &str,
+    out: &Path,
+    manifest: Option<String>,
+) -> anyhow::Result<()> {
+    let mut entries = tokio::fs::read_dir(out).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name == MASTER_PLAYLIST || content_type(&file_name).is_none() {
+            continue;
+        }
+        let mut file = tokio::fs::File::open(entry.path()).await?;
+        storage
+            .put_stream(&object_key(sha256, &file_name), &mut file)
+            .await?;
+    }
+    if let Some(manifest) = manifest {
+        storage
+            .put_stream(
+                &object_key(sha256, dash::MANIFEST),
+                &mut Cursor::new(manifest.into_bytes()),
+            )
+            .await?;
+    }
+
+    let master = tokio::fs::read_to_string(out.join(MASTER_PLAYLIST)).await?;
+    let master = address_renditions(&master, sha256);
+    storage
+        .put_stream(
+            &object_key(sha256, MASTER_PLAYLIST),
+            &mut Cursor::new(master.into_bytes()),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn remove_dir(dir: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_dir_all(dir).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Transcode the contents of `entry` into `out` and store the result.
+async fn transcode(
+    storage: &dyn StorageBackend,
+    ffmpeg: &str,
+    config: &HlsConfig,
+    entry: &ArchiveEntry,
+    sha256: &str,
+    out: &Path,
+) -> anyhow::Result<bool> {
+    let Some(height) = display_height(entry) else {
+        return Ok(false);
+    };
+    let renditions = select(&config.renditions, height);
+    let has_audio = entry.audio_codec.is_some();
+
+    let key = blob_key(sha256);
+    let mut command = ffmpeg_command(ffmpeg, storage, &key, &[]);
+    command.args(output_args(config, &renditions, has_audio, out));
+    let Some(mut child) = spawn_ffmpeg(&mut command)? else {
+        return Ok(false);
+    };
+
+    let feed = feed(child.stdin.take(), storage, &key);
+    let (fed, output) = tokio::time::timeout(TIMEOUT, async {
+        tokio::join!(feed, child.wait_with_output())
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("ffmpeg timed out"))?;
+    let output = output?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fed?;
+
+    // DASH clients can play the same segments, if they are fMP4
+    let manifest = match config.segment_format {
+        SegmentFormat::Fmp4 => {
+            let mut names: Vec<String> = renditions.into_iter().map(|r| r.name).collect();
+            if has_audio {
+                names.push(AUDIO_RENDITION.to_string());
+            }
+            let (out, base_url) = (out.to_owned(), format!("{}/", sha256));
+            let manifest = tokio::task::spawn_blocking(move || {
+                let names: Vec<&str> = names.iter().map(String::as_str).collect();
+                dash::manifest(&out, &names, &base_url)
+            })
+            .await??;
+            Some(manifest)
+        }
+        SegmentFormat::Ts => None,
+    };
+    store(storage, sha256, out, manifest).await?;
+    Ok(true)
+}
+
+/// Package the contents of `entry`, unless they have a package already or aren't a video.
+/// Returns whether one was made.
+pub async fn generate(
+    storage: &dyn StorageBackend,
+    config: &SharedConfig,
+    entry: &ArchiveEntry,
+) -> anyhow::Result<bool> {
+    let current = config.current();
+    let (config, ffmpeg) = (&current.hls, &current.thumbnails.ffmpeg);
+    if !config.enabled || ffmpeg.is_empty() || display_height(entry).is_none() {
+        return Ok(false);
+    }
+    let Some(sha256) = &entry.sha256 else {
+        return Ok(false);
+    };
+    if storage
+        .stat(&object_key(sha256, MASTER_PLAYLIST))
+        .await?
+        .is_some()
+    {
+        return Ok(false);
+    }
+    // Whatever an interrupted run left behind
+    remove(storage, sha256).await?;
+
+    let out = config.work_dir.join(sha256);
+    remove_dir(&out).await?;
+    tokio::fs::create_dir_all(&out).await?;
+    let packaged = transcode(storage, ffmpeg, config, entry, sha256, &out).await;
+    if let Err(err) = remove_dir(&out).await {
+        warn!("Failed to clean up {}: {}", out.display(), err);
+    }
+    if !packaged? {
+        remove(storage, sha256).await?;
+        return Ok(false);
+    }
+
+    // Deleted meanwhile, and its package with it had it been there
+    if storage.stat(&blob_key(sha256)).await?.is_none() {
+        remove(storage, sha256).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
@@ -0,0 +1,315 @@
+//! The persistent queue of processing each video goes through after upload: probing, then
+//! thumbnails, a storyboard and an HLS package once the probe has found what the video is.
+//!
+//! Jobs are kept in the catalog, so the queue survives restarts: jobs that were running are
+//! queued again at startup. Each kind runs at most `jobs.concurrency.<kind>` at a time, highest
+//! priority first; a failed attempt is retried with exponential backoff until
+//! `jobs.max_attempts`. Hashing stays with the upload itself, as contents are stored under their
+//! SHA-256 and that has to be known to commit them.
+
+use crate::{
+    catalog::{self, Catalog, NewJob, ProbeTarget},
+    config::{JobsConfig, SharedConfig},
+    hls,
+    models::{ArchiveEntry, Job, JobKind},
+    probe,
+    state::AppState,
+    storyboards, thumbnails,
+};
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{sync::Notify, task::AbortHandle};
+
+/// Jobs queued for uploads (or by hand) go ahead of those catching up on older videos.
+pub const DEFAULT_PRIORITY: i64 = 10;
+
+const BACKFILL_PRIORITY: i64 = 0;
+
+/// How often the queue looks for jobs that are due, besides whenever one is queued or finishes.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often jobs past `jobs.retention_days` are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Wait before retrying a job that has failed `attempts` times.
+fn backoff(config: &JobsConfig, attempts: u32) -> u64 {
+    let factor = 1u64
+        .checked_shl(attempts.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    config
+        .backoff_secs
+        .saturating_mul(factor)
+        .min(config.max_backoff_secs)
+}
+
+/// Runs queued jobs in the background, and tracks those running so they can be cancelled.
+pub struct JobQueue {
+    catalog: Catalog,
+    config: SharedConfig,
+    wake: Notify,
+    running: Mutex<HashMap<i64, (JobKind, AbortHandle)>>,
+}
+
+impl JobQueue {
+    pub fn new(catalog: Catalog, config: SharedConfig) -> Self {
+        Self {
+            catalog,
+            config,
+            wake: Notify::new(),
+            running: Mutex::default(),
+        }
+    }
+
+    /// Queue a `kind` job for the current contents of `entry`. Returns its id, or `None` if one
+    /// is queued or running already.
+    pub async fn enqueue(
+        &self,
+        kind: JobKind,
+        entry: &ArchiveEntry,
+        priority: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        let Some(sha256) = &entry.sha256 else {
+            return Ok(None);
+        };
+        let id = self
+            .catalog
+            .enqueue_job(NewJob {
+                kind,
+                video_id: entry.id,
+                sha256: sha256.clone(),
+                priority,
+                max_attempts: self.config.current().jobs.max_attempts,
+                created_at: catalog::unix_seconds(SystemTime::now()),
+            })
+            .await?;
+        if id.is_some() {
+            self.wake.notify_one();
+        }
+        Ok(id)
+    }
+
+    /// Cancel job `id`, stopping it if it is running. Returns the job as it is now, or `None` if
+    /// there is no such job.
+    pub async fn cancel(&self, id: i64) -> anyhow::Result<Option<Job>> {
+        let now = catalog::unix_seconds(SystemTime::now());
+        let job = self.catalog.cancel_job(id, now).await?;
+        self.abort(id);
+        Ok(job)
+    }
+
+    /// Cancel every unfinished job of video `video_id`, ahead of deleting it.
+    pub async fn cancel_video(&self, video_id: i64) -> anyhow::Result<()> {
+        let now = catalog::unix_seconds(SystemTime::now());
+        for id in self.catalog.cancel_video_jobs(video_id, now).await? {
+            self.abort(id);
+        }
+        Ok(())
+    }
+
+    /// Stop job `id` if it is running here. ffmpeg and ffprobe are killed as their handles drop.
+    fn abort(&self, id: i64) {
+        let stopped = self
+            .running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+        if let Some((_, task)) = stopped {
+            task.abort();
+            self.wake.notify_one();
+        }
+    }
+
+    fn running(&self, kind: JobKind) -> usize {
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|(running, _)| *running == kind)
+            .count()
+    }
+
+    /// Start running jobs, after queueing again those a restart interrupted and catching up on
+    /// videos archived before the queue.
+    pub fn spawn(self: Arc<Self>, state: AppState) {
+        tokio::spawn(async move {
+            if let Err(err) = self.recover().await {
+                warn!("Failed to restore the job queue: {:#}", err);
+            }
+
+            let mut pruned_at: Option<Instant> = None;
+            loop {
+                if pruned_at.is_none_or(|at| at.elapsed() >= PRUNE_INTERVAL) {
+                    if let Err(err) = self.prune().await {
+                        warn!("Failed to prune finished jobs: {:#}", err);
+                    }
+                    pruned_at = Some(Instant::now());
+                }
+                if let Err(err) = self.dispatch(&state).await {
+                    warn!("Failed to start queued jobs: {:#}", err);
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                    _ = self.wake.notified() => {}
+                }
+            }
+        });
+    }
+
+    async fn recover(&self) -> anyhow::Result<()> {
+        let now = catalog::unix_seconds(SystemTime::now());
+        let interrupted = self.catalog.requeue_interrupted(now).await?;
+        if interrupted > 0 {
+            info!("Queued {} interrupted job(s) again", interrupted);
+        }
+
+        // Thumbnails follow from probes, so only those need catching up on directly
+        let max_attempts = self.config.current().jobs.max_attempts;
+        for kind in [JobKind::Probe, JobKind::Storyboard, JobKind::Hls] {
+            let queued = self
+                .catalog
+                .backfill_jobs(kind, BACKFILL_PRIORITY, max_attempts, now)
+                .await?;
+            if queued > 0 {
+                info!(
+                    "Queued {} {} job(s) for previously archived files",
+                    queued,
+                    kind.as_str()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn prune(&self) -> anyhow::Result<()> {
+        let retention = self
+            .config
+            .current()
+            .jobs
+            .retention_days
+            .saturating_mul(24 * 60 * 60);
+        let retention = i64::try_from(retention).unwrap_or(i64::MAX);
+        let cutoff = catalog::unix_seconds(SystemTime::now()).saturating_sub(retention);
+        let pruned = self.catalog.prune_jobs(cutoff).await?;
+        if pruned > 0 {
+            info!("Forgot {} finished job(s)", pruned);
+        }
+        Ok(())
+    }
+
+    /// Start as many due jobs of each kind as its limit allows.
+    async fn dispatch(self: &Arc<Self>, state: &AppState) -> anyhow::Result<()> {
+        let limits = self.config.current().jobs.concurrency.clone();
+        for kind in JobKind::ALL {
+            while self.running(kind) < limits.of(kind) {
+                let now = catalog::unix_seconds(SystemTime::now());
+                let Some(job) = self.catalog.claim_job(kind, now).await? else {
+                    break;
+                };
+                self.start(state, job);
+            }
+        }
+        Ok(())
+    }
+
+    fn start(self: &Arc<Self>, state: &AppState, job: Job) {
+        // Held until the task is tracked, so it can't finish and untrack itself first
+        let mut running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        let (id, kind) = (job.id, job.kind);
+        let (queue, state) = (self.clone(), state.clone());
+        let task = tokio::spawn(async move {
+            let result = run(&state, &job).await;
+            queue.finish(&job, result).await;
+        });
+        running.insert(id, (kind, task.abort_handle()));
+    }
+
+    /// Record how an attempt of `job` went, and queue it again for a retry if it failed and has
+    /// attempts left.
+    async fn finish(&self, job: &Job, result: anyhow::Result<String>) {
+        let now = catalog::unix_seconds(SystemTime::now());
+        let recorded = match result {
+            Ok(message) => {
+                info!(
+                    "{} job of {}: {}",
+                    job.kind.as_str(),
+                    job.file_name,
+                    message
+                );
+                self.catalog.complete_job(job.id, &message, now).await
+            }
+            Err(err) => {
+                let error = format!("{:#}", err);
+                let config = self.config.current().jobs.clone();
+                let retry_at = (job.attempts < job.max_attempts)
+                    .then(|| now + backoff(&config, job.attempts) as i64);
+                match retry_at {
+                    Some(_) => warn!(
+                        "{} job of {} failed, will retry: {}",
+                        job.kind.as_str(),
+                        job.file_name,
+                        error
+                    ),
+                    None => warn!(
+                        "{} job of {} failed {} time(s), giving up: {}",
+                        job.kind.as_str(),
+                        job.file_name,
+                        job.attempts,
+                        error
+                    ),
+                }
+                self.catalog.fail_job(job.id, &error, retry_at, now).await
+            }
+        };
+        if let Err(err) = recorded {
+            warn!("Failed to record the outcome of job {}: {:#}", job.id, err);
+        }
+
+        self.running
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&job.id);
+        self.wake.notify_one();
+    }
+}
+
+/// Do the work of `job`. Returns what came of it, for its log.
+async fn run(state: &AppState, job: &Job) -> anyhow::Result<String> {
+    let entry = state.catalog.get_by_id(job.video_id).await?;
+    let Some(entry) = entry.filter(|entry| entry.sha256.as_deref() == Some(job.sha256.as_str()))
+    else {
+        return Ok(String::from("nothing to do, the video has new contents"));
+    };
+
+    let storage = state.storage.as_ref();
+    let done = match job.kind {
+        JobKind::Probe => {
+            let target = ProbeTarget {
+                file_name: entry.file_name.clone(),
+                sha256: job.sha256.clone(),
+                content_type: entry.content_type.clone(),
+            };
+            let Some(entry) = probe::probe_file(state, &target).await? else {
+                return Ok(String::from("nothing to do, the video has new contents"));
+            };
+            for kind in [JobKind::Thumbnails, JobKind::Storyboard, JobKind::Hls] {
+                state.jobs.enqueue(kind, &entry, job.priority).await?;
+            }
+            return Ok(match &entry.container {
+                Some(container) => format!("probed, {}", container),
+                None => String::from("probed, not media that can be read"),
+            });
+        }
+        JobKind::Thumbnails => thumbnails::generate(state, &entry).await?.is_some(),
+        JobKind::Storyboard => storyboards::generate(storage, &state.config, &entry).await?,
+        JobKind::Hls => hls::generate(storage, &state.config, &entry).await?,
+    };
+    Ok(String::from(match done {
+        true => "done",
+        false => "nothing to do",
+    }))
+}
@@ -1,189 +1,223 @@
+mod catalog;
+mod config;
+mod dash;
+mod error;
+mod faststart;
+mod fixity;
+mod handlers;
+mod hls;
+mod jobs;
 mod models;
+mod mp4;
+mod probe;
+mod state;
+mod storage;
+mod storyboards;
+mod subtitles;
+mod thumbnails;
+mod tus;
 mod utils;
 
 use axum::{
-    body::StreamBody,
-    extract::{DefaultBodyLimit, Multipart, Path},
-    http::{Method, StatusCode},
-    response::IntoResponse,
-    routing::{delete, get, post},
-    Json, Router,
+    extract::DefaultBodyLimit,
+    handler::Handler,
+    http::{header, Method},
+    middleware,
+    routing::{delete, get, head, post},
+    Router,
 };
-use http::HeaderMap;
-use log::info;
-use std::{net::SocketAddr, path::Path as FilePath, str::FromStr, time::Duration};
-use tokio::{
-    fs::{read_dir, remove_file, File},
-    io::AsyncWriteExt,
+use clap::Parser;
+use handlers::{
+    archive::archive_handler,
+    delete::delete_file_handler,
+    fallback_func,
+    fixity::{fixity_run_handler, fixity_status_handler, verify_file_handler},
+    hls::{dash_manifest_handler, hls_file_handler, hls_master_handler},
+    jobs::{job_cancel_handler, job_create_handler, job_handler, jobs_handler},
+    metadata::metadata_handler,
+    metrics::metrics_handler,
+    search::search_handler,
+    storyboard::{storyboard_sheet_handler, storyboard_track_handler},
+    stream::video_stream_handler,
+    subtitles::{subtitles_delete_handler, subtitles_handler, subtitles_upload_handler},
+    thumbnail::{poster_upload_handler, thumbnail_handler, MAX_POSTER_SIZE},
+    tus::{tus_create, tus_delete, tus_discovery, tus_head, tus_patch},
+    upload::video_upload_handler,
+    videos::{video_metadata_handler, video_metadata_patch_handler},
 };
-use tokio_util::io::ReaderStream;
+use log::info;
+use state::AppState;
+use std::{sync::Arc, time::Duration};
+use subtitles::MAX_SUBTITLE_SIZE;
 use tower_http::{
-    cors::{Any, CorsLayer},
+    cors::{AllowOrigin, Any, CorsLayer},
     trace::TraceLayer,
 };
 use tracing::Span;
 
-async fn fallback_func() -> (StatusCode, Json<models::ResponseError>) {
-    (
-        StatusCode::NOT_FOUND,
-        Json(models::ResponseError {
-            message: String::new(),
-            error: String::from("page not found"),
-        }),
-    )
-}
-
-async fn video_stream_handler(
-    Path(file_name): Path<String>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let file_path = format!("./archive/{}", file_name);
-
-    // Check if the file exists
-    if !FilePath::new(&file_path).exists() {
-        return Err(StatusCode::NOT_FOUND);
-    }
-
-    // Open the video file
-    let file = File::open(file_path)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Stream the file in chunks
-    let stream = ReaderStream::new(file);
-    let body = StreamBody::new(stream);
-
-    // Set appropriate headers
-    let mut response_headers = HeaderMap::new();
-    response_headers.insert("Content-Type", "video/mp4".parse().unwrap());
-    response_headers.insert("Accept-Ranges", "bytes".parse().unwrap());
-
-    Ok((response_headers, body))
-}
-
-// Function to save the file in chunks
-async fn save_file(
-    field: &mut axum::extract::multipart::Field<'_>,
-    file_path: &str,
-) -> anyhow::Result<()> {
-    let mut file = File::create(file_path).await?;
-
-    while let Some(chunk) = field.chunk().await? {
-        file.write_all(&chunk).await?;
-    }
-
-    Ok(())
-}
-
-async fn video_upload(mut multipart: Multipart) -> Result<(StatusCode, String), anyhow::Error> {
-    // Create archive directory if it doesn't exist
-    if !FilePath::new("./archive").exists() {
-        tokio::fs::create_dir("./archive")
-            .await
-            .expect("Failed to create archive directory");
-    }
-
-    while let Some(mut field) = multipart.next_field().await? {
-        let file_name = field
-            .file_name()
-            .ok_or_else(|| anyhow::anyhow!("Missing file name"))?
-            .to_string();
-
-        let file_path = format!("./archive/{}", file_name);
-        info!("Uploading file: {}", file_name);
-
-        // Attempt to save the file
-        save_file(&mut field, &file_path)
-            .await
-            .expect(&format!("Error uploading file: {}", file_name));
-        info!("File {} uploaded successfully", file_name);
-    }
-
-    // Return successful response
-    Ok((StatusCode::OK, "Video uploaded successfully".to_string()))
-}
-
-async fn video_upload_handler(multipart: Multipart) -> impl IntoResponse {
-    match video_upload(multipart).await {
-        Ok(response) => response,
-        Err(err) => {
-            eprintln!("Error: {}", err);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".to_string(),
-            )
-        }
-    }
-}
-
-async fn archive_handler() -> Result<(StatusCode, Json<models::ArchiveResponse>), StatusCode> {
-    std::fs::create_dir_all("./archive").expect("Failed to create archive directory!!");
-    let mut file_names = vec![];
-
-    // Read the directory contents
-    match read_dir("./archive").await {
-        Ok(mut entries) => {
-            while let Some(entry) = entries.next_entry().await.unwrap() {
-                if let Ok(file_name) = entry.file_name().into_string() {
-                    file_names.push(file_name);
-                }
-            }
-
-            // If the directory is empty, file_names will remain empty.
-            let response = models::ArchiveResponse { files: file_names };
-            Ok((StatusCode::OK, Json(response)))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
-}
-
-// New function to delete a file
-async fn delete_file_handler(
-    Path(file_name): Path<String>,
-) -> Result<(StatusCode, String), StatusCode> {
-    let file_path = format!("./archive/{}", file_name);
-
-    // Check if the file exists
-    if !FilePath::new(&file_path).exists() {
-        return Err(StatusCode::NOT_FOUND);
-    }
-
-    // Attempt to delete the file
-    match remove_file(&file_path).await {
-        Ok(_) => {
-            info!("File {} deleted successfully", file_name);
-            Ok((StatusCode::OK, "File deleted successfully".to_string()))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
-}
-
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = config::Cli::parse();
+    let config = config::Config::load(&cli)?;
+
     // Initialize logging
-    utils::logger::initialize();
+    let logger = utils::logger::initialize(&config.log)?;
+
+    let storage = storage::from_config(&config.storage)?;
+    let swept = storage::staging::sweep(storage.as_ref(), storage::staging::STALE_AFTER).await?;
+    if swept > 0 {
+        info!("Removed {} abandoned upload(s) from staging", swept);
+    }
+    let catalog = catalog::Catalog::open(&config.catalog.path)?;
+    catalog.reconcile(storage.as_ref()).await?;
+
+    let tus = Arc::new(tus::TusStore::new(&config.tus.dir)?);
+    tus.sweep_expired(&catalog).await?;
+    tus::spawn_sweeper(tus.clone(), catalog.clone());
+
+    let addr = config.server.bind;
+    let max_upload_size = config.max_upload_size();
+    let shared_config = config::SharedConfig::new(config);
+    #[cfg(unix)]
+    config::spawn_reloader(cli, shared_config.clone(), logger);
+
+    let scrubber = Arc::new(fixity::Scrubber::new(
+        storage.clone(),
+        catalog.clone(),
+        shared_config.clone(),
+    ));
+    scrubber.clone().spawn();
+    let jobs = Arc::new(jobs::JobQueue::new(catalog.clone(), shared_config.clone()));
+
+    let state = AppState {
+        storage,
+        catalog,
+        tus,
+        config: shared_config.clone(),
+        fixity: scrubber,
+        jobs,
+        commit_lock: Default::default(),
+    };
+    state.jobs.clone().spawn(state.clone());
+    handlers::tus::resume_archiving(&state).await?;
 
     // Configure CORS
     let cors = CorsLayer::new()
-        .allow_methods([Method::POST, Method::GET, Method::DELETE])
+        .allow_methods([
+            Method::POST,
+            Method::GET,
+            Method::HEAD,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .expose_headers([
+            header::ACCEPT_RANGES,
+            header::CONTENT_RANGE,
+            header::CONTENT_LENGTH,
+            header::ETAG,
+            header::LAST_MODIFIED,
+            header::LOCATION,
+            handlers::tus::TUS_RESUMABLE,
+            handlers::tus::TUS_VERSION_HEADER,
+            handlers::tus::TUS_EXTENSION,
+            handlers::tus::TUS_MAX_SIZE,
+            handlers::tus::UPLOAD_OFFSET,
+            handlers::tus::UPLOAD_LENGTH,
+            handlers::tus::UPLOAD_METADATA,
+            handlers::tus::UPLOAD_EXPIRES,
+            handlers::tus::ARCHIVE_STATUS,
+            handlers::tus::UPLOAD_OUTCOME,
+            handlers::tus::ARCHIVE_FILE_NAME,
+            handlers::tus::ARCHIVE_ERROR,
+            utils::digest::REPR_DIGEST,
+            utils::request_id::X_REQUEST_ID,
+        ])
         .allow_headers(Any)
-        .allow_origin(Any);
+        // The CORS layer replaces any Vary a handler sets, so /thumbnail's is listed here
+        .vary([
+            header::ORIGIN,
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            header::ACCEPT,
+        ])
+        // Re-checked per request so a reload can change the allowed origins
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            shared_config.current().cors.allows(origin.as_bytes())
+        }));
 
     // Create the Axum app
     let app = Router::new()
         .route("/upload", post(video_upload_handler))
         .route("/archive", get(archive_handler))
-        .route("/stream/:file_name", get(video_stream_handler))
+        .route(
+            "/stream/:file_name",
+            get(video_stream_handler).head(video_stream_handler),
+        )
+        .route(
+            "/stream/:file_name/storyboard.vtt",
+            get(storyboard_track_handler),
+        )
+        .route(
+            "/stream/:file_name/storyboard/:sheet",
+            get(storyboard_sheet_handler),
+        )
         .route("/delete/:file_name", delete(delete_file_handler)) // Add delete route
+        .route("/metadata/:file_name", get(metadata_handler))
+        .route("/verify/:file_name", post(verify_file_handler))
+        .route(
+            "/admin/fixity",
+            get(fixity_status_handler).post(fixity_run_handler),
+        )
+        .route(
+            "/thumbnail/:id",
+            get(thumbnail_handler)
+                .put(poster_upload_handler.layer(DefaultBodyLimit::max(MAX_POSTER_SIZE))),
+        )
+        .route("/hls/:id/master.m3u8", get(hls_master_handler))
+        .route("/hls/:id/:contents/:file", get(hls_file_handler))
+        .route("/dash/:id/manifest.mpd", get(dash_manifest_handler))
+        .route("/dash/:id/:contents/:file", get(hls_file_handler))
+        .route(
+            "/videos/:id",
+            get(video_metadata_handler).patch(video_metadata_patch_handler),
+        )
+        .route("/search", get(search_handler))
+        .route(
+            "/subtitles/:id/:language",
+            get(subtitles_handler)
+                .put(subtitles_upload_handler.layer(DefaultBodyLimit::max(MAX_SUBTITLE_SIZE)))
+                .delete(subtitles_delete_handler),
+        )
+        .route("/jobs", get(jobs_handler).post(job_create_handler))
+        .route("/jobs/:id", get(job_handler))
+        .route("/jobs/:id/cancel", post(job_cancel_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/files", post(tus_create))
+        .route(
+            "/files/:id",
+            head(tus_head).patch(tus_patch).delete(tus_delete),
+        )
         .fallback(fallback_func)
+        .with_state(state.clone())
         .layer(cors)
-        .layer(DefaultBodyLimit::max(20 * 1024 * 1024 * 1024)) // 20GB
+        .layer(middleware::from_fn_with_state(state, tus_discovery))
+        .layer(DefaultBodyLimit::max(max_upload_size as usize))
+        .layer(middleware::from_fn(utils::request_id::request_id))
         .layer(TraceLayer::new_for_http().on_response(
             |response: &http::Response<axum::body::BoxBody>, latency: Duration, span: &Span| {
                 let status = response.status();
+                let request_id = response
+                    .headers()
+                    .get(utils::request_id::X_REQUEST_ID)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("-");
 
                 // Log the response status and latency
                 info!(
-                    "Time: {:?}ms, Response Status: {}",
+                    "Request {}: Time: {:?}ms, Response Status: {}",
+                    request_id,
                     latency.as_millis(),
                     status.as_u16()
                 );
@@ -192,15 +226,12 @@ async fn main() -> anyhow::Result<()> {
             },
         ));
 
-    // Set the address to bind the server
-    let addr = SocketAddr::from_str("0.0.0.0:8080").unwrap();
     info!("Server started on http://{}\n", addr);
 
     // Start the server
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .await?;
 
     Ok(())
 }
@@ -0,0 +1,219 @@
+use super::{ConflictPolicy, StorageKey, StorageKeyError, UploadOutcome};
+use std::{collections::HashSet, fmt};
+
+/// An upload's name is taken and its conflict policy doesn't allow resolving that.
+#[derive(Debug)]
+pub struct UploadConflict(pub String);
+
+impl fmt::Display for UploadConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UploadConflict {}
+
+/// The stored name that `key` would clash with, compared case-insensitively.
+fn find_clash<'a>(key: &StorageKey, existing: &'a [String]) -> Option<&'a str> {
+    let folded = key.folded();
+    existing
+        .iter()
+        .map(String::as_str)
+        .find(|name| name.to_lowercase() == folded)
+}
+
+/// Whether `policy` can resolve a clash between `key` and `existing` at all.
+///
+/// Checked before any bytes are streamed so a doomed upload is refused up front, then again at
+/// commit time in case another upload took the name meanwhile.
+pub fn check_conflict(
+    key: &StorageKey,
+    policy: ConflictPolicy,
+    existing: &[String],
+) -> Result<(), UploadConflict> {
+    let Some(clash) = find_clash(key, existing) else {
+        return Ok(());
+    };
+
+    match policy {
+        ConflictPolicy::Reject => Err(UploadConflict(format!(
+            "'{}' already exists in the archive",
+            clash
+        ))),
+        ConflictPolicy::Version | ConflictPolicy::Overwrite if clash != key.as_str() => Err(
+            UploadConflict(format!("'{}' differs only by case from '{}'", key, clash)),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Where an upload named `key` is stored given the names in `existing`, and how any clash was
+/// resolved. A `Versioned` outcome leaves it to the caller to move the current file aside.
+pub fn resolve_conflict(
+    key: &StorageKey,
+    policy: ConflictPolicy,
+    existing: &[String],
+) -> anyhow::Result<(StorageKey, UploadOutcome)> {
+    check_conflict(key, policy, existing)?;
+
+    if find_clash(key, existing).is_none() {
+        return Ok((key.clone(), UploadOutcome::Created));
+    }
+    match policy {
+        ConflictPolicy::Rename => Ok((free_name(key, existing)?, UploadOutcome::Renamed)),
+        ConflictPolicy::Version => Ok((key.clone(), UploadOutcome::Versioned)),
+        ConflictPolicy::Overwrite => Ok((key.clone(), UploadOutcome::Overwritten)),
+        // Already refused by check_conflict
+        ConflictPolicy::Reject => {
+            Err(UploadConflict(format!("'{}' already exists in the archive", key)).into())
+        }
+    }
+}
+
+/// The first of `name (1).ext`, `name (2).ext`, ... that isn't taken.
+fn free_name(key: &StorageKey, existing: &[String]) -> Result<StorageKey, StorageKeyError> {
+    let taken: HashSet<String> = existing.iter().map(|name| name.to_lowercase()).collect();
+    let (stem, extension) = match key.as_str().rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (key.as_str(), String::new()),
+    };
+
+    let mut n = 1;
+    loop {
+        let candidate = StorageKey::parse(&format!("{} ({}){}", stem, n, extension))?;
+        if !taken.contains(&candidate.folded()) {
+            return Ok(candidate);
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::MAX_KEY_BYTES;
+
+    fn key(name: &str) -> StorageKey {
+        StorageKey::parse(name).unwrap()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn resolve(
+        name: &str,
+        policy: ConflictPolicy,
+        existing: &[&str],
+    ) -> anyhow::Result<(String, UploadOutcome)> {
+        resolve_conflict(&key(name), policy, &names(existing))
+            .map(|(target, outcome)| (target.to_string(), outcome))
+    }
+
+    #[test]
+    fn stores_free_names_as_they_are() {
+        for policy in [
+            ConflictPolicy::Reject,
+            ConflictPolicy::Rename,
+            ConflictPolicy::Version,
+            ConflictPolicy::Overwrite,
+        ] {
+            assert_eq!(
+                resolve("a.mp4", policy, &["b.mp4", "a.mp4.part", "a.mkv"]).unwrap(),
+                (String::from("a.mp4"), UploadOutcome::Created),
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn resolves_clashes_by_policy() {
+        let existing = ["a.mp4", "b.mp4"];
+        assert_eq!(
+            resolve("a.mp4", ConflictPolicy::Rename, &existing).unwrap(),
+            (String::from("a (1).mp4"), UploadOutcome::Renamed)
+        );
+        assert_eq!(
+            resolve("a.mp4", ConflictPolicy::Version, &existing).unwrap(),
+            (String::from("a.mp4"), UploadOutcome::Versioned)
+        );
+        assert_eq!(
+            resolve("a.mp4", ConflictPolicy::Overwrite, &existing).unwrap(),
+            (String::from("a.mp4"), UploadOutcome::Overwritten)
+        );
+
+        let err = resolve("a.mp4", ConflictPolicy::Reject, &existing).unwrap_err();
+        assert_eq!(
+            err.downcast::<UploadConflict>().unwrap().0,
+            "'a.mp4' already exists in the archive"
+        );
+    }
+
+    #[test]
+    fn clashes_ignore_case() {
+        let existing = names(&["Holiday.MP4"]);
+        for (policy, expected) in [
+            (
+                ConflictPolicy::Reject,
+                Some("'Holiday.MP4' already exists in the archive"),
+            ),
+            (ConflictPolicy::Rename, None),
+            (
+                ConflictPolicy::Version,
+                Some("'holiday.mp4' differs only by case from 'Holiday.MP4'"),
+            ),
+            (
+                ConflictPolicy::Overwrite,
+                Some("'holiday.mp4' differs only by case from 'Holiday.MP4'"),
+            ),
+        ] {
+            let result = check_conflict(&key("holiday.mp4"), policy, &existing);
+            assert_eq!(
+                result.err().map(|err| err.0),
+                expected.map(str::to_string),
+                "{:?}",
+                policy
+            );
+        }
+        assert_eq!(
+            resolve("holiday.mp4", ConflictPolicy::Rename, &["Holiday.MP4"]).unwrap(),
+            (String::from("holiday (1).mp4"), UploadOutcome::Renamed)
+        );
+    }
+
+    #[test]
+    fn renames_to_the_first_free_name() {
+        for (name, existing, expected) in [
+            ("a.mp4", &["a.mp4"][..], "a (1).mp4"),
+            ("a.mp4", &["a.mp4", "a (1).mp4", "A (2).MP4"], "a (3).mp4"),
+            ("a.mp4", &["a.mp4", "a (2).mp4"], "a (1).mp4"),
+            ("archive.tar.gz", &["archive.tar.gz"], "archive.tar (1).gz"),
+            ("README", &["README"], "README (1)"),
+            ("README", &["README", "README (1)"], "README (2)"),
+            ("a (1).mp4", &["a (1).mp4"], "a (1) (1).mp4"),
+        ] {
+            assert_eq!(
+                resolve(name, ConflictPolicy::Rename, existing).unwrap(),
+                (expected.to_string(), UploadOutcome::Renamed),
+                "{:?} {:?}",
+                name,
+                existing
+            );
+        }
+    }
+
+    #[test]
+    fn never_renames_to_a_dotfile_or_an_invalid_name() {
+        // Dot-prefixed names can't be uploaded, so there is no stem to lose
+        assert_eq!(StorageKey::parse(".profile"), Err(StorageKeyError::Hidden));
+
+        // A name too long for a suffix can't be renamed
+        let long = format!("{}.mp4", "x".repeat(MAX_KEY_BYTES - 4));
+        let err = resolve(&long, ConflictPolicy::Rename, &[long.as_str()]).unwrap_err();
+        assert!(matches!(
+            err.downcast::<StorageKeyError>(),
+            Ok(StorageKeyError::TooLong(_))
+        ));
+    }
+}
@@ -1,3 +1,9 @@
+pub mod conflict;
+pub mod storage_key;
 pub mod types;
+pub mod video_metadata;
 
+pub use conflict::*;
+pub use storage_key::*;
 pub use types::*;
+pub use video_metadata::*;
@@ -0,0 +1,272 @@
+use crate::error::AppError;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::request::Parts,
+};
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+/// Most filesystems cap a single path component at 255 bytes.
+pub const MAX_KEY_BYTES: usize = 255;
+
+/// Characters that are unsafe in a file name on at least one platform we might sync the archive to.
+const RESERVED_CHARS: &[char] = &['/', '\\', '<', '>', ':', '"', '|', '?', '*'];
+
+/// Device names Windows refuses to create, with or without an extension.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A client-supplied file name that is safe to use as a single component under the archive root.
+///
+/// Every handler that turns a name into a storage location must go through [`StorageKey::parse`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StorageKey(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageKeyError {
+    Empty,
+    TooLong(usize),
+    DotSegment,
+    Hidden,
+    ReservedChar(char),
+    ControlChar,
+    ReservedName(String),
+    TrailingDotOrSpace,
+}
+
+impl StorageKey {
+    /// Validate and normalize a client-supplied name into its canonical (NFC) form.
+    pub fn parse(raw: &str) -> Result<Self, StorageKeyError> {
+        let name: String = raw.trim().nfc().collect();
+
+        if name.is_empty() {
+            return Err(StorageKeyError::Empty);
+        }
+        if name.len() > MAX_KEY_BYTES {
+            return Err(StorageKeyError::TooLong(name.len()));
+        }
+        if name == "." || name == ".." {
+            return Err(StorageKeyError::DotSegment);
+        }
+        // Dot-prefixed names are reserved for the archive's own bookkeeping
+        if name.starts_with('.') {
+            return Err(StorageKeyError::Hidden);
+        }
+        if let Some(c) = name.chars().find(|c| RESERVED_CHARS.contains(c)) {
+            return Err(StorageKeyError::ReservedChar(c));
+        }
+        if name.chars().any(is_control) {
+            return Err(StorageKeyError::ControlChar);
+        }
+        if name.ends_with('.') || name.ends_with(' ') {
+            return Err(StorageKeyError::TrailingDotOrSpace);
+        }
+
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+            return Err(StorageKeyError::ReservedName(stem.to_string()));
+        }
+
+        Ok(Self(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Case-folded form used to detect names that would collide on case-insensitive filesystems.
+    pub fn folded(&self) -> String {
+        self.0.to_lowercase()
+    }
+}
+
+/// C0/C1 controls plus the bidirectional overrides that can disguise an extension.
+fn is_control(c: char) -> bool {
+    c.is_control()
+        || matches!(c, '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+impl fmt::Display for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for StorageKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for StorageKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "file name is empty"),
+            Self::TooLong(len) => write!(
+                f,
+                "file name is {} bytes, the limit is {}",
+                len, MAX_KEY_BYTES
+            ),
+            Self::DotSegment => write!(f, "file name may not be '.' or '..'"),
+            Self::Hidden => write!(f, "file name may not start with '.'"),
+            Self::ReservedChar(c) => write!(f, "file name may not contain {:?}", c),
+            Self::ControlChar => write!(f, "file name may not contain control characters"),
+            Self::ReservedName(name) => write!(f, "'{}' is a reserved device name", name),
+            Self::TrailingDotOrSpace => write!(f, "file name may not end with '.' or a space"),
+        }
+    }
+}
+
+impl std::error::Error for StorageKeyError {}
+
+/// Extracts a `:file_name` path parameter as a validated [`StorageKey`].
+pub struct KeyPath(pub StorageKey);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for KeyPath {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(file_name) = Path::<String>::from_request_parts(parts, state).await?;
+
+        Ok(KeyPath(StorageKey::parse(&file_name)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<String, StorageKeyError> {
+        StorageKey::parse(raw).map(|key| key.as_str().to_string())
+    }
+
+    #[test]
+    fn accepts_ordinary_names() {
+        for (raw, expected) in [
+            ("video.mp4", "video.mp4"),
+            ("  padded.mp4  ", "padded.mp4"),
+            ("no extension", "no extension"),
+            ("a..b.mp4", "a..b.mp4"),
+            ("Ünïcödé 動画.mkv", "Ünïcödé 動画.mkv"),
+            ("console.mp4", "console.mp4"),
+            ("COM10.mp4", "COM10.mp4"),
+        ] {
+            assert_eq!(parse(raw).as_deref(), Ok(expected), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_traversal_and_separators() {
+        for (raw, expected) in [
+            ("", StorageKeyError::Empty),
+            ("   ", StorageKeyError::Empty),
+            (".", StorageKeyError::DotSegment),
+            ("..", StorageKeyError::DotSegment),
+            (" .. ", StorageKeyError::DotSegment),
+            (".hidden", StorageKeyError::Hidden),
+            (".storyboards", StorageKeyError::Hidden),
+            ("a/../b", StorageKeyError::ReservedChar('/')),
+            ("../etc/passwd", StorageKeyError::Hidden),
+            ("/etc/passwd", StorageKeyError::ReservedChar('/')),
+            ("a/b.mp4", StorageKeyError::ReservedChar('/')),
+            ("..\\windows", StorageKeyError::Hidden),
+            ("a\\b.mp4", StorageKeyError::ReservedChar('\\')),
+            ("C:\\video.mp4", StorageKeyError::ReservedChar(':')),
+            ("C:video.mp4", StorageKeyError::ReservedChar(':')),
+            ("a<b", StorageKeyError::ReservedChar('<')),
+            ("a\"b", StorageKeyError::ReservedChar('"')),
+            ("what?.mp4", StorageKeyError::ReservedChar('?')),
+            ("*.mp4", StorageKeyError::ReservedChar('*')),
+            ("a|b", StorageKeyError::ReservedChar('|')),
+        ] {
+            assert_eq!(parse(raw), Err(expected), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_control_and_bidi_characters() {
+        for raw in [
+            "a\0b.mp4",
+            "a\nb.mp4",
+            "a\tb.mp4",
+            "a\u{7f}b.mp4",
+            "a\u{85}b.mp4",
+            "a\u{200e}b.mp4",
+            "a\u{200f}b.mp4",
+            // Right-to-left override: "gpj.exe" shown as "exe.jpg"
+            "invoice\u{202e}gpj.exe",
+            "a\u{2066}b.mp4",
+            "a\u{2069}b.mp4",
+        ] {
+            assert_eq!(parse(raw), Err(StorageKeyError::ControlChar), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn normalizes_to_nfc() {
+        let nfd = "Cafe\u{301}.mp4";
+        let nfc = "Caf\u{e9}.mp4";
+        assert_eq!(parse(nfd).as_deref(), Ok(nfc));
+        assert_eq!(
+            StorageKey::parse(nfd).unwrap(),
+            StorageKey::parse(nfc).unwrap()
+        );
+        assert_eq!(
+            StorageKey::parse("VIDEO.MP4").unwrap().folded(),
+            "video.mp4"
+        );
+    }
+
+    #[test]
+    fn limits_names_to_255_bytes() {
+        assert!(parse(&"a".repeat(MAX_KEY_BYTES)).is_ok());
+        assert_eq!(
+            parse(&"a".repeat(MAX_KEY_BYTES + 1)),
+            Err(StorageKeyError::TooLong(MAX_KEY_BYTES + 1))
+        );
+        // Bytes, not characters: 'é' is two in UTF-8
+        assert!(parse(&"é".repeat(127)).is_ok());
+        assert_eq!(parse(&"é".repeat(128)), Err(StorageKeyError::TooLong(256)));
+        assert!(parse(&"動".repeat(85)).is_ok());
+        assert_eq!(parse(&"動".repeat(86)), Err(StorageKeyError::TooLong(258)));
+        // Measured after normalizing: 300 bytes of NFD are 200 of NFC
+        assert!(parse(&"e\u{301}".repeat(100)).is_ok());
+    }
+
+    #[test]
+    fn rejects_trailing_dots_and_spaces() {
+        for raw in ["video.", "video..", "video .", "video. ", "video.mp4."] {
+            assert_eq!(
+                parse(raw),
+                Err(StorageKeyError::TrailingDotOrSpace),
+                "{:?}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_reserved_device_names() {
+        for (raw, stem) in [
+            ("CON", "CON"),
+            ("con", "con"),
+            ("nul.txt", "nul"),
+            ("NUL.tar.gz", "NUL"),
+            ("aux .mp4", "aux"),
+            ("Com1.mp4", "Com1"),
+            ("lpt9", "lpt9"),
+            ("PRN.mp4", "PRN"),
+        ] {
+            assert_eq!(
+                parse(raw),
+                Err(StorageKeyError::ReservedName(stem.to_string())),
+                "{:?}",
+                raw
+            );
+        }
+    }
+}
@@ -1,23 +1,560 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
+/// Body of every error response.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ResponseError {
+    /// Stable, machine-readable error code, e.g. `file_exists`.
+    pub code: String,
     pub message: String,
-    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
+/// One page of the archive listing.
 #[derive(Debug, Serialize)]
 pub struct ArchiveResponse {
+    pub files: Vec<ArchiveEntry>,
+    /// Files matching the filters, across every page.
+    pub total: u64,
+    /// Pass as `cursor` for the page after this one; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// What the archive listing is ordered by. Ties are broken by id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveSort {
+    #[default]
+    Name,
+    Size,
+    UploadedAt,
+    /// Files of unknown duration come before every other.
+    Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// A catalog record for one archived video.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveEntry {
+    pub id: i64,
+    pub file_name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub content_type: String,
+    pub size: u64,
+    /// Unix timestamp, seconds.
+    pub uploaded_at: i64,
+    pub uploader: Option<String>,
+    /// Hex digests of the contents.
+    pub sha256: Option<String>,
+    pub md5: Option<String>,
+    /// SHA-256 of the contents as uploaded, when ingest rewrote them for streaming.
+    pub original_sha256: Option<String>,
+    /// Result of the last fixity check of the contents, and when it ran.
+    pub fixity: Option<Fixity>,
+    pub fixity_checked_at: Option<i64>,
+    /// What probing the contents found; all `None` until probed, or if they aren't media.
+    pub duration_ms: Option<i64>,
+    /// e.g. `mp4`, `mov`, `matroska`, `webm`.
+    pub container: Option<String>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    /// Overall, bits per second.
+    pub bit_rate: Option<u64>,
+    /// Clockwise degrees the video must be turned for display.
+    pub rotation: Option<i32>,
+    /// When the recording was made, as set through `/videos/:id` or else as the device noted
+    /// it; Unix seconds.
+    pub recorded_at: Option<i64>,
+    /// Where the poster and its thumbnails came from; `None` while there are none.
+    pub poster: Option<Poster>,
+    /// Background processing of the current contents: the most pressing state among the latest
+    /// job of each kind, `running` first, then `queued`, `failed`, `cancelled` and `succeeded`.
+    /// `None` if no job was ever queued for them.
+    pub processing: Option<JobState>,
+    /// Starts at 1 and goes up each time a new upload supersedes this file under the `version` policy.
+    pub version: i64,
+    pub tags: Vec<String>,
+    pub source: Option<String>,
+    pub license: Option<String>,
+    /// Free-form fields set through `/videos/:id`.
+    pub custom: BTreeMap<String, String>,
+    /// Languages of the attached subtitles, served at `/subtitles/:id/:language`.
+    pub subtitles: Vec<String>,
+}
+
+/// What probing a file's contents found.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MediaInfo {
+    pub container: Option<String>,
+    pub duration_ms: Option<i64>,
+    /// Overall, bits per second.
+    pub bit_rate: Option<u64>,
+    /// Unix seconds.
+    pub recorded_at: Option<i64>,
+    /// Where the recording was made, as the device noted it.
+    pub location: Option<Location>,
+    pub tracks: Vec<MediaTrack>,
+    /// `native` or `ffprobe`.
+    pub probed_with: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    Other,
+}
+
+/// One stream of a media file. Fields that don't apply to its kind are `None`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MediaTrack {
+    pub kind: TrackKind,
+    /// Named as ffprobe names it, e.g. `h264`, `hevc`, `aac`.
+    pub codec: Option<String>,
+    pub language: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub rotation: Option<i32>,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+}
+
+/// A point on the globe, in decimal degrees and metres.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+impl Location {
+    /// Parse the decimal-degree form of ISO 6709 that cameras and phones write, e.g.
+    /// `+37.3861-122.0839+010.000/`.
+    pub fn from_iso6709(text: &str) -> Option<Self> {
+        let text = text.trim().trim_end_matches('/');
+        let mut parts = vec![];
+        let mut start = 0;
+        for (i, c) in text.char_indices().skip(1) {
+            if c == '+' || c == '-' {
+                parts.push(&text[start..i]);
+                start = i;
+            }
+        }
+        parts.push(&text[start..]);
+
+        let (latitude, longitude, altitude) = match parts.as_slice() {
+            [latitude, longitude] => (latitude, longitude, None),
+            [latitude, longitude, altitude] => (latitude, longitude, Some(altitude)),
+            _ => return None,
+        };
+        let coordinate = |part: &str| {
+            part.starts_with(['+', '-'])
+                .then(|| part.parse::<f64>().ok())
+                .flatten()
+                .filter(|value| value.is_finite())
+        };
+
+        let latitude = coordinate(latitude).filter(|value| value.abs() <= 90.0)?;
+        let longitude = coordinate(longitude).filter(|value| value.abs() <= 180.0)?;
+        let altitude = match altitude {
+            Some(altitude) => Some(coordinate(altitude)?),
+            None => None,
+        };
+        Some(Self {
+            latitude,
+            longitude,
+            altitude,
+        })
+    }
+}
+
+impl MediaInfo {
+    pub fn first_track(&self, kind: TrackKind) -> Option<&MediaTrack> {
+        self.tracks.iter().find(|track| track.kind == kind)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetadataResponse {
+    pub file_name: String,
+    pub sha256: Option<String>,
+    /// Unix seconds; `None` if the file hasn't been probed yet.
+    pub probed_at: Option<i64>,
+    /// `None` if the contents aren't media we could read.
+    pub media: Option<MediaInfo>,
+}
+
+/// What to do when an upload's name is already taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Refuse the upload with 409 Conflict.
+    Reject,
+    /// Store under the first free `name (n).ext`.
+    Rename,
+    /// Keep the existing file as a previous version and make the upload current.
+    Version,
+    /// Replace the existing file. Only ever taken from an explicit per-request flag.
+    Overwrite,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "rename" => Ok(Self::Rename),
+            "version" => Ok(Self::Version),
+            "overwrite" => Ok(Self::Overwrite),
+            other => Err(format!(
+                "unknown conflict policy '{}', expected reject, rename, version or overwrite",
+                other
+            )),
+        }
+    }
+}
+
+/// Which way an upload was stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadOutcome {
+    Created,
+    Renamed,
+    Versioned,
+    Overwritten,
+}
+
+impl UploadOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Renamed => "renamed",
+            Self::Versioned => "versioned",
+            Self::Overwritten => "overwritten",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadedFile {
+    /// The name the client asked for.
+    pub requested_name: String,
+    pub outcome: UploadOutcome,
+    pub file: ArchiveEntry,
+    /// Hex digests the server computed over this file as received, by algorithm name.
+    pub digests: BTreeMap<&'static str, String>,
+    /// Algorithms of the client-supplied part digests that matched.
+    pub verified: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadResponse {
+    pub files: Vec<UploadedFile>,
+    /// Algorithms of the client-supplied request body digests that matched.
+    pub verified: Vec<&'static str>,
+}
+
+/// Whether stored contents still match the digests recorded at ingest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fixity {
+    Ok,
+    /// The contents no longer hash to what was recorded.
+    Mismatch,
+    /// The contents are gone from storage.
+    Missing,
+}
+
+impl Fixity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Mismatch => "mismatch",
+            Self::Missing => "missing",
+        }
+    }
+
+    pub fn is_damaged(self) -> bool {
+        self != Self::Ok
+    }
+}
+
+impl std::str::FromStr for Fixity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ok" => Ok(Self::Ok),
+            "mismatch" => Ok(Self::Mismatch),
+            "missing" => Ok(Self::Missing),
+            other => Err(format!("unknown fixity '{}'", other)),
+        }
+    }
+}
+
+/// Where a video's poster image came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Poster {
+    /// A frame grabbed from the video.
+    Generated,
+    /// Uploaded by a client; kept when the video's contents are replaced.
+    Custom,
+}
+
+impl Poster {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Generated => "generated",
+            Self::Custom => "custom",
+        }
+    }
+}
+
+impl std::str::FromStr for Poster {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "generated" => Ok(Self::Generated),
+            "custom" => Ok(Self::Custom),
+            other => Err(format!("unknown poster '{}'", other)),
+        }
+    }
+}
+
+/// An encoding thumbnails are served in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    Jpeg,
+    Webp,
+}
+
+impl ThumbnailFormat {
+    pub const ALL: [Self; 2] = [Self::Jpeg, Self::Webp];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// A blob whose last fixity check failed.
+#[derive(Debug, Serialize)]
+pub struct FlaggedBlob {
+    pub sha256: String,
+    pub size: u64,
+    pub fixity: Fixity,
+    pub detail: Option<String>,
+    pub checked_at: i64,
+    /// Every file whose current contents or kept versions are this blob.
     pub files: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct File {
-    pub filetype: String,
-    pub contents: String,
+/// Progress of one pass of the fixity scrubber.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrubReport {
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    /// Started by hand, covering every blob rather than only those due.
+    pub forced: bool,
+    /// Blobs to check in this pass.
+    pub due: u64,
+    pub ok: u64,
+    pub mismatched: u64,
+    pub missing: u64,
+    /// Checks that could not complete, e.g. storage errors; retried on the next pass.
+    pub errors: u64,
+    pub bytes_read: u64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct FileUpload {
-    pub file: File,
+#[derive(Debug, Serialize)]
+pub struct FixityResponse {
+    pub running: bool,
+    pub current: Option<ScrubReport>,
+    pub last: Option<ScrubReport>,
+    pub flagged: Vec<FlaggedBlob>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VerifyResponse {
+    pub file_name: String,
+    pub sha256: String,
+    pub fixity: Fixity,
+    pub detail: Option<String>,
+    pub checked_at: i64,
+}
+
+/// Processing a video goes through after upload, each step a job of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// Read media metadata; queues the other kinds when it succeeds.
+    Probe,
+    Thumbnails,
+    Storyboard,
+    Hls,
+}
+
+impl JobKind {
+    pub const ALL: [Self; 4] = [Self::Probe, Self::Thumbnails, Self::Storyboard, Self::Hls];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Probe => "probe",
+            Self::Thumbnails => "thumbnails",
+            Self::Storyboard => "storyboard",
+            Self::Hls => "hls",
+        }
+    }
+}
+
+impl std::str::FromStr for JobKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+            .ok_or_else(|| format!("unknown job kind '{}'", value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    /// Waiting for its turn, or for a retry after failing.
+    Queued,
+    Running,
+    Succeeded,
+    /// Failed on every attempt it was allowed.
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn is_finished(self) -> bool {
+        !matches!(self, Self::Queued | Self::Running)
+    }
+}
+
+impl std::str::FromStr for JobState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "queued" => Ok(Self::Queued),
+            "running" => Ok(Self::Running),
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            "cancelled" => Ok(Self::Cancelled),
+            other => Err(format!("unknown job state '{}'", other)),
+        }
+    }
+}
+
+/// One step of processing one video's contents.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: i64,
+    pub kind: JobKind,
+    pub video_id: i64,
+    pub file_name: String,
+    /// The contents it processes; a job whose video has new contents by the time it runs has
+    /// nothing to do.
+    pub sha256: String,
+    pub state: JobState,
+    /// Higher runs first among queued jobs of the same kind.
+    pub priority: i64,
+    /// Attempts started so far, and how many it gets before it is given up on.
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// Unix seconds.
+    pub created_at: i64,
+    /// Not started before this, Unix seconds; later than `created_at` while backing off.
+    pub run_after: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Why the last attempt failed.
+    pub error: Option<String>,
+    /// Only in responses about a single job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<Vec<JobLogEntry>>,
+}
+
+/// Something that happened to a job, e.g. an attempt starting or failing.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobLogEntry {
+    /// Unix seconds.
+    pub at: i64,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobsResponse {
+    pub jobs: Vec<Job>,
+}
+
+/// Videos matching a search, best first.
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+}
+
+/// Snippets are HTML: the text is escaped and matches are marked `<mark>…</mark>`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub file: ArchiveEntry,
+    /// Relevance, higher is better; only comparable within one search.
+    pub score: f64,
+    /// Where the name, title, description or tags matched, if they did.
+    pub snippet: Option<String>,
+    /// Best matching cues of the video's subtitles, in order of time.
+    pub subtitle_hits: Vec<SubtitleHit>,
+}
+
+/// A subtitle cue that matched, timed so a player can seek `/stream` straight to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubtitleHit {
+    pub language: String,
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub snippet: String,
 }
@@ -0,0 +1,494 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+pub const MAX_TITLE_CHARS: usize = 500;
+
+pub const MAX_DESCRIPTION_CHARS: usize = 10_000;
+
+/// For `source` and `license`.
+pub const MAX_FIELD_CHARS: usize = 1000;
+
+pub const MAX_TAGS: usize = 50;
+
+pub const MAX_TAG_CHARS: usize = 64;
+
+pub const MAX_CUSTOM_FIELDS: usize = 50;
+
+pub const MAX_CUSTOM_KEY_CHARS: usize = 64;
+
+pub const MAX_CUSTOM_VALUE_CHARS: usize = 2000;
+
+/// What users say about a video, as opposed to what is found from its contents. It is kept when
+/// the contents are replaced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VideoMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    /// When the recording was made, Unix seconds. Takes the place of the date found by probing.
+    pub recorded_at: Option<i64>,
+    /// Where the video came from, e.g. who filmed it or the collection it belongs to.
+    pub source: Option<String>,
+    pub license: Option<String>,
+    /// Anything else, as free-form text values.
+    pub custom: BTreeMap<String, String>,
+}
+
+/// A change to [`VideoMetadata`], in the manner of a JSON merge patch (RFC 7396): fields left out
+/// stay as they are, `null` clears them. `tags` replaces the whole list, while `custom` is merged
+/// key by key.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetadataPatch {
+    #[serde(default, deserialize_with = "present")]
+    pub title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub tags: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub recorded_at: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    pub source: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub license: Option<Option<String>>,
+    #[serde(default)]
+    pub custom: BTreeMap<String, Option<String>>,
+}
+
+/// Tells a field sent as `null` (`Some(None)`) from one left out (`None`, by `default`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// `value` trimmed, or `None` if that leaves nothing. Line breaks and tabs are only allowed where
+/// `multiline`.
+fn clean(
+    field: &str,
+    value: Option<String>,
+    max_chars: usize,
+    multiline: bool,
+) -> Result<Option<String>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if value.chars().count() > max_chars {
+        return Err(format!("{} is longer than {} characters", field, max_chars));
+    }
+    if value
+        .chars()
+        .any(|c| c.is_control() && !(multiline && matches!(c, '\n' | '\r' | '\t')))
+    {
+        return Err(format!("{} contains control characters", field));
+    }
+    Ok(Some(value.to_string()))
+}
+
+fn valid_custom_key(key: &str) -> bool {
+    (1..=MAX_CUSTOM_KEY_CHARS).contains(&key.len())
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.'))
+}
+
+impl MetadataPatch {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.description.is_none()
+            && self.tags.is_none()
+            && self.recorded_at.is_none()
+            && self.source.is_none()
+            && self.license.is_none()
+            && self.custom.is_empty()
+    }
+
+    /// Set the field a multipart upload sent as `name`: one of the fields of [`VideoMetadata`],
+    /// `tags` as a comma-separated list (or repeated), or `custom.<key>`.
+    pub fn set_form_field(&mut self, name: &str, value: String) -> Result<(), String> {
+        match name {
+            "title" => self.title = Some(Some(value)),
+            "description" => self.description = Some(Some(value)),
+            "source" => self.source = Some(Some(value)),
+            "license" => self.license = Some(Some(value)),
+            "recorded_at" => {
+                let recorded_at = match value.trim() {
+                    "" => None,
+                    value => Some(value.parse().map_err(|_| {
+                        String::from("recorded_at must be a Unix timestamp in seconds")
+                    })?),
+                };
+                self.recorded_at = Some(recorded_at);
+            }
+            "tags" => self
+                .tags
+                .get_or_insert_with(|| Some(vec![]))
+                .get_or_insert_with(Vec::new)
+                .extend(value.split(',').map(str::to_string)),
+            _ => match name.strip_prefix("custom.") {
+                Some(key) => {
+                    self.custom.insert(key.to_string(), Some(value));
+                }
+                None => return Err(format!("unknown metadata field '{}'", name)),
+            },
+        }
+        Ok(())
+    }
+
+    /// Apply the patch to `metadata`, checking what it sets. `metadata` is left as it was if the
+    /// patch isn't valid.
+    pub fn apply(self, metadata: &mut VideoMetadata) -> Result<(), String> {
+        let mut patched = metadata.clone();
+        if let Some(title) = self.title {
+            patched.title = clean("title", title, MAX_TITLE_CHARS, false)?;
+        }
+        if let Some(description) = self.description {
+            patched.description = clean("description", description, MAX_DESCRIPTION_CHARS, true)?;
+        }
+        if let Some(source) = self.source {
+            patched.source = clean("source", source, MAX_FIELD_CHARS, false)?;
+        }
+        if let Some(license) = self.license {
+            patched.license = clean("license", license, MAX_FIELD_CHARS, false)?;
+        }
+        if let Some(recorded_at) = self.recorded_at {
+            patched.recorded_at = recorded_at;
+        }
+
+        if let Some(tags) = self.tags {
+            patched.tags.clear();
+            for tag in tags.unwrap_or_default() {
+                let Some(tag) = clean("a tag", Some(tag), MAX_TAG_CHARS, false)? else {
+                    continue;
+                };
+                if !patched.tags.contains(&tag) {
+                    patched.tags.push(tag);
+                }
+            }
+            if patched.tags.len() > MAX_TAGS {
+                return Err(format!("a video can have at most {} tags", MAX_TAGS));
+            }
+        }
+
+        for (key, value) in self.custom {
+            if !valid_custom_key(&key) {
+                return Err(format!(
+                    "custom field names must be 1 to {} letters, digits, '_', '-' or '.'",
+                    MAX_CUSTOM_KEY_CHARS
+                ));
+            }
+            let field = format!("custom field '{}'", key);
+            match clean(&field, value, MAX_CUSTOM_VALUE_CHARS, true)? {
+                Some(value) => patched.custom.insert(key, value),
+                None => patched.custom.remove(&key),
+            };
+        }
+        if patched.custom.len() > MAX_CUSTOM_FIELDS {
+            return Err(format!(
+                "a video can have at most {} custom fields",
+                MAX_CUSTOM_FIELDS
+            ));
+        }
+
+        *metadata = patched;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(json: &str) -> MetadataPatch {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn metadata() -> VideoMetadata {
+        VideoMetadata {
+            title: Some(String::from("Title")),
+            description: Some(String::from("Line one\nLine two")),
+            tags: vec![String::from("b"), String::from("a")],
+            recorded_at: Some(1_700_000_000),
+            source: Some(String::from("Camera")),
+            license: Some(String::from("CC-BY-4.0")),
+            custom: [(String::from("reel"), String::from("3"))].into(),
+        }
+    }
+
+    #[test]
+    fn tells_null_from_missing() {
+        let empty = patch("{}");
+        assert!(empty.is_empty());
+        assert_eq!(empty.title, None);
+
+        let cleared = patch(r#"{"title": null, "tags": null, "custom": {"reel": null}}"#);
+        assert!(!cleared.is_empty());
+        assert_eq!(cleared.title, Some(None));
+        assert_eq!(cleared.tags, Some(None));
+        assert_eq!(cleared.custom["reel"], None);
+
+        assert!(serde_json::from_str::<MetadataPatch>(r#"{"name": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn applies_patches() {
+        for (json, expected) in [
+            ("{}", metadata()),
+            (
+                r#"{"title": "  New  ", "recorded_at": 5}"#,
+                VideoMetadata {
+                    title: Some(String::from("New")),
+                    recorded_at: Some(5),
+                    ..metadata()
+                },
+            ),
+            (
+                r#"{"title": null, "description": "   ", "recorded_at": null, "source": null,
+                    "license": null, "tags": null, "custom": {"reel": null, "absent": null}}"#,
+                VideoMetadata::default(),
+            ),
+            (
+                r#"{"tags": [" z ", "y", "z", "", "x"]}"#,
+                VideoMetadata {
+                    tags: vec![String::from("z"), String::from("y"), String::from("x")],
+                    ..metadata()
+                },
+            ),
+            (
+                r#"{"custom": {"take": "2", "reel": "4"}}"#,
+                VideoMetadata {
+                    custom: [
+                        (String::from("reel"), String::from("4")),
+                        (String::from("take"), String::from("2")),
+                    ]
+                    .into(),
+                    ..metadata()
+                },
+            ),
+            (
+                r#"{"description": "Tabbed\tand\r\nbroken", "custom": {"notes": "a\nb"}}"#,
+                VideoMetadata {
+                    description: Some(String::from("Tabbed\tand\r\nbroken")),
+                    custom: [
+                        (String::from("notes"), String::from("a\nb")),
+                        (String::from("reel"), String::from("3")),
+                    ]
+                    .into(),
+                    ..metadata()
+                },
+            ),
+        ] {
+            let mut patched = metadata();
+            patch(json).apply(&mut patched).unwrap();
+            assert_eq!(patched, expected, "{}", json);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_patches() {
+        let long = |chars: usize| "x".repeat(chars);
+        let tags = |count: usize| (0..count).map(|i| format!("tag{}", i)).collect::<Vec<_>>();
+        let custom = |count: usize| {
+            (0..count)
+                .map(|i| (format!("key{}", i), Some(String::from("value"))))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        for (patch, message) in [
+            (
+                MetadataPatch {
+                    title: Some(Some(long(MAX_TITLE_CHARS + 1))),
+                    ..Default::default()
+                },
+                "title is longer",
+            ),
+            (
+                MetadataPatch {
+                    description: Some(Some(long(MAX_DESCRIPTION_CHARS + 1))),
+                    ..Default::default()
+                },
+                "description is longer",
+            ),
+            (
+                MetadataPatch {
+                    license: Some(Some(long(MAX_FIELD_CHARS + 1))),
+                    ..Default::default()
+                },
+                "license is longer",
+            ),
+            (
+                MetadataPatch {
+                    title: Some(Some(String::from("two\nlines"))),
+                    ..Default::default()
+                },
+                "title contains control characters",
+            ),
+            (
+                MetadataPatch {
+                    source: Some(Some(String::from("bell\u{7}"))),
+                    ..Default::default()
+                },
+                "source contains control characters",
+            ),
+            (
+                MetadataPatch {
+                    description: Some(Some(String::from("escape\u{1b}[0m"))),
+                    ..Default::default()
+                },
+                "description contains control characters",
+            ),
+            (
+                MetadataPatch {
+                    tags: Some(Some(vec![long(MAX_TAG_CHARS + 1)])),
+                    ..Default::default()
+                },
+                "a tag is longer",
+            ),
+            (
+                MetadataPatch {
+                    tags: Some(Some(vec![String::from("a\tb")])),
+                    ..Default::default()
+                },
+                "a tag contains control characters",
+            ),
+            (
+                MetadataPatch {
+                    tags: Some(Some(tags(MAX_TAGS + 1))),
+                    ..Default::default()
+                },
+                "at most 50 tags",
+            ),
+            (
+                MetadataPatch {
+                    custom: custom(MAX_CUSTOM_FIELDS),
+                    ..Default::default()
+                },
+                "at most 50 custom fields",
+            ),
+            (
+                MetadataPatch {
+                    custom: [(String::from("has space"), None)].into(),
+                    ..Default::default()
+                },
+                "custom field names",
+            ),
+            (
+                MetadataPatch {
+                    custom: [(long(MAX_CUSTOM_KEY_CHARS + 1), None)].into(),
+                    ..Default::default()
+                },
+                "custom field names",
+            ),
+            (
+                MetadataPatch {
+                    custom: [(
+                        String::from("notes"),
+                        Some(long(MAX_CUSTOM_VALUE_CHARS + 1)),
+                    )]
+                    .into(),
+                    ..Default::default()
+                },
+                "custom field 'notes' is longer",
+            ),
+        ] {
+            let description = format!("{:?}", patch);
+            let mut patched = metadata();
+            let err = patch.apply(&mut patched).unwrap_err();
+            assert!(err.contains(message), "{}: {}", description, err);
+            // Nothing of a failed patch is kept
+            assert_eq!(patched, metadata(), "{}", description);
+        }
+    }
+
+    #[test]
+    fn allows_exactly_the_limits() {
+        let mut patched = metadata();
+        MetadataPatch {
+            title: Some(Some("é".repeat(MAX_TITLE_CHARS))),
+            tags: Some(Some(
+                (0..MAX_TAGS)
+                    .map(|i| format!("{:0>width$}", i, width = MAX_TAG_CHARS))
+                    .collect(),
+            )),
+            // One is already set
+            custom: (1..MAX_CUSTOM_FIELDS)
+                .map(|i| (format!("key{}", i), Some(String::from("value"))))
+                .collect(),
+            ..Default::default()
+        }
+        .apply(&mut patched)
+        .unwrap();
+        assert_eq!(patched.tags.len(), MAX_TAGS);
+        assert_eq!(patched.custom.len(), MAX_CUSTOM_FIELDS);
+
+        // Removing a field makes room for another
+        MetadataPatch {
+            custom: [
+                (String::from("reel"), None),
+                (String::from("another"), Some(String::from("value"))),
+            ]
+            .into(),
+            ..Default::default()
+        }
+        .apply(&mut patched)
+        .unwrap();
+        assert_eq!(patched.custom.len(), MAX_CUSTOM_FIELDS);
+    }
+
+    #[test]
+    fn sets_form_fields() {
+        let mut patch = MetadataPatch::default();
+        for (name, value) in [
+            ("title", "Title"),
+            ("tags", "a,b"),
+            ("tags", "c"),
+            ("recorded_at", " 1700000000 "),
+            ("custom.reel", "3"),
+            ("license", ""),
+        ] {
+            patch.set_form_field(name, value.to_string()).unwrap();
+        }
+        assert_eq!(patch.title, Some(Some(String::from("Title"))));
+        assert_eq!(
+            patch.tags,
+            Some(Some(vec![
+                String::from("a"),
+                String::from("b"),
+                String::from("c")
+            ]))
+        );
+        assert_eq!(patch.recorded_at, Some(Some(1_700_000_000)));
+        assert_eq!(patch.custom["reel"], Some(String::from("3")));
+
+        // Empty values clear, as in a JSON patch
+        let mut metadata = metadata();
+        patch.apply(&mut metadata).unwrap();
+        assert_eq!(metadata.license, None);
+
+        let mut patch = MetadataPatch::default();
+        patch.set_form_field("recorded_at", String::new()).unwrap();
+        assert_eq!(patch.recorded_at, Some(None));
+
+        for (name, value) in [
+            ("recorded_at", "yesterday"),
+            ("recorded_at", "1.5"),
+            ("name", "x"),
+            ("custom", "x"),
+        ] {
+            assert!(
+                MetadataPatch::default()
+                    .set_form_field(name, value.to_string())
+                    .is_err(),
+                "{:?}",
+                (name, value)
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Box headers and the byte-level reads everything else is built on.
+
+use std::ops::Range;
+
+/// Compatible brands kept from an `ftyp`; real files list a handful.
+const MAX_COMPATIBLE_BRANDS: usize = 64;
+
+pub(super) fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(pos..pos.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+pub(super) fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(pos..pos.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+pub(super) fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(pos..pos.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct BoxHeader {
+    pub kind: [u8; 4],
+    pub header_len: u64,
+    /// `None` for a box that runs to the end of its parent.
+    pub size: Option<u64>,
+}
+
+pub(super) fn parse_header(data: &[u8]) -> Option<BoxHeader> {
+    let kind = data.get(4..8)?.try_into().ok()?;
+    let (header_len, size) = match u32_at(data, 0)? {
+        0 => (8, None),
+        1 => (16, Some(u64_at(data, 8)?)),
+        size => (8, Some(size as u64)),
+    };
+    Some(BoxHeader {
+        kind,
+        header_len,
+        size,
+    })
+}
+
+/// Where each box packed in `data` lies: its type and the range of its body. Stops at the first
+/// box that doesn't fit.
+pub(super) fn box_ranges(data: &[u8]) -> impl Iterator<Item = ([u8; 4], Range<usize>)> + '_ {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let rest = data.get(pos..)?;
+        let header = parse_header(rest)?;
+        let size = header.size.unwrap_or(rest.len() as u64);
+        if size < header.header_len || size > rest.len() as u64 {
+            return None;
+        }
+
+        let body = pos + header.header_len as usize..pos + size as usize;
+        pos += size as usize;
+        Some((header.kind, body))
+    })
+}
+
+/// The boxes packed in `data`, stopping at the first one that doesn't fit.
+pub(super) fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    box_ranges(data).map(move |(kind, body)| (kind, &data[body]))
+}
+
+pub(super) fn find<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data).find(|(k, _)| k == kind).map(|(_, body)| body)
+}
+
+/// The `ftyp` box: which specifications a file claims to follow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileType {
+    pub major_brand: [u8; 4],
+    pub minor_version: u32,
+    pub compatible_brands: Vec<[u8; 4]>,
+}
+
+impl FileType {
+    /// Parse the body of an `ftyp` box.
+    pub fn parse(body: &[u8]) -> Option<Self> {
+        let major_brand = body.get(0..4)?.try_into().ok()?;
+        let minor_version = u32_at(body, 4)?;
+        let compatible_brands = body
+            .get(8..)?
+            .chunks_exact(4)
+            .take(MAX_COMPATIBLE_BRANDS)
+            .filter_map(|brand| brand.try_into().ok())
+            .collect();
+        Some(Self {
+            major_brand,
+            minor_version,
+            compatible_brands,
+        })
+    }
+
+    /// The `ftyp` a file opens with, from as much of its head as is at hand.
+    pub fn from_head(head: &[u8]) -> Option<Self> {
+        let header = parse_header(head)?;
+        if &header.kind != b"ftyp" {
+            return None;
+        }
+        let body = head.get(header.header_len as usize..)?;
+        let len = header.size.map_or(body.len() as u64, |size| {
+            size.saturating_sub(header.header_len)
+        });
+        Self::parse(body.get(..len as usize).unwrap_or(body))
+    }
+
+    fn has_brand(&self, brand: &[u8; 4]) -> bool {
+        &self.major_brand == brand || self.compatible_brands.contains(brand)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match &self.major_brand {
+            b"qt  " => "video/quicktime",
+            b"M4A " | b"M4B " | b"M4P " => "audio/mp4",
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" => "image/heic",
+            // Generic HEIF brands; the compatible brands say which image codec
+            b"mif1" | b"msf1" if self.has_brand(b"avif") => "image/avif",
+            b"mif1" | b"msf1" => "image/heic",
+            [b'3', b'g', b'2', _] => "video/3gpp2",
+            [b'3', b'g', _, _] => "video/3gpp",
+            _ => "video/mp4",
+        }
+    }
+
+    /// Short container name, as ffprobe would give it.
+    pub fn container(&self) -> &'static str {
+        match &self.major_brand {
+            b"qt  " => "mov",
+            [b'3', b'g', _, _] => "3gp",
+            b"M4A " | b"M4B " | b"M4P " => "m4a",
+            _ => "mp4",
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Moving `moov` ahead of the media data ("fast start") without touching the media itself.
+//!
+//! The sample tables address chunks by absolute file offset, so every `stco`/`co64` entry that
+//! points into data pushed back by the move has to grow by the size of `moov`.
+
+use super::{
+    boxes::{box_ranges, u32_at, u64_at},
+    walk::TopLevelBox,
+};
+use std::{fmt, ops::Range};
+
+/// How to rewrite a file with `moov` first: it goes in at `insert_at`, which pushes everything
+/// from there up to its old place back by its size. Boxes after it stay where they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub insert_at: u64,
+    pub moov: TopLevelBox,
+}
+
+impl Relocation {
+    /// Original offsets that the move shifts.
+    pub fn shifted(&self) -> Range<u64> {
+        self.insert_at..self.moov.offset
+    }
+}
+
+/// Why a `moov` couldn't be adjusted for its new place. Unless every track can be, the file
+/// has to be left as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// A chunk offset table lists more entries than it holds.
+    Malformed([u8; 4]),
+    /// The boxes inside this one don't add up to its size.
+    Truncated([u8; 4]),
+    /// There is a track whose chunks can't be found, or no track at all, as in a compressed
+    /// (`cmov`) movie.
+    NoChunkOffsets,
+    /// An `stco` entry would no longer fit in 32 bits.
+    NeedsCo64,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(kind) => write!(f, "malformed {} box", String::from_utf8_lossy(kind)),
+            Self::Truncated(kind) => write!(f, "truncated {} box", String::from_utf8_lossy(kind)),
+            Self::NoChunkOffsets => write!(f, "a track has no chunk offsets"),
+            Self::NeedsCo64 => write!(f, "chunk offsets would overflow stco"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Where `moov` would go, given a file's top-level boxes. `None` if it already comes before the
+/// media data, or the file isn't one that can be fixed by moving it.
+pub fn plan(top_level: &[TopLevelBox]) -> Option<Relocation> {
+    let moov = top_level.iter().position(|top| &top.kind == b"moov")?;
+    let mdat = top_level.iter().position(|top| &top.kind == b"mdat")?;
+    // Fragments address their samples relative to each `moof`, not through `moov`
+    if moov < mdat || top_level.iter().any(|top| &top.kind == b"moof") {
+        return None;
+    }
+
+    Some(Relocation {
+        insert_at: top_level[mdat].offset,
+        moov: top_level[moov],
+    })
+}
+
+/// Adjust the chunk offsets in the body of a `moov` box for `relocation`.
+pub fn shift_chunk_offsets(moov: &mut [u8], relocation: &Relocation) -> Result<(), PatchError> {
+    let shifted = relocation.shifted();
+    let delta = relocation.moov.size();
+
+    let traks: Vec<_> = children(moov, *b"moov")?
+        .into_iter()
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, body)| body)
+        .collect();
+    if traks.is_empty() {
+        return Err(PatchError::NoChunkOffsets);
+    }
+    for trak in traks {
+        let trak = &mut moov[trak];
+        let stbl = descend(trak, *b"trak", &[b"mdia", b"minf", b"stbl"])?;
+        let stbl = &mut trak[stbl];
+
+        let tables: Vec<_> = children(stbl, *b"stbl")?
+            .into_iter()
+            .filter(|(kind, _)| kind == b"stco" || kind == b"co64")
+            .collect();
+        if tables.is_empty() {
+            return Err(PatchError::NoChunkOffsets);
+        }
+        for (kind, body) in tables {
+            shift_table(kind, &mut stbl[body], &shifted, delta)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A box's type and the range of its body.
+type BoxRange = ([u8; 4], Range<usize>);
+
+/// The boxes in the body of a `kind` box, which have to fill it: [`box_ranges`] stops quietly
+/// at the first that doesn't fit, and anything it skips might hold chunk offsets.
+fn children(body: &[u8], kind: [u8; 4]) -> Result<Vec<BoxRange>, PatchError> {
+    let children: Vec<_> = box_ranges(body).collect();
+    let end = children.last().map_or(0, |(_, range)| range.end);
+    if end != body.len() {
+        return Err(PatchError::Truncated(kind));
+    }
+    Ok(children)
+}
+
+/// The body of the box reached by following `path` down from the body of a `kind` box.
+fn descend(data: &[u8], kind: [u8; 4], path: &[&[u8; 4]]) -> Result<Range<usize>, PatchError> {
+    let mut range = 0..data.len();
+    let mut parent = kind;
+    for &&child in path {
+        let (_, body) = children(&data[range.clone()], parent)?
+            .into_iter()
+            .find(|(k, _)| *k == child)
+            .ok_or(PatchError::NoChunkOffsets)?;
+        range = range.start + body.start..range.start + body.end;
+        parent = child;
+    }
+    Ok(range)
+}
+
+/// Add `delta` to the entries of an `stco` or `co64` table that fall in `shifted`.
+fn shift_table(
+    kind: [u8; 4],
+    table: &mut [u8],
+    shifted: &Range<u64>,
+    delta: u64,
+) -> Result<(), PatchError> {
+    let width = if &kind == b"co64" { 8 } else { 4 };
+    let count = u32_at(table, 4).ok_or(PatchError::Malformed(kind))? as usize;
+    let entries = table.get_mut(8..).unwrap_or_default();
+    if entries.len() / width < count {
+        return Err(PatchError::Malformed(kind));
+    }
+
+    for entry in entries.chunks_exact_mut(width).take(count) {
+        let offset = match width {
+            8 => u64_at(entry, 0),
+            _ => u32_at(entry, 0).map(u64::from),
+        }
+        .unwrap_or_default();
+        if !shifted.contains(&offset) {
+            continue;
+        }
+
+        let offset = offset + delta;
+        if width == 8 {
+            entry.copy_from_slice(&offset.to_be_bytes());
+        } else {
+            let offset = u32::try_from(offset).map_err(|_| PatchError::NeedsCo64)?;
+            entry.copy_from_slice(&offset.to_be_bytes());
+        }
+    }
+    Ok(())
+}
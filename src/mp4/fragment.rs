@@ -0,0 +1,206 @@
+//! Fragmented MP4 as packaged for streaming: an init segment holding one track's `moov`, and
+//! media segments of `moof`/`mdat` pairs. Enough is read to describe them in a DASH manifest.
+
+use super::{
+    boxes::{boxes, find, u32_at, u64_at},
+    track::{audio_children, decoder_config, descriptor, parse_trak},
+};
+use crate::models::MediaTrack;
+
+/// `trun` entries read from one fragment; a few seconds of video has a few hundred.
+const MAX_SAMPLES: usize = 1 << 20;
+
+/// Bytes of a `VisualSampleEntry` ahead of its child boxes.
+const VISUAL_ENTRY_LEN: usize = 78;
+
+/// The track of an init segment.
+#[derive(Debug, Clone)]
+pub struct InitSegment {
+    pub track: MediaTrack,
+    /// Units per second of every time in its media segments.
+    pub timescale: u32,
+    /// As RFC 6381 has it, e.g. `avc1.64001F` or `mp4a.40.2`.
+    pub codecs: String,
+    /// Pixel aspect ratio of video, from `pasp`; square when it has none.
+    pub sample_aspect: (u32, u32),
+    /// From `trex`, for fragments that don't give their own.
+    pub default_sample_duration: Option<u32>,
+}
+
+/// When a media segment starts, in its track's timescale, how long it runs and how many samples
+/// it holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentTiming {
+    pub start: u64,
+    pub duration: u64,
+    pub samples: u64,
+}
+
+/// Read an init segment, or the head of one up to the end of its `moov`.
+pub fn parse_init(data: &[u8]) -> Option<InitSegment> {
+    let moov = find(data, b"moov")?;
+    let trak = find(moov, b"trak")?;
+    let track = parse_trak(trak)?;
+
+    let mdhd = find(trak, b"mdia").and_then(|mdia| find(mdia, b"mdhd"))?;
+    let timescale = match mdhd.first() {
+        Some(1) => u32_at(mdhd, 20)?,
+        _ => u32_at(mdhd, 12)?,
+    };
+    let (fourcc, entry) = find(trak, b"mdia")
+        .and_then(|mdia| find(mdia, b"minf"))
+        .and_then(|minf| find(minf, b"stbl"))
+        .and_then(|stbl| find(stbl, b"stsd"))
+        .and_then(|stsd| boxes(stsd.get(8..)?).next())?;
+    let default_sample_duration = find(moov, b"mvex")
+        .and_then(|mvex| find(mvex, b"trex"))
+        .and_then(|trex| u32_at(trex, 12));
+
+    Some(InitSegment {
+        track,
+        timescale: (timescale > 0).then_some(timescale)?,
+        codecs: codecs(&fourcc, entry),
+        sample_aspect: sample_aspect(entry).unwrap_or((1, 1)),
+        default_sample_duration,
+    })
+}
+
+/// The RFC 6381 `codecs` parameter for a sample entry.
+fn codecs(fourcc: &[u8; 4], entry: &[u8]) -> String {
+    let name = String::from_utf8_lossy(fourcc).into_owned();
+    match fourcc {
+        b"avc1" | b"avc3" => entry
+            .get(VISUAL_ENTRY_LEN..)
+            .and_then(|children| find(children, b"avcC"))
+            .and_then(|avcc| avcc.get(1..4))
+            .map_or(name.clone(), |profile| {
+                format!(
+                    "{}.{:02X}{:02X}{:02X}",
+                    name, profile[0], profile[1], profile[2]
+                )
+            }),
+        b"mp4a" => {
+            let config = entry
+                .get(audio_children(entry)..)
+                .and_then(|children| find(children, b"esds"))
+                .and_then(decoder_config);
+            match config.and_then(|config| Some((*config.first()?, config))) {
+                Some((0x40, config)) => match audio_object_type(config) {
+                    Some(object_type) => format!("mp4a.40.{}", object_type),
+                    None => String::from("mp4a.40"),
+                },
+                Some((object_type, _)) => format!("mp4a.{:02X}", object_type),
+                None => name,
+            }
+        }
+        _ => name,
+    }
+}
+
+/// The MPEG-4 audio object type from the `AudioSpecificConfig` in a decoder config, e.g. 2 for
+/// AAC-LC.
+fn audio_object_type(config: &[u8]) -> Option<u8> {
+    // The object type, stream type, buffer size and bit rates come first
+    let mut pos = 13;
+    let (tag, _) = descriptor(config, &mut pos)?;
+    if tag != 0x05 {
+        return None;
+    }
+    let first = *config.get(pos)?;
+    match first >> 3 {
+        // Escape to a 6-bit extension
+        31 => Some(32 + ((first & 0x07) << 3 | config.get(pos + 1)? >> 5)),
+        object_type => Some(object_type),
+    }
+}
+
+/// Pixel aspect ratio from the `pasp` box of a visual sample entry.
+fn sample_aspect(entry: &[u8]) -> Option<(u32, u32)> {
+    let pasp = find(entry.get(VISUAL_ENTRY_LEN..)?, b"pasp")?;
+    let (h_spacing, v_spacing) = (u32_at(pasp, 0)?, u32_at(pasp, 4)?);
+    (h_spacing > 0 && v_spacing > 0).then_some((h_spacing, v_spacing))
+}
+
+/// Timing of a media segment, from its `moof` boxes. `data` needs no more than those; `mdat`s
+/// are skipped if there. `default_sample_duration` is the init segment's.
+pub fn segment_timing(data: &[u8], default_sample_duration: Option<u32>) -> Option<SegmentTiming> {
+    let mut timing: Option<SegmentTiming> = None;
+    for (_, moof) in boxes(data).filter(|(kind, _)| kind == b"moof") {
+        let traf = find(moof, b"traf")?;
+        let tfhd = find(traf, b"tfhd")?;
+        let default_duration = tfhd_default_duration(tfhd).or(default_sample_duration);
+        let start = find(traf, b"tfdt").and_then(|tfdt| match tfdt.first() {
+            Some(1) => u64_at(tfdt, 4),
+            _ => u32_at(tfdt, 4).map(u64::from),
+        });
+
+        let (mut duration, mut samples) = (0, 0);
+        for (_, trun) in boxes(traf).filter(|(kind, _)| kind == b"trun") {
+            let (trun_duration, trun_samples) = trun_duration(trun, default_duration)?;
+            duration += trun_duration;
+            samples += trun_samples;
+        }
+
+        timing = Some(match timing {
+            Some(timing) => SegmentTiming {
+                duration: timing.duration + duration,
+                samples: timing.samples + samples,
+                ..timing
+            },
+            None => SegmentTiming {
+                start: start?,
+                duration,
+                samples,
+            },
+        });
+    }
+    timing
+}
+
+/// The default sample duration a `tfhd` box gives, if it gives one.
+fn tfhd_default_duration(tfhd: &[u8]) -> Option<u32> {
+    let flags = u32_at(tfhd, 0)? & 0x00ff_ffff;
+    if flags & 0x08 == 0 {
+        return None;
+    }
+    // Past the track ID, the base data offset and the sample description index
+    let mut pos = 8;
+    if flags & 0x01 != 0 {
+        pos += 8;
+    }
+    if flags & 0x02 != 0 {
+        pos += 4;
+    }
+    u32_at(tfhd, pos)
+}
+
+/// Total duration and sample count of a `trun` box.
+fn trun_duration(trun: &[u8], default_duration: Option<u32>) -> Option<(u64, u64)> {
+    let flags = u32_at(trun, 0)? & 0x00ff_ffff;
+    let count = u32_at(trun, 4)? as usize;
+    if count > MAX_SAMPLES {
+        return None;
+    }
+
+    let mut pos = 8;
+    if flags & 0x01 != 0 {
+        pos += 4;
+    }
+    if flags & 0x04 != 0 {
+        pos += 4;
+    }
+    if flags & 0x100 == 0 {
+        return Some((count as u64 * u64::from(default_duration?), count as u64));
+    }
+
+    let stride = [0x100, 0x200, 0x400, 0x800]
+        .into_iter()
+        .filter(|flag| flags & flag != 0)
+        .count()
+        * 4;
+    let mut duration = 0;
+    for n in 0..count {
+        duration += u64::from(u32_at(trun, pos + n * stride)?);
+    }
+    Some((duration, count as u64))
+}
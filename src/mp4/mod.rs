@@ -0,0 +1,37 @@
+//! A pure-Rust reader for ISO base media files: MP4, QuickTime, 3GP and HEIF.
+//!
+//! Covers the box structure and the boxes that describe a movie: `ftyp`, `moov/mvhd`,
+//! `trak/tkhd`, `mdia/hdlr`, `stsd` and `udta/©xyz`. Everything it reads comes from uploads, so
+//! every read is bounds-checked and counts are capped; malformed input gives `None` or an
+//! [`Error`], never a panic.
+//!
+//! It can also move `moov` to the front of a file for progressive playback, see
+//! [`plan_faststart`], and read the timing of fragmented MP4 segments, see [`segment_timing`].
+
+mod boxes;
+mod faststart;
+mod fragment;
+mod movie;
+mod track;
+mod walk;
+
+#[cfg(test)]
+mod tests;
+
+pub use boxes::FileType;
+pub use faststart::{plan as plan_faststart, shift_chunk_offsets};
+pub use fragment::{parse_init, segment_timing, InitSegment, SegmentTiming};
+pub use movie::parse_movie;
+pub use walk::{Error, Walker, HEADER_LEN};
+
+/// Content types this module can read.
+pub const CONTENT_TYPES: &[&str] = &[
+    "video/mp4",
+    "video/quicktime",
+    "video/3gpp",
+    "video/3gpp2",
+    "audio/mp4",
+];
+
+/// Largest `moov` box read into memory. Even hours-long recordings stay well below this.
+pub const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
@@ -0,0 +1,54 @@
+//! The `moov` box: the movie header, its tracks and its user data.
+
+use super::{
+    boxes::{boxes, find, u16_at, u32_at, u64_at},
+    track::{parse_trak, to_ms},
+};
+use crate::models::{Location, MediaInfo};
+
+/// Tracks read from one movie; real files have a handful.
+const MAX_TRACKS: usize = 256;
+
+/// Seconds between the MP4 epoch (1904-01-01) and the Unix epoch.
+const MP4_EPOCH_OFFSET: u64 = 2_082_844_800;
+
+/// Read what the body of a `moov` box says about the movie. The container and bit rate are left
+/// to the caller, which knows the file as a whole.
+pub fn parse_movie(moov: &[u8]) -> MediaInfo {
+    let mut media = MediaInfo {
+        probed_with: String::from("native"),
+        ..Default::default()
+    };
+
+    if let Some(mvhd) = find(moov, b"mvhd") {
+        let (created, timescale, duration) = match mvhd.first() {
+            Some(1) => (u64_at(mvhd, 4), u32_at(mvhd, 20), u64_at(mvhd, 24)),
+            _ => (
+                u32_at(mvhd, 4).map(u64::from),
+                u32_at(mvhd, 12),
+                u32_at(mvhd, 16).map(u64::from),
+            ),
+        };
+        media.duration_ms = to_ms(duration, timescale);
+        media.recorded_at = created
+            .filter(|&created| created > MP4_EPOCH_OFFSET)
+            .and_then(|created| i64::try_from(created - MP4_EPOCH_OFFSET).ok());
+    }
+
+    media.location = find(moov, b"udta")
+        .and_then(|udta| find(udta, b"\xa9xyz"))
+        .and_then(location);
+    media.tracks = boxes(moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .take(MAX_TRACKS)
+        .filter_map(|(_, trak)| parse_trak(trak))
+        .collect();
+    media
+}
+
+/// A QuickTime `©xyz` user data item: a length-prefixed ISO 6709 string.
+fn location(xyz: &[u8]) -> Option<Location> {
+    let len = u16_at(xyz, 0)? as usize;
+    let text = std::str::from_utf8(xyz.get(4..4 + len)?).ok()?;
+    Location::from_iso6709(text)
+}
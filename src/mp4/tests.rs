@@ -0,0 +1,467 @@
+use super::{
+    faststart::{PatchError, Relocation},
+    walk::TopLevelBox,
+    *,
+};
+use crate::{models::TrackKind, utils::mime};
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(body);
+    data
+}
+
+fn full_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    mp4_box(kind, &[&[0; 4], body].concat())
+}
+
+fn ftyp() -> Vec<u8> {
+    mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2avc1mp41")
+}
+
+fn video_trak() -> Vec<u8> {
+    let mut tkhd = [0; 84];
+    tkhd[40..44].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    tkhd[56..60].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+    tkhd[76..80].copy_from_slice(&(1920u32 << 16).to_be_bytes());
+    tkhd[80..84].copy_from_slice(&(1080u32 << 16).to_be_bytes());
+
+    let mut mdhd = [0; 24];
+    mdhd[12..16].copy_from_slice(&30_000u32.to_be_bytes());
+    mdhd[16..20].copy_from_slice(&300_300u32.to_be_bytes());
+    mdhd[20..22].copy_from_slice(&0x15c7u16.to_be_bytes()); // "eng"
+
+    let mut avc1 = [0; 78];
+    avc1[24..26].copy_from_slice(&1920u16.to_be_bytes());
+    avc1[26..28].copy_from_slice(&1080u16.to_be_bytes());
+    let stsd = full_box(
+        b"stsd",
+        &[&1u32.to_be_bytes(), &mp4_box(b"avc1", &avc1)[..]].concat(),
+    );
+    let stts = full_box(
+        b"stts",
+        &[
+            1u32.to_be_bytes(),
+            300u32.to_be_bytes(),
+            1001u32.to_be_bytes(),
+        ]
+        .concat(),
+    );
+
+    let stco = full_box(b"stco", &[1u32.to_be_bytes(), 0u32.to_be_bytes()].concat());
+
+    let stbl = mp4_box(b"stbl", &[stsd, stts, stco].concat());
+    let mdia = mp4_box(
+        b"mdia",
+        &[
+            full_box(b"mdhd", &mdhd[4..]),
+            full_box(b"hdlr", b"\0\0\0\0vide"),
+            mp4_box(b"minf", &stbl),
+        ]
+        .concat(),
+    );
+    mp4_box(b"trak", &[full_box(b"tkhd", &tkhd[4..]), mdia].concat())
+}
+
+fn moov() -> Vec<u8> {
+    let mut mvhd = [0; 100];
+    mvhd[4..8].copy_from_slice(&3_786_912_000u32.to_be_bytes()); // 2024-01-01
+    mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+    mvhd[16..20].copy_from_slice(&10_010u32.to_be_bytes());
+
+    let location = b"+37.3861-122.0839+010.000/";
+    let xyz = [
+        &(location.len() as u16).to_be_bytes()[..],
+        &[0x15, 0xc7],
+        location,
+    ]
+    .concat();
+    let udta = mp4_box(b"udta", &mp4_box(b"\xa9xyz", &xyz));
+
+    mp4_box(
+        b"moov",
+        &[mp4_box(b"mvhd", &mvhd), video_trak(), udta].concat(),
+    )
+}
+
+fn sample_file() -> Vec<u8> {
+    [ftyp(), moov(), mp4_box(b"mdat", &[0; 64])].concat()
+}
+
+/// Walk `data` the way the probe does, then parse its movie.
+fn parse_file(data: &[u8]) -> Result<Option<crate::models::MediaInfo>, Error> {
+    let mut walker = Walker::new(data.len() as u64);
+    while let Some(offset) = walker.offset() {
+        let end = data.len().min(offset as usize + HEADER_LEN as usize);
+        let top = walker.next(&data[offset as usize..end])?;
+        if &top.kind == b"moov" {
+            let start = top.body_offset as usize;
+            return Ok(Some(parse_movie(
+                &data[start..start + top.body_len as usize],
+            )));
+        }
+    }
+    Ok(None)
+}
+
+#[test]
+fn reads_a_well_formed_movie() {
+    let data = sample_file();
+    assert_eq!(
+        FileType::from_head(&data).unwrap().content_type(),
+        "video/mp4"
+    );
+    assert_eq!(mime::sniff(&data), Some("video/mp4"));
+
+    let media = parse_file(&data).unwrap().unwrap();
+    assert_eq!(media.duration_ms, Some(10_010));
+    assert_eq!(media.recorded_at, Some(1_704_067_200));
+
+    let location = media.location.unwrap();
+    assert_eq!(location.latitude, 37.3861);
+    assert_eq!(location.longitude, -122.0839);
+    assert_eq!(location.altitude, Some(10.0));
+
+    let video = media.first_track(TrackKind::Video).unwrap();
+    assert_eq!(video.codec.as_deref(), Some("h264"));
+    assert_eq!(video.language.as_deref(), Some("eng"));
+    assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+    assert_eq!(video.frame_rate, Some(29.97));
+    assert_eq!(video.rotation, Some(0));
+}
+
+#[test]
+fn heif_brands_are_told_apart() {
+    let avif = mp4_box(b"ftyp", b"mif1\0\0\0\0mif1avifmiaf");
+    let heic = mp4_box(b"ftyp", b"mif1\0\0\0\0mif1heic");
+    assert_eq!(mime::sniff(&avif), Some("image/avif"));
+    assert_eq!(mime::sniff(&heic), Some("image/heic"));
+}
+
+#[test]
+fn walker_reports_broken_layouts() {
+    let mut truncated = sample_file();
+    truncated.truncate(truncated.len() - 1);
+    assert_eq!(
+        parse_file(&truncated[ftyp().len() + moov().len()..]).unwrap_err(),
+        Error::Truncated(0)
+    );
+
+    let undersized = [ftyp(), vec![0, 0, 0, 4, b'f', b'r', b'e', b'e']].concat();
+    assert_eq!(
+        parse_file(&undersized).unwrap_err(),
+        Error::BadSize(ftyp().len() as u64)
+    );
+
+    let many = mp4_box(b"free", &[]).repeat(2000);
+    assert_eq!(parse_file(&many).unwrap_err(), Error::TooManyBoxes);
+
+    // A last box of size 0 runs to the end of the file
+    let open_ended = [ftyp(), vec![0, 0, 0, 0], b"mdat".to_vec(), vec![0; 32]].concat();
+    assert!(parse_file(&open_ended).unwrap().is_none());
+}
+
+/// The top-level boxes of `data`, which must be well formed.
+fn top_level(data: &[u8]) -> Vec<TopLevelBox> {
+    let mut walker = Walker::new(data.len() as u64);
+    let mut boxes = vec![];
+    while let Some(offset) = walker.offset() {
+        boxes.push(walker.next(&data[offset as usize..]).unwrap());
+    }
+    boxes
+}
+
+/// The single chunk offset in a file built from [`moov`].
+fn chunk_offset(data: &[u8]) -> usize {
+    let stco = data.windows(4).position(|w| w == b"stco").unwrap();
+    u32::from_be_bytes(data[stco + 12..stco + 16].try_into().unwrap()) as usize
+}
+
+/// A file with `moov` after the media, whose one chunk is the `mdat` body.
+fn moov_last() -> Vec<u8> {
+    let mdat = mp4_box(b"mdat", b"chunk of media data");
+    let mut moov = moov();
+    let stco = moov.windows(4).position(|w| w == b"stco").unwrap();
+    let chunk = (ftyp().len() + 8) as u32;
+    moov[stco + 12..stco + 16].copy_from_slice(&chunk.to_be_bytes());
+    [ftyp(), mdat, moov, mp4_box(b"free", &[0; 8])].concat()
+}
+
+#[test]
+fn faststart_moves_moov_and_its_chunk_offsets() {
+    let data = moov_last();
+    let boxes = top_level(&data);
+    let relocation = plan_faststart(&boxes).unwrap();
+    let moov = relocation.moov;
+    assert_eq!(relocation.insert_at, ftyp().len() as u64);
+
+    let (insert_at, start, end) = (
+        relocation.insert_at as usize,
+        moov.offset as usize,
+        moov.end() as usize,
+    );
+    let mut moov_box = data[start..end].to_vec();
+    shift_chunk_offsets(&mut moov_box[8..], &relocation).unwrap();
+    let rewritten = [
+        &data[..insert_at],
+        &moov_box,
+        &data[insert_at..start],
+        &data[end..],
+    ]
+    .concat();
+
+    assert_eq!(rewritten.len(), data.len());
+    let chunk = chunk_offset(&rewritten);
+    assert_eq!(
+        &rewritten[chunk..chunk + 5],
+        &data[chunk_offset(&data)..chunk_offset(&data) + 5]
+    );
+    assert!(plan_faststart(&top_level(&rewritten)).is_none());
+    assert!(plan_faststart(&top_level(&sample_file())).is_none());
+}
+
+#[test]
+fn faststart_refuses_broken_tables() {
+    let data = moov_last();
+    let relocation = plan_faststart(&top_level(&data)).unwrap();
+    let mut moov =
+        data[relocation.moov.body_offset as usize..relocation.moov.end() as usize].to_vec();
+
+    let stco = moov.windows(4).position(|w| w == b"stco").unwrap();
+    moov[stco + 8..stco + 12].copy_from_slice(&2u32.to_be_bytes());
+    assert_eq!(
+        shift_chunk_offsets(&mut moov, &relocation),
+        Err(PatchError::Malformed(*b"stco"))
+    );
+
+    let relocation = Relocation {
+        insert_at: 0,
+        moov: TopLevelBox {
+            body_len: u32::MAX as u64,
+            ..relocation.moov
+        },
+    };
+    moov[stco + 8..stco + 12].copy_from_slice(&1u32.to_be_bytes());
+    assert_eq!(
+        shift_chunk_offsets(&mut moov, &relocation),
+        Err(PatchError::NeedsCo64)
+    );
+}
+
+#[test]
+fn faststart_refuses_tracks_it_cannot_patch() {
+    let relocation = plan_faststart(&top_level(&moov_last())).unwrap();
+    let mvhd = full_box(b"mvhd", &[0; 96]);
+    let patch = |body: &[u8]| shift_chunk_offsets(&mut body.to_vec(), &relocation);
+    assert_eq!(patch(&[mvhd.clone(), video_trak()].concat()), Ok(()));
+
+    // A track without a sample table, next to one that has
+    let no_stbl = mp4_box(
+        b"trak",
+        &[
+            full_box(b"tkhd", &[0; 80]),
+            mp4_box(b"mdia", &mp4_box(b"minf", &[])),
+        ]
+        .concat(),
+    );
+    assert_eq!(
+        patch(&[mvhd.clone(), video_trak(), no_stbl].concat()),
+        Err(PatchError::NoChunkOffsets)
+    );
+
+    // A track cut off inside its sample table, as a box of the size that's left
+    let trak = video_trak();
+    let cut = mp4_box(b"trak", &trak[8..trak.len() - 10]);
+    assert_eq!(
+        patch(&[mvhd.clone(), video_trak(), cut].concat()),
+        Err(PatchError::Truncated(*b"trak"))
+    );
+    assert_eq!(
+        patch(&[mvhd.clone(), video_trak(), vec![0; 6]].concat()),
+        Err(PatchError::Truncated(*b"moov"))
+    );
+
+    // A compressed movie, and a sample table without chunk offsets
+    let cmov = mp4_box(b"cmov", &full_box(b"dcom", b"zlib"));
+    assert_eq!(
+        patch(&[mvhd.clone(), cmov].concat()),
+        Err(PatchError::NoChunkOffsets)
+    );
+    let stco = trak.windows(4).position(|w| w == b"stco").unwrap();
+    let mut no_stco = trak.clone();
+    no_stco[stco..stco + 4].copy_from_slice(b"free");
+    assert_eq!(
+        patch(&[mvhd, no_stco].concat()),
+        Err(PatchError::NoChunkOffsets)
+    );
+}
+
+/// xorshift64*, so that fuzz failures reproduce.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// An init segment of `video_trak`, with samples of 1001 by default.
+fn init_segment() -> Vec<u8> {
+    let trex = full_box(
+        b"trex",
+        &[1u32, 1, 1001, 0, 0]
+            .iter()
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<_>>(),
+    );
+    let mvex = mp4_box(b"mvex", &trex);
+    [ftyp(), mp4_box(b"moov", &[video_trak(), mvex].concat())].concat()
+}
+
+/// A media segment starting at `start`: one fragment of samples of the default duration and one
+/// listing `durations`.
+fn media_segment(start: u64, defaults: u32, durations: &[u32]) -> Vec<u8> {
+    let tfhd = mp4_box(b"tfhd", &[0x0002_0000u32, 1].map(u32::to_be_bytes).concat());
+    let tfdt = mp4_box(
+        b"tfdt",
+        &[&0x0100_0000u32.to_be_bytes()[..], &start.to_be_bytes()].concat(),
+    );
+    let trun = |flags: u32, count: u32, durations: &[u32]| {
+        let body = [flags, count]
+            .iter()
+            .chain(durations)
+            .flat_map(|n| n.to_be_bytes())
+            .collect::<Vec<_>>();
+        mp4_box(b"trun", &body)
+    };
+    let moof = |runs: Vec<u8>| {
+        mp4_box(
+            b"moof",
+            &mp4_box(b"traf", &[tfhd.clone(), tfdt.clone(), runs].concat()),
+        )
+    };
+    [
+        moof(trun(0, defaults, &[])),
+        mp4_box(b"mdat", &[0; 16]),
+        moof(trun(0x100, durations.len() as u32, durations)),
+        mp4_box(b"mdat", &[0; 16]),
+    ]
+    .concat()
+}
+
+#[test]
+fn reads_fragmented_tracks() {
+    let init = parse_init(&init_segment()).unwrap();
+    assert_eq!(init.track.kind, TrackKind::Video);
+    assert_eq!(
+        (init.track.width, init.track.height),
+        (Some(1920), Some(1080))
+    );
+    assert_eq!(init.timescale, 30_000);
+    assert_eq!(init.codecs, "avc1");
+    assert_eq!(init.sample_aspect, (1, 1));
+    assert_eq!(init.default_sample_duration, Some(1001));
+
+    let segment = media_segment(180_180, 150, &[1001, 1001, 2002]);
+    assert_eq!(
+        segment_timing(&segment, init.default_sample_duration),
+        Some(SegmentTiming {
+            start: 180_180,
+            duration: 150 * 1001 + 4004,
+            samples: 153,
+        })
+    );
+    // Without a default, runs that don't list their durations can't be timed
+    assert_eq!(segment_timing(&segment, None), None);
+}
+
+/// Every entry point, on input that may be anything.
+fn parse_everything(data: &[u8]) {
+    let _ = FileType::from_head(data);
+    let _ = mime::sniff(data);
+    let _ = parse_movie(data);
+    let _ = parse_file(data);
+    let _ = parse_init(data);
+    let _ = segment_timing(data, Some(1001));
+
+    let relocation = Relocation {
+        insert_at: 0,
+        moov: TopLevelBox {
+            kind: *b"moov",
+            offset: 1 << 20,
+            body_offset: (1 << 20) + 8,
+            body_len: 1 << 10,
+        },
+    };
+    let _ = shift_chunk_offsets(&mut data.to_vec(), &relocation);
+}
+
+#[test]
+fn fuzz_truncations() {
+    let data = sample_file();
+    let moov = moov();
+    for len in 0..=data.len() {
+        parse_everything(&data[..len]);
+        parse_everything(&data[data.len() - len..]);
+    }
+    for len in 0..=moov.len() {
+        parse_everything(&moov[8..len.max(8)]);
+    }
+}
+
+#[test]
+fn fuzz_mutations() {
+    let samples = [
+        sample_file(),
+        moov_last(),
+        init_segment(),
+        media_segment(0, 4, &[1001, 1001]),
+    ];
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+    for i in 0..20_000 {
+        let mut data = samples[i % samples.len()].clone();
+        for _ in 0..=rng.below(8) {
+            let pos = rng.below(data.len());
+            match rng.below(4) {
+                0 => data[pos] = rng.next() as u8,
+                // Box sizes and counts are where parsers go wrong
+                1 => {
+                    let value = [0, 1, 7, 8, 16, u32::MAX, rng.next() as u32][rng.below(7)];
+                    let end = data.len().min(pos + 4);
+                    data[pos..end].copy_from_slice(&value.to_be_bytes()[..end - pos]);
+                }
+                2 => data.truncate(pos),
+                _ => {
+                    let len = rng.below(32);
+                    data.splice(pos..pos, (0..len).map(|_| rng.next() as u8));
+                }
+            }
+            if data.is_empty() {
+                break;
+            }
+        }
+        parse_everything(&data);
+        if let Some(moov) = data.get(ftyp().len() + 8..) {
+            parse_everything(moov);
+        }
+    }
+}
+
+#[test]
+fn fuzz_random_bytes() {
+    let mut rng = Rng(0x0123_4567_89ab_cdef);
+    for _ in 0..20_000 {
+        let len = rng.below(256);
+        let data: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+        parse_everything(&data);
+    }
+}
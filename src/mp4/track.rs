@@ -0,0 +1,253 @@
+//! Track descriptions: `tkhd`, `mdia/hdlr`, `mdhd` and the sample table.
+
+use super::boxes::{boxes, find, u16_at, u32_at, u64_at};
+use crate::{
+    models::{MediaTrack, TrackKind},
+    probe::frame_rate,
+};
+
+pub(super) fn to_ms(duration: Option<u64>, timescale: Option<u32>) -> Option<i64> {
+    let timescale = timescale.filter(|&timescale| timescale > 0)?;
+    // All ones means unknown
+    let duration = duration.filter(|&d| d != u64::MAX && d != u32::MAX as u64)?;
+    i64::try_from(duration as u128 * 1000 / timescale as u128).ok()
+}
+
+pub(super) fn parse_trak(trak: &[u8]) -> Option<MediaTrack> {
+    let mdia = find(trak, b"mdia")?;
+    let kind = match find(mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12)) {
+        Some(b"vide") => TrackKind::Video,
+        Some(b"soun") => TrackKind::Audio,
+        Some(b"sbtl" | b"subt" | b"text" | b"clcp") => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    };
+
+    let mdhd = find(mdia, b"mdhd");
+    let (timescale, duration, language) = match mdhd {
+        Some(mdhd) if mdhd.first() == Some(&1) => {
+            (u32_at(mdhd, 20), u64_at(mdhd, 24), u16_at(mdhd, 32))
+        }
+        Some(mdhd) => (
+            u32_at(mdhd, 12),
+            u32_at(mdhd, 16).map(u64::from),
+            u16_at(mdhd, 20),
+        ),
+        None => (None, None, None),
+    };
+
+    let stbl = find(mdia, b"minf").and_then(|minf| find(minf, b"stbl"));
+    let entry = stbl
+        .and_then(|stbl| find(stbl, b"stsd"))
+        .and_then(|stsd| boxes(stsd.get(8..)?).next());
+
+    let mut track = MediaTrack {
+        kind,
+        codec: entry.map(|(fourcc, body)| codec_name(&fourcc, body)),
+        language: language.and_then(decode_language),
+        duration_ms: to_ms(duration, timescale),
+        width: None,
+        height: None,
+        frame_rate: None,
+        rotation: None,
+        channels: None,
+        sample_rate: None,
+    };
+
+    match kind {
+        TrackKind::Video => {
+            let tkhd = find(trak, b"tkhd");
+            let (width, height) = entry
+                .and_then(|(_, body)| Some((u16_at(body, 24)?, u16_at(body, 26)?)))
+                .filter(|&(width, height)| width > 0 && height > 0)
+                .map(|(width, height)| (width as u32, height as u32))
+                .or_else(|| tkhd.and_then(display_size))
+                .unzip();
+            track.width = width;
+            track.height = height;
+            track.rotation = tkhd.and_then(rotation);
+
+            let samples = stbl.and_then(|stbl| find(stbl, b"stts")).map(sample_count);
+            if let (Some(samples), Some(timescale), Some(duration)) = (samples, timescale, duration)
+            {
+                track.frame_rate = frame_rate(samples as f64 * timescale as f64, duration as f64);
+            }
+        }
+        TrackKind::Audio => {
+            if let Some((_, body)) = entry {
+                let (channels, sample_rate) = audio_format(body);
+                track.channels = channels;
+                track.sample_rate = sample_rate;
+            }
+        }
+        _ => {}
+    }
+
+    Some(track)
+}
+
+/// Offset of the matrix in `tkhd`, which depends on its version.
+fn matrix_offset(tkhd: &[u8]) -> usize {
+    match tkhd.first() {
+        Some(1) => 52,
+        _ => 40,
+    }
+}
+
+/// Width and height from `tkhd`, 16.16 fixed point.
+fn display_size(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let offset = matrix_offset(tkhd) + 36;
+    let (width, height) = (u32_at(tkhd, offset)? >> 16, u32_at(tkhd, offset + 4)? >> 16);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Clockwise degrees, from the rotation part of the `tkhd` matrix.
+fn rotation(tkhd: &[u8]) -> Option<i32> {
+    let offset = matrix_offset(tkhd);
+    let a = u32_at(tkhd, offset)? as i32;
+    let b = u32_at(tkhd, offset + 4)? as i32;
+    if a == 0 && b == 0 {
+        return None;
+    }
+    let degrees = (b as f64).atan2(a as f64).to_degrees().round() as i32;
+    Some(degrees.rem_euclid(360))
+}
+
+/// Total samples listed in an `stts` box.
+fn sample_count(stts: &[u8]) -> u64 {
+    let entries = u32_at(stts, 4).unwrap_or(0) as usize;
+    (0..entries)
+        .map_while(|i| u32_at(stts, 8 + i * 8))
+        .map(u64::from)
+        .sum()
+}
+
+/// Channel count and sample rate from an audio sample entry, in any QuickTime sound version.
+fn audio_format(entry: &[u8]) -> (Option<u32>, Option<u32>) {
+    let (channels, sample_rate) = match u16_at(entry, 8) {
+        Some(2) => (
+            u32_at(entry, 40),
+            u64_at(entry, 32).map(|rate| f64::from_bits(rate) as u32),
+        ),
+        _ => (
+            u16_at(entry, 16).map(u32::from),
+            u16_at(entry, 24).map(u32::from),
+        ),
+    };
+    (
+        channels.filter(|&n| n > 0),
+        sample_rate.filter(|&rate| rate > 0),
+    )
+}
+
+/// ISO 639-2/T code packed as three 5-bit letters.
+fn decode_language(packed: u16) -> Option<String> {
+    let code: String = [10, 5, 0]
+        .into_iter()
+        .map(|shift| (((packed >> shift) & 0x1f) as u8 + 0x60) as char)
+        .collect();
+    (code.chars().all(|c| c.is_ascii_lowercase()) && code != "und").then_some(code)
+}
+
+/// Codec named the way ffprobe names it.
+fn codec_name(fourcc: &[u8; 4], entry: &[u8]) -> String {
+    let name = match fourcc {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" | b"dvh1" | b"dvhe" => "hevc",
+        b"av01" => "av1",
+        b"vp08" => "vp8",
+        b"vp09" => "vp9",
+        b"mp4v" => "mpeg4",
+        b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores",
+        b"jpeg" | b"mjpa" | b"mjpb" => "mjpeg",
+        b"mp4a" => mp4a_codec(entry),
+        b"ac-3" => "ac3",
+        b"ec-3" => "eac3",
+        b"Opus" => "opus",
+        b"fLaC" => "flac",
+        b"alac" => "alac",
+        b".mp3" => "mp3",
+        b"samr" => "amr_nb",
+        b"sawb" => "amr_wb",
+        b"lpcm" | b"sowt" | b"twos" | b"in24" | b"in32" | b"fl32" | b"fl64" | b"raw " => "pcm",
+        b"tx3g" => "mov_text",
+        b"wvtt" => "webvtt",
+        b"stpp" => "ttml",
+        b"c608" => "eia_608",
+        other => return String::from_utf8_lossy(other).trim().to_lowercase(),
+    };
+    name.to_string()
+}
+
+/// Where the child boxes of an audio sample entry start, which depends on its sound version.
+pub(super) fn audio_children(entry: &[u8]) -> usize {
+    match u16_at(entry, 8) {
+        Some(1) => 44,
+        Some(2) => 64,
+        _ => 28,
+    }
+}
+
+/// `mp4a` covers several codecs; the `esds` object type says which.
+fn mp4a_codec(entry: &[u8]) -> &'static str {
+    let object_type = entry
+        .get(audio_children(entry)..)
+        .and_then(|children| find(children, b"esds"))
+        .and_then(decoder_config)
+        .and_then(|config| config.first().copied());
+
+    match object_type {
+        Some(0x69 | 0x6B) => "mp3",
+        Some(0xA5) => "ac3",
+        Some(0xA6) => "eac3",
+        Some(0xA9 | 0xAC) => "dts",
+        Some(0xAD) => "opus",
+        Some(0xDD) => "vorbis",
+        _ => "aac",
+    }
+}
+
+/// The body of the decoder config descriptor inside an `esds` box, which starts with the
+/// `objectTypeIndication`.
+pub(super) fn decoder_config(esds: &[u8]) -> Option<&[u8]> {
+    // Skip the full box header
+    let mut pos = 4;
+    let (tag, len) = descriptor(esds, &mut pos)?;
+    if tag != 0x03 {
+        return None;
+    }
+    let end = pos.checked_add(len)?;
+
+    let flags = *esds.get(pos + 2)?;
+    pos += 3;
+    if flags & 0x80 != 0 {
+        pos += 2;
+    }
+    if flags & 0x40 != 0 {
+        pos += 1 + *esds.get(pos)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        pos += 2;
+    }
+
+    let (tag, len) = descriptor(esds, &mut pos)?;
+    if tag != 0x04 || pos >= end {
+        return None;
+    }
+    esds.get(pos..pos.saturating_add(len).min(esds.len()))
+}
+
+/// A descriptor tag and its length, which takes up to four 7-bit bytes.
+pub(super) fn descriptor(data: &[u8], pos: &mut usize) -> Option<(u8, usize)> {
+    let tag = *data.get(*pos)?;
+    *pos += 1;
+    let mut len = 0usize;
+    for _ in 0..4 {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        len = (len << 7) | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Some((tag, len))
+}
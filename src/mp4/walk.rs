@@ -0,0 +1,107 @@
+//! Stepping through the top-level boxes of a file without holding it in memory.
+
+use super::boxes::parse_header;
+use std::fmt;
+
+/// Bytes to read at [`Walker::offset`] to be sure of a whole box header.
+pub const HEADER_LEN: u64 = 16;
+
+/// Files with more top-level boxes than this aren't worth walking.
+const MAX_TOP_LEVEL_BOXES: usize = 1024;
+
+/// A top-level box: its type, and where it and its body lie in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopLevelBox {
+    pub kind: [u8; 4],
+    pub offset: u64,
+    pub body_offset: u64,
+    pub body_len: u64,
+}
+
+impl TopLevelBox {
+    /// Header and body.
+    pub fn size(&self) -> u64 {
+        self.body_offset - self.offset + self.body_len
+    }
+
+    pub fn end(&self) -> u64 {
+        self.body_offset + self.body_len
+    }
+}
+
+/// Why a file's top-level boxes don't add up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The box at this offset runs past the end of the file.
+    Truncated(u64),
+    /// The box at this offset is smaller than its own header.
+    BadSize(u64),
+    TooManyBoxes,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated(offset) => write!(f, "box at offset {} is truncated", offset),
+            Self::BadSize(offset) => write!(f, "box at offset {} has an invalid size", offset),
+            Self::TooManyBoxes => write!(
+                f,
+                "file has more than {} top-level boxes",
+                MAX_TOP_LEVEL_BOXES
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Walks the top-level boxes of a file of known size. The caller does the reading: it fetches
+/// [`HEADER_LEN`] bytes at [`Walker::offset`] and hands them to [`Walker::next`].
+#[derive(Debug, Clone)]
+pub struct Walker {
+    size: u64,
+    offset: u64,
+    seen: usize,
+}
+
+impl Walker {
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            offset: 0,
+            seen: 0,
+        }
+    }
+
+    /// Where the next box starts, or `None` once the end of the file is reached.
+    pub fn offset(&self) -> Option<u64> {
+        (self.offset < self.size).then_some(self.offset)
+    }
+
+    /// Parse the box whose header starts `head`, and move past it.
+    pub fn next(&mut self, head: &[u8]) -> Result<TopLevelBox, Error> {
+        let offset = self.offset;
+        let remaining = self.size.saturating_sub(offset);
+        if self.seen >= MAX_TOP_LEVEL_BOXES {
+            return Err(Error::TooManyBoxes);
+        }
+
+        let header = parse_header(head).ok_or(Error::Truncated(offset))?;
+        let size = header.size.unwrap_or(remaining);
+        if size < header.header_len {
+            return Err(Error::BadSize(offset));
+        }
+        if size > remaining {
+            return Err(Error::Truncated(offset));
+        }
+
+        self.seen += 1;
+        self.offset = offset + size;
+        Ok(TopLevelBox {
+            kind: header.kind,
+            offset,
+            body_offset: offset + header.header_len,
+            body_len: size - header.header_len,
+        })
+    }
+}
@@ -0,0 +1,200 @@
+//! Fallback for formats the built-in parsers don't cover, using ffprobe's JSON output.
+
+use crate::{
+    models::{Location, MediaInfo, MediaTrack, TrackKind},
+    storage::StorageBackend,
+};
+use log::{debug, warn};
+use serde_json::Value;
+use std::{io::ErrorKind, process::Stdio, time::Duration};
+use tokio::process::Command;
+
+/// ffprobe only reads headers, but a file piped in over a slow backend can take a while.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Run `ffprobe` on the object at `key`: by path when the backend has one, otherwise piped in.
+/// Returns `None` if ffprobe isn't installed or can't make sense of the contents.
+pub async fn probe(
+    ffprobe: &str,
+    storage: &dyn StorageBackend,
+    key: &str,
+) -> anyhow::Result<Option<MediaInfo>> {
+    let mut command = Command::new(ffprobe);
+    command
+        .args(["-v", "error", "-print_format", "json"])
+        .args(["-show_format", "-show_streams"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    match storage.local_path(key) {
+        Some(path) => command.arg(path).stdin(Stdio::null()),
+        None => command.arg("pipe:0").stdin(Stdio::piped()),
+    };
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            warn!("ffprobe not found at '{}', skipping", ffprobe);
+            return Ok(None);
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let stdin = child.stdin.take();
+    let feed = async move {
+        let Some(mut stdin) = stdin else {
+            return anyhow::Ok(());
+        };
+        let mut reader = storage.get_range_stream(key, None).await?;
+        // ffprobe stops reading once it has seen enough
+        match tokio::io::copy(&mut reader, &mut stdin).await {
+            Err(err) if err.kind() != ErrorKind::BrokenPipe => Err(err.into()),
+            _ => Ok(()),
+        }
+    };
+
+    let (fed, output) = tokio::time::timeout(TIMEOUT, async {
+        tokio::join!(feed, child.wait_with_output())
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("ffprobe timed out"))?;
+    let output = output?;
+    if !output.status.success() {
+        debug!(
+            "ffprobe could not read {}: {}",
+            key,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Ok(None);
+    }
+    fed?;
+
+    let json: Value = serde_json::from_slice(&output.stdout)?;
+    Ok(parse(&json))
+}
+
+fn number<T: std::str::FromStr>(value: &Value) -> Option<T> {
+    match value {
+        Value::String(text) => text.parse().ok(),
+        Value::Number(number) => number.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+fn seconds_to_ms(value: &Value) -> Option<i64> {
+    let seconds: f64 = number(value)?;
+    (seconds.is_finite() && seconds >= 0.0).then_some((seconds * 1000.0) as i64)
+}
+
+/// A rate such as `30000/1001`; `0/0` means unknown.
+fn ratio(value: &Value) -> Option<f64> {
+    let (num, den) = value.as_str()?.split_once('/')?;
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    super::frame_rate(num, den)
+}
+
+fn parse(json: &Value) -> Option<MediaInfo> {
+    let streams = json["streams"].as_array().filter(|s| !s.is_empty())?;
+    let format = &json["format"];
+
+    Some(MediaInfo {
+        container: format["format_name"]
+            .as_str()
+            .and_then(|names| names.split(',').next())
+            .map(str::to_string),
+        duration_ms: seconds_to_ms(&format["duration"]),
+        bit_rate: number(&format["bit_rate"]),
+        recorded_at: format["tags"]["creation_time"]
+            .as_str()
+            .and_then(parse_timestamp),
+        location: format["tags"]["location"]
+            .as_str()
+            .and_then(Location::from_iso6709),
+        tracks: streams
+            .iter()
+            .filter(|stream| stream["disposition"]["attached_pic"] != 1)
+            .map(parse_stream)
+            .collect(),
+        probed_with: String::from("ffprobe"),
+    })
+}
+
+fn parse_stream(stream: &Value) -> MediaTrack {
+    let kind = match stream["codec_type"].as_str() {
+        Some("video") => TrackKind::Video,
+        Some("audio") => TrackKind::Audio,
+        Some("subtitle") => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    };
+
+    // The display matrix turns counter-clockwise; the older `rotate` tag clockwise
+    let rotation = stream["side_data_list"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|side_data| number::<f64>(&side_data["rotation"]))
+        .map(|degrees| (-degrees.round() as i32).rem_euclid(360))
+        .or_else(|| number::<i32>(&stream["tags"]["rotate"]).map(|d| d.rem_euclid(360)));
+
+    MediaTrack {
+        kind,
+        codec: stream["codec_name"].as_str().map(str::to_string),
+        language: stream["tags"]["language"]
+            .as_str()
+            .filter(|language| *language != "und")
+            .map(str::to_string),
+        duration_ms: seconds_to_ms(&stream["duration"]),
+        width: number(&stream["width"]),
+        height: number(&stream["height"]),
+        frame_rate: ratio(&stream["avg_frame_rate"]).or_else(|| ratio(&stream["r_frame_rate"])),
+        rotation: rotation.filter(|_| kind == TrackKind::Video),
+        channels: number(&stream["channels"]),
+        sample_rate: number(&stream["sample_rate"]),
+    }
+}
+
+/// Unix seconds from an ISO 8601 timestamp such as `2024-05-01T12:34:56.000000Z`.
+fn parse_timestamp(text: &str) -> Option<i64> {
+    let (date, time) = text.split_once(['T', ' '])?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    // Anything after the seconds is a fraction and/or a zone offset
+    let (clock, zone) = match time.find(['Z', 'z', '+', '-']) {
+        Some(i) => time.split_at(i),
+        None => (time, ""),
+    };
+    let clock = clock.split('.').next()?;
+    let mut clock = clock.splitn(3, ':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next().flatten()?);
+
+    let offset = match zone.as_bytes().first() {
+        Some(sign @ (b'+' | b'-')) => {
+            // `+hh:mm`, `+hhmm` or `+hh`
+            let digits: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+            let (h, m) = digits.split_at(digits.len().min(2));
+            let minutes = h.parse::<i64>().ok()? * 60 + m.parse::<i64>().unwrap_or(0);
+            if *sign == b'+' {
+                minutes * 60
+            } else {
+                -minutes * 60
+            }
+        }
+        _ => 0,
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
@@ -0,0 +1,743 @@
+//! Just enough Matroska/WebM to read the segment info and track entries.
+
+use super::{bit_rate, frame_rate, Source};
+use crate::models::{MediaInfo, MediaTrack, TrackKind};
+
+const EBML: u32 = 0x1A45DFA3;
+const DOC_TYPE: u32 = 0x4282;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TRACKS: u32 = 0x1654AE6B;
+const CLUSTER: u32 = 0x1F43B675;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const DATE_UTC: u32 = 0x4461;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const LANGUAGE: u32 = 0x22B59C;
+const LANGUAGE_BCP47: u32 = 0x22B59D;
+const DEFAULT_DURATION: u32 = 0x23E383;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+
+/// Segment children looked at before giving up on finding the tracks.
+const MAX_ELEMENTS: usize = 1024;
+
+/// Largest `Info` or `Tracks` element read into memory.
+const MAX_ELEMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Seconds between the Unix epoch and the Matroska epoch (2001-01-01).
+const MATROSKA_EPOCH_OFFSET: i64 = 978_307_200;
+
+/// Longest element header: a 4-byte ID and an 8-byte size.
+const MAX_HEADER_LEN: u64 = 12;
+
+struct Header {
+    id: u32,
+    /// `None` for an element of unknown size, which runs to the end of its parent.
+    size: Option<u64>,
+    len: usize,
+}
+
+/// An element ID, which keeps its length marker bit.
+fn read_id(data: &[u8]) -> Option<(u32, usize)> {
+    let len = data.first()?.leading_zeros() as usize + 1;
+    if len > 4 {
+        return None;
+    }
+    let id = data
+        .get(..len)?
+        .iter()
+        .fold(0u32, |id, &byte| (id << 8) | byte as u32);
+    Some((id, len))
+}
+
+/// An element data size, with the length marker bit stripped.
+fn read_size(data: &[u8]) -> Option<(Option<u64>, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 {
+        return None;
+    }
+    let size = data
+        .get(1..len)?
+        .iter()
+        .fold((first & (0x7f >> (len - 1))) as u64, |size, &byte| {
+            (size << 8) | byte as u64
+        });
+    let unknown = (1u64 << (7 * len)) - 1;
+    Some(((size != unknown).then_some(size), len))
+}
+
+fn parse_header(data: &[u8]) -> Option<Header> {
+    let (id, id_len) = read_id(data)?;
+    let (size, size_len) = read_size(data.get(id_len..)?)?;
+    Some(Header {
+        id,
+        size,
+        len: id_len + size_len,
+    })
+}
+
+/// The elements packed in `data`, stopping at the first one that doesn't fit.
+fn elements(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let rest = data.get(pos..)?;
+        let header = parse_header(rest)?;
+        let body = rest.get(header.len..)?;
+        let size = match header.size {
+            Some(size) => usize::try_from(size)
+                .ok()
+                .filter(|&size| size <= body.len())?,
+            None => body.len(),
+        };
+        pos += header.len + size;
+        Some((header.id, &body[..size]))
+    })
+}
+
+fn find(data: &[u8], id: u32) -> Option<&[u8]> {
+    elements(data).find(|(i, _)| *i == id).map(|(_, body)| body)
+}
+
+fn uint(data: &[u8]) -> Option<u64> {
+    (data.len() <= 8).then(|| data.iter().fold(0, |n, &byte| (n << 8) | byte as u64))
+}
+
+fn int(data: &[u8]) -> Option<i64> {
+    let n = uint(data)?;
+    let bits = data.len() as u32 * 8;
+    // Sign-extend from the element's width
+    Some(match bits {
+        0 => 0,
+        64 => n as i64,
+        _ => ((n << (64 - bits)) as i64) >> (64 - bits),
+    })
+}
+
+fn float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// Read the EBML header, then walk the segment up to its first cluster for `Info` and `Tracks`.
+/// Returns `None` if this isn't Matroska or the tracks weren't found there.
+pub async fn probe(source: &Source<'_>) -> anyhow::Result<Option<MediaInfo>> {
+    let head = source.read(0, MAX_HEADER_LEN).await?;
+    let Some(Header {
+        id: EBML,
+        size: Some(ebml_size),
+        len,
+    }) = parse_header(&head)
+    else {
+        return Ok(None);
+    };
+    let ebml = source.read(len as u64, ebml_size.min(4096)).await?;
+    let doc_type = find(&ebml, DOC_TYPE).map(string);
+
+    let mut offset = (len as u64).saturating_add(ebml_size);
+    let head = source.read(offset, MAX_HEADER_LEN).await?;
+    let Some(Header {
+        id: SEGMENT, len, ..
+    }) = parse_header(&head)
+    else {
+        return Ok(None);
+    };
+    offset += len as u64;
+
+    let mut info = None;
+    let mut tracks = None;
+    for _ in 0..MAX_ELEMENTS {
+        if offset >= source.size() || (info.is_some() && tracks.is_some()) {
+            break;
+        }
+        let head = source.read(offset, MAX_HEADER_LEN).await?;
+        let Some(header) = parse_header(&head) else {
+            break;
+        };
+        if header.id == CLUSTER {
+            break;
+        }
+        let Some(size) = header.size else {
+            break;
+        };
+
+        let body_offset = offset + header.len as u64;
+        if header.id == INFO || header.id == TRACKS {
+            anyhow::ensure!(
+                size <= MAX_ELEMENT_SIZE,
+                "element of {} bytes is too large",
+                size
+            );
+            let body = source.read(body_offset, size).await?;
+            match header.id {
+                INFO => info = Some(body),
+                _ => tracks = Some(body),
+            }
+        }
+        offset = body_offset.saturating_add(size);
+    }
+
+    let Some(tracks) = tracks else {
+        return Ok(None);
+    };
+    Ok(Some(parse(
+        doc_type.as_deref(),
+        info.as_deref(),
+        &tracks,
+        source.size(),
+    )))
+}
+
+fn parse(doc_type: Option<&str>, info: Option<&[u8]>, tracks: &[u8], file_size: u64) -> MediaInfo {
+    let container = match doc_type {
+        Some("webm") => "webm",
+        _ => "matroska",
+    };
+    let mut media = MediaInfo {
+        container: Some(container.to_string()),
+        probed_with: String::from("native"),
+        ..Default::default()
+    };
+
+    if let Some(info) = info {
+        let scale = find(info, TIMESTAMP_SCALE)
+            .and_then(uint)
+            .unwrap_or(1_000_000);
+        media.duration_ms = find(info, DURATION)
+            .and_then(float)
+            .map(|duration| duration * scale as f64 / 1_000_000.0)
+            .filter(|ms| ms.is_finite() && *ms >= 0.0 && *ms < i64::MAX as f64)
+            .map(|ms| ms as i64);
+        media.recorded_at = find(info, DATE_UTC)
+            .and_then(int)
+            .map(|ns| MATROSKA_EPOCH_OFFSET + ns.div_euclid(1_000_000_000));
+    }
+
+    media.tracks = elements(tracks)
+        .filter(|(id, _)| *id == TRACK_ENTRY)
+        .map(|(_, entry)| parse_track(entry))
+        .collect();
+    media.bit_rate = bit_rate(file_size, media.duration_ms);
+    media
+}
+
+fn parse_track(entry: &[u8]) -> MediaTrack {
+    let kind = match find(entry, TRACK_TYPE).and_then(uint) {
+        Some(1) => TrackKind::Video,
+        Some(2) => TrackKind::Audio,
+        Some(17) => TrackKind::Subtitle,
+        _ => TrackKind::Other,
+    };
+    // Matroska's default language is English
+    let language = find(entry, LANGUAGE_BCP47)
+        .or_else(|| find(entry, LANGUAGE))
+        .map(string)
+        .unwrap_or_else(|| String::from("eng"));
+
+    let mut track = MediaTrack {
+        kind,
+        codec: find(entry, CODEC_ID).map(|id| codec_name(&string(id))),
+        language: (language != "und").then_some(language),
+        duration_ms: None,
+        width: None,
+        height: None,
+        frame_rate: None,
+        rotation: None,
+        channels: None,
+        sample_rate: None,
+    };
+
+    match kind {
+        TrackKind::Video => {
+            let video = find(entry, VIDEO).unwrap_or_default();
+            let dimension = |id| {
+                find(video, id)
+                    .and_then(uint)
+                    .and_then(|n| u32::try_from(n).ok())
+            };
+            track.width = dimension(PIXEL_WIDTH);
+            track.height = dimension(PIXEL_HEIGHT);
+            track.frame_rate = find(entry, DEFAULT_DURATION)
+                .and_then(uint)
+                .and_then(|ns| frame_rate(1_000_000_000.0, ns as f64));
+        }
+        TrackKind::Audio => {
+            let audio = find(entry, AUDIO).unwrap_or_default();
+            track.channels = Some(
+                find(audio, CHANNELS)
+                    .and_then(uint)
+                    .and_then(|n| u32::try_from(n).ok())
+                    .unwrap_or(1),
+            );
+            track.sample_rate = Some(
+                find(audio, SAMPLING_FREQUENCY)
+                    .and_then(float)
+                    .filter(|rate| rate.is_finite() && *rate > 0.0)
+                    .map_or(8000, |rate| rate as u32),
+            );
+        }
+        _ => {}
+    }
+
+    track
+}
+
+/// Codec named the way ffprobe names it.
+fn codec_name(codec_id: &str) -> String {
+    let name = match codec_id {
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "V_AV1" => "av1",
+        "V_VP8" => "vp8",
+        "V_VP9" => "vp9",
+        "V_THEORA" => "theora",
+        "V_PRORES" => "prores",
+        "V_MJPEG" => "mjpeg",
+        "V_MPEG1" => "mpeg1video",
+        "V_MPEG2" => "mpeg2video",
+        id if id.starts_with("V_MPEG4/ISO/") => "mpeg4",
+        id if id.starts_with("A_AAC") => "aac",
+        "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "A_MPEG/L3" => "mp3",
+        "A_MPEG/L2" => "mp2",
+        "A_AC3" => "ac3",
+        "A_EAC3" => "eac3",
+        "A_FLAC" => "flac",
+        "A_ALAC" => "alac",
+        "A_TRUEHD" => "truehd",
+        id if id.starts_with("A_DTS") => "dts",
+        id if id.starts_with("A_PCM/") => "pcm",
+        "S_TEXT/UTF8" => "subrip",
+        "S_TEXT/WEBVTT" | "D_WEBVTT/SUBTITLES" => "webvtt",
+        "S_TEXT/ASS" | "S_TEXT/SSA" | "S_ASS" | "S_SSA" => "ass",
+        "S_HDMV/PGS" => "hdmv_pgs_subtitle",
+        "S_VOBSUB" => "dvd_subtitle",
+        other => return other.to_lowercase(),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::{ByteReader, ObjectMeta, StorageBackend},
+        utils::range::ByteRange,
+    };
+    use async_trait::async_trait;
+    use std::{io::Cursor, time::SystemTime};
+    use tokio::io::AsyncRead;
+
+    /// A single object held in memory, for probing.
+    struct Memory(Vec<u8>);
+
+    #[async_trait]
+    impl StorageBackend for Memory {
+        async fn put_stream(
+            &self,
+            _key: &str,
+            _reader: &mut (dyn AsyncRead + Send + Unpin),
+        ) -> anyhow::Result<u64> {
+            anyhow::bail!("not supported")
+        }
+
+        async fn get_range_stream(
+            &self,
+            _key: &str,
+            range: Option<ByteRange>,
+        ) -> anyhow::Result<ByteReader> {
+            let data = match range {
+                Some(range) => &self.0[range.start as usize..=range.end as usize],
+                None => &self.0[..],
+            };
+            Ok(Box::new(Cursor::new(data.to_vec())))
+        }
+
+        async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>> {
+            Ok(Some(ObjectMeta {
+                key: key.to_string(),
+                size: self.0.len() as u64,
+                modified: SystemTime::UNIX_EPOCH,
+            }))
+        }
+
+        async fn list(&self, _prefix: &str) -> anyhow::Result<Vec<ObjectMeta>> {
+            anyhow::bail!("not supported")
+        }
+
+        async fn delete(&self, _key: &str) -> anyhow::Result<()> {
+            anyhow::bail!("not supported")
+        }
+
+        async fn rename(&self, _from: &str, _to: &str) -> anyhow::Result<()> {
+            anyhow::bail!("not supported")
+        }
+    }
+
+    async fn probe_bytes(data: &[u8]) -> anyhow::Result<Option<MediaInfo>> {
+        let storage = Memory(data.to_vec());
+        let source = Source::open(&storage, "video.webm").await?;
+        probe(&source).await
+    }
+
+    /// Probing anything must neither panic nor find more than `data` could hold.
+    async fn probe_anything(data: &[u8]) {
+        if let Ok(Some(media)) = probe_bytes(data).await {
+            assert!(matches!(
+                media.container.as_deref(),
+                Some("webm" | "matroska")
+            ));
+            assert!(media.tracks.len() <= data.len());
+        }
+    }
+
+    /// A data size `len` bytes long, its marker bit included.
+    fn size(size: u64, len: usize) -> Vec<u8> {
+        let marked = size | 1 << (7 * len);
+        marked.to_be_bytes()[8 - len..].to_vec()
+    }
+
+    /// An element size of all ones, meaning unknown.
+    const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+    fn id_bytes(id: u32) -> Vec<u8> {
+        let bytes = id.to_be_bytes();
+        let skip = (id.leading_zeros() / 8) as usize;
+        bytes[skip..].to_vec()
+    }
+
+    fn element(id: u32, body: &[u8]) -> Vec<u8> {
+        let len = (1..=8)
+            .find(|&len| (body.len() as u64) < (1 << (7 * len)) - 1)
+            .unwrap();
+        [id_bytes(id), size(body.len() as u64, len), body.to_vec()].concat()
+    }
+
+    fn unknown_size_element(id: u32, body: &[u8]) -> Vec<u8> {
+        [id_bytes(id), UNKNOWN_SIZE.to_vec(), body.to_vec()].concat()
+    }
+
+    fn ebml_header() -> Vec<u8> {
+        element(
+            EBML,
+            &[element(0x4286, &[1]), element(DOC_TYPE, b"webm")].concat(),
+        )
+    }
+
+    fn info() -> Vec<u8> {
+        // 2024-01-01, in nanoseconds since 2001-01-01
+        let date = (1_704_067_200 - MATROSKA_EPOCH_OFFSET) * 1_000_000_000;
+        element(
+            INFO,
+            &[
+                element(TIMESTAMP_SCALE, &[0x0f, 0x42, 0x40]),
+                element(DURATION, &10_010.0f64.to_be_bytes()),
+                element(DATE_UTC, &date.to_be_bytes()),
+            ]
+            .concat(),
+        )
+    }
+
+    fn video_track() -> Vec<u8> {
+        element(
+            TRACK_ENTRY,
+            &[
+                element(TRACK_TYPE, &[1]),
+                element(CODEC_ID, b"V_VP9"),
+                element(LANGUAGE, b"und"),
+                element(DEFAULT_DURATION, &33_366_667u32.to_be_bytes()),
+                element(
+                    VIDEO,
+                    &[
+                        element(PIXEL_WIDTH, &1920u16.to_be_bytes()),
+                        element(PIXEL_HEIGHT, &1080u16.to_be_bytes()),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        )
+    }
+
+    fn audio_track() -> Vec<u8> {
+        element(
+            TRACK_ENTRY,
+            &[
+                element(TRACK_TYPE, &[2]),
+                element(CODEC_ID, b"A_OPUS"),
+                element(LANGUAGE_BCP47, b"pt-BR"),
+                element(
+                    AUDIO,
+                    &[
+                        element(CHANNELS, &[2]),
+                        element(SAMPLING_FREQUENCY, &48_000.0f32.to_be_bytes()),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        )
+    }
+
+    fn tracks() -> Vec<u8> {
+        element(TRACKS, &[video_track(), audio_track()].concat())
+    }
+
+    fn segment_body() -> Vec<u8> {
+        [
+            element(0xEC, &[0; 16]), // Void
+            info(),
+            tracks(),
+            element(CLUSTER, &[0; 64]),
+        ]
+        .concat()
+    }
+
+    fn sample_file() -> Vec<u8> {
+        [ebml_header(), element(SEGMENT, &segment_body())].concat()
+    }
+
+    #[tokio::test]
+    async fn reads_a_minimal_webm() {
+        let media = probe_bytes(&sample_file()).await.unwrap().unwrap();
+        assert_eq!(media.container.as_deref(), Some("webm"));
+        assert_eq!(media.duration_ms, Some(10_010));
+        assert_eq!(media.recorded_at, Some(1_704_067_200));
+        assert!(media.bit_rate.is_some());
+        assert_eq!(media.tracks.len(), 2);
+
+        let video = &media.tracks[0];
+        assert_eq!(video.kind, TrackKind::Video);
+        assert_eq!(video.codec.as_deref(), Some("vp9"));
+        assert_eq!(video.language, None);
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        assert_eq!(video.frame_rate, Some(29.97));
+
+        let audio = &media.tracks[1];
+        assert_eq!(audio.kind, TrackKind::Audio);
+        assert_eq!(audio.codec.as_deref(), Some("opus"));
+        assert_eq!(audio.language.as_deref(), Some("pt-BR"));
+        assert_eq!((audio.channels, audio.sample_rate), (Some(2), Some(48_000)));
+    }
+
+    #[tokio::test]
+    async fn reads_a_live_stream_of_unknown_size() {
+        let data = [
+            ebml_header(),
+            unknown_size_element(SEGMENT, &segment_body()),
+        ]
+        .concat();
+        let media = probe_bytes(&data).await.unwrap().unwrap();
+        assert_eq!(media.tracks.len(), 2);
+
+        // A track entry of unknown size runs to the end of `Tracks`
+        let open_entry = element(
+            TRACKS,
+            &unknown_size_element(TRACK_ENTRY, &element(TRACK_TYPE, &[2])),
+        );
+        let data = [
+            ebml_header(),
+            element(SEGMENT, &[info(), open_entry].concat()),
+        ]
+        .concat();
+        let media = probe_bytes(&data).await.unwrap().unwrap();
+        assert_eq!(media.tracks.len(), 1);
+        assert_eq!(media.tracks[0].kind, TrackKind::Audio);
+
+        // Where an unknown-size element ends can't be told without parsing it
+        let data = [
+            ebml_header(),
+            element(
+                SEGMENT,
+                &[unknown_size_element(INFO, &[]), tracks()].concat(),
+            ),
+        ]
+        .concat();
+        assert!(probe_bytes(&data).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ignores_what_isnt_matroska() {
+        let not_segment = [ebml_header(), element(INFO, &[])].concat();
+        let no_tracks = [ebml_header(), element(SEGMENT, &info())].concat();
+        for data in [
+            &b""[..],
+            b"\0\0\0\x18ftypisom",
+            &unknown_size_element(EBML, &[]),
+            &ebml_header(),
+            &not_segment,
+            &no_tracks,
+            // An ID longer than 4 bytes, then a size longer than 8
+            &[0x08, 0, 0, 0, 0],
+            &[0x1a, 0x45, 0xdf, 0xa3, 0x00, 0xff],
+        ] {
+            assert!(probe_bytes(data).await.unwrap().is_none(), "{:?}", data);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_elements() {
+        // Larger than we read into memory
+        let huge_tracks = [id_bytes(TRACKS), size(MAX_ELEMENT_SIZE + 1, 8)].concat();
+        let data = [ebml_header(), element(SEGMENT, &huge_tracks)].concat();
+        assert!(probe_bytes(&data).await.is_err());
+
+        // Larger than the file: what is there is read
+        let cut_short = [id_bytes(TRACKS), size(1 << 20, 4), video_track()].concat();
+        let data = [ebml_header(), element(SEGMENT, &cut_short)].concat();
+        let media = probe_bytes(&data).await.unwrap().unwrap();
+        assert_eq!(media.tracks.len(), 1);
+
+        // Past the end of any file, from the header or a segment child
+        let ebml = [id_bytes(EBML), size((1 << 56) - 2, 8)].concat();
+        assert!(probe_bytes(&ebml).await.unwrap().is_none());
+        let void = [0xEC, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe];
+        let data = [
+            ebml_header(),
+            element(SEGMENT, &[&void[..], &tracks()].concat()),
+        ]
+        .concat();
+        assert!(probe_bytes(&data).await.unwrap().is_none());
+
+        // Children claiming more than their parent holds are dropped
+        let entry = [
+            id_bytes(TRACK_ENTRY),
+            size(1000, 2),
+            element(TRACK_TYPE, &[1]),
+        ]
+        .concat();
+        let data = [ebml_header(), element(SEGMENT, &element(TRACKS, &entry))].concat();
+        let media = probe_bytes(&data).await.unwrap().unwrap();
+        assert!(media.tracks.is_empty());
+
+        // Numbers wider than 8 bytes are ignored
+        let tracks = element(
+            TRACKS,
+            &element(
+                TRACK_ENTRY,
+                &[
+                    element(TRACK_TYPE, &[0; 9]),
+                    element(DEFAULT_DURATION, &[0xff; 16]),
+                ]
+                .concat(),
+            ),
+        );
+        let data = [ebml_header(), element(SEGMENT, &tracks)].concat();
+        let media = probe_bytes(&data).await.unwrap().unwrap();
+        assert_eq!(media.tracks[0].kind, TrackKind::Other);
+    }
+
+    #[tokio::test]
+    async fn deep_nesting() {
+        // Only the levels Matroska defines are looked into, however deep elements go
+        let mut entry = element(TRACK_TYPE, &[1]);
+        for _ in 0..10_000 {
+            entry = element(TRACK_ENTRY, &entry);
+        }
+        let data = [ebml_header(), element(SEGMENT, &element(TRACKS, &entry))].concat();
+        let media = probe_bytes(&data).await.unwrap().unwrap();
+        assert_eq!(media.tracks.len(), 1);
+        assert_eq!(media.tracks[0].kind, TrackKind::Other);
+
+        let mut entry = element(TRACK_TYPE, &[1]);
+        for _ in 0..10_000 {
+            entry = unknown_size_element(TRACK_ENTRY, &entry);
+        }
+        let data = [ebml_header(), element(SEGMENT, &element(TRACKS, &entry))].concat();
+        probe_anything(&data).await;
+
+        // As many elements as are looked at before the tracks, then some more
+        let voids = element(0xEC, &[]).repeat(MAX_ELEMENTS);
+        let data = [ebml_header(), element(SEGMENT, &[voids, tracks()].concat())].concat();
+        assert!(probe_bytes(&data).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn fuzz_truncations() {
+        let data = sample_file();
+        for len in 0..=data.len() {
+            let media = probe_bytes(&data[..len]).await.unwrap();
+            if let Some(media) = media {
+                assert!(media.tracks.len() <= 2);
+            }
+            probe_anything(&data[data.len() - len..]).await;
+        }
+    }
+
+    /// xorshift64*, so that fuzz failures reproduce.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    #[tokio::test]
+    async fn fuzz_mutations() {
+        let sample = sample_file();
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+
+        for _ in 0..10_000 {
+            let mut data = sample.clone();
+            for _ in 0..=rng.below(8) {
+                let pos = rng.below(data.len());
+                match rng.below(4) {
+                    0 => data[pos] = rng.next() as u8,
+                    // Length markers and sizes are where parsers go wrong
+                    1 => {
+                        let value = [0x00, 0x01, 0x08, 0x40, 0x7f, 0x80, 0xff][rng.below(7)];
+                        data[pos] = value;
+                    }
+                    2 => data.truncate(pos),
+                    _ => {
+                        let len = rng.below(16);
+                        data.splice(pos..pos, (0..len).map(|_| rng.next() as u8));
+                    }
+                }
+                if data.is_empty() {
+                    break;
+                }
+            }
+            probe_anything(&data).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn fuzz_random_bytes() {
+        let mut rng = Rng(0x0123_4567_89ab_cdef);
+        for _ in 0..10_000 {
+            let len = rng.below(128);
+            let mut data: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            // Mostly past the EBML magic, so the rest gets looked at
+            if rng.below(4) > 0 {
+                data.splice(0..0, id_bytes(EBML));
+            }
+            probe_anything(&data).await;
+        }
+    }
+}
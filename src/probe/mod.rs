@@ -0,0 +1,150 @@
+//! Reading media metadata (duration, codecs, resolution, ...) out of stored files.
+//!
+//! MP4-family and Matroska files are handled by built-in parsers that fetch only the boxes and
+//! elements they need. Anything else goes to ffprobe, when one is configured.
+
+mod ffprobe;
+mod mkv;
+mod mp4;
+
+use crate::{
+    catalog::{self, ProbeTarget},
+    models::{ArchiveEntry, MediaInfo},
+    state::AppState,
+    storage::{blobs::blob_key, StorageBackend},
+    utils::{mime, range::ByteRange},
+};
+use log::{debug, warn};
+use std::time::SystemTime;
+use tokio::io::AsyncReadExt;
+
+/// Random access to a stored object.
+pub struct Source<'a> {
+    storage: &'a dyn StorageBackend,
+    key: &'a str,
+    size: u64,
+}
+
+impl<'a> Source<'a> {
+    pub async fn open(storage: &'a dyn StorageBackend, key: &'a str) -> anyhow::Result<Self> {
+        let Some(meta) = storage.stat(key).await? else {
+            anyhow::bail!("no object at '{}'", key);
+        };
+        Ok(Self {
+            storage,
+            key,
+            size: meta.size,
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Up to `len` bytes from `offset`; fewer at the end of the object.
+    pub async fn read(&self, offset: u64, len: u64) -> anyhow::Result<Vec<u8>> {
+        let end = offset.saturating_add(len).min(self.size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+
+        let range = ByteRange {
+            start: offset,
+            end: end - 1,
+        };
+        let mut data = Vec::with_capacity(range.len() as usize);
+        self.storage
+            .get_range_stream(self.key, Some(range))
+            .await?
+            .read_to_end(&mut data)
+            .await?;
+        Ok(data)
+    }
+}
+
+/// Whether ffprobe is worth running on contents of this type.
+fn maybe_media(content_type: &str) -> bool {
+    content_type.starts_with("video/")
+        || content_type.starts_with("audio/")
+        || content_type == "application/ogg"
+        || content_type == mime::OCTET_STREAM
+}
+
+/// Overall bits per second of a file of `size` bytes playing for `duration_ms`.
+fn bit_rate(size: u64, duration_ms: Option<i64>) -> Option<u64> {
+    let duration_ms = u128::try_from(duration_ms?).ok().filter(|&ms| ms > 0)?;
+    u64::try_from(size as u128 * 8 * 1000 / duration_ms).ok()
+}
+
+/// Frames per second, to three decimals.
+pub(crate) fn frame_rate(frames: f64, seconds: f64) -> Option<f64> {
+    let rate = frames / seconds;
+    (rate.is_finite() && rate > 0.0).then(|| (rate * 1000.0).round() / 1000.0)
+}
+
+/// Read the metadata of the object at `key`. Returns `None` for contents that aren't media, or
+/// that nothing available can read.
+pub async fn probe(
+    storage: &dyn StorageBackend,
+    key: &str,
+    content_type: &str,
+    ffprobe: &str,
+) -> anyhow::Result<Option<MediaInfo>> {
+    let source = Source::open(storage, key).await?;
+    let native = match content_type {
+        content_type if crate::mp4::CONTENT_TYPES.contains(&content_type) => {
+            mp4::probe(&source).await
+        }
+        "video/webm" | "video/x-matroska" => mkv::probe(&source).await,
+        _ => Ok(None),
+    };
+
+    match native {
+        Ok(Some(media)) => return Ok(Some(media)),
+        Ok(None) => {}
+        Err(err) if err.is::<crate::mp4::Error>() => {
+            warn!(
+                "{} is not a well-formed {} file: {:#}",
+                key, content_type, err
+            )
+        }
+        Err(err) => debug!("Built-in parser could not read {}: {:#}", key, err),
+    }
+
+    if ffprobe.is_empty() || !maybe_media(content_type) {
+        return Ok(None);
+    }
+    ffprobe::probe(ffprobe, storage, key).await
+}
+
+/// Probe a catalogued file and record what was found. Returns the updated entry, or `None` if
+/// the file was replaced or deleted meanwhile.
+pub async fn probe_file(
+    state: &AppState,
+    file: &ProbeTarget,
+) -> anyhow::Result<Option<ArchiveEntry>> {
+    let ffprobe = state.config.current().probe.ffprobe.clone();
+    let media = probe(
+        state.storage.as_ref(),
+        &blob_key(&file.sha256),
+        &file.content_type,
+        &ffprobe,
+    )
+    .await?;
+
+    match &media {
+        Some(media) => debug!(
+            "Probed {}: {} track(s) via {}",
+            file.file_name,
+            media.tracks.len(),
+            media.probed_with
+        ),
+        None => debug!("{} is not media we can read", file.file_name),
+    }
+
+    let now = catalog::unix_seconds(SystemTime::now());
+    state
+        .catalog
+        .set_media(&file.file_name, &file.sha256, media, now)
+        .await
+}
@@ -0,0 +1,43 @@
+//! MP4, QuickTime and 3GP files, read with the [`crate::mp4`] parser a few boxes at a time.
+
+use super::{bit_rate, Source};
+use crate::{
+    models::MediaInfo,
+    mp4::{self, FileType, Walker},
+};
+
+/// Walk the top-level boxes of the file for `ftyp` and `moov`. Returns `None` if there is no
+/// `moov`, e.g. for a fragmented file.
+pub async fn probe(source: &Source<'_>) -> anyhow::Result<Option<MediaInfo>> {
+    let size = source.size();
+    let mut walker = Walker::new(size);
+    let mut file_type = None;
+
+    while let Some(offset) = walker.offset() {
+        let head = source.read(offset, mp4::HEADER_LEN).await?;
+        let top = walker.next(&head)?;
+        match &top.kind {
+            b"ftyp" => {
+                let body = source.read(top.body_offset, top.body_len.min(256)).await?;
+                file_type = FileType::parse(&body);
+            }
+            b"moov" => {
+                anyhow::ensure!(
+                    top.body_len <= mp4::MAX_MOOV_SIZE,
+                    "moov box of {} bytes is too large",
+                    top.body_len
+                );
+                let moov = source.read(top.body_offset, top.body_len).await?;
+                let mut media = mp4::parse_movie(&moov);
+                // Pre-ftyp files are QuickTime
+                let container = file_type.as_ref().map_or("mov", FileType::container);
+                media.container = Some(container.to_string());
+                media.bit_rate = bit_rate(size, media.duration_ms);
+                return Ok(Some(media));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(None)
+}
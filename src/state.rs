@@ -0,0 +1,56 @@
+use crate::{
+    catalog::Catalog, config::SharedConfig, fixity::Scrubber, jobs::JobQueue,
+    storage::StorageBackend, tus::TusStore,
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Shared by every handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub storage: Arc<dyn StorageBackend>,
+    pub catalog: Catalog,
+    pub tus: Arc<TusStore>,
+    pub config: SharedConfig,
+    pub fixity: Arc<Scrubber>,
+    /// Processes uploaded videos in the background.
+    pub jobs: Arc<JobQueue>,
+    /// Serializes the final move of uploads into the archive so name resolution can't race.
+    pub commit_lock: Arc<Mutex<()>>,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// A state over a local archive in a fresh scratch directory named after `test`, with an
+    /// in-memory catalog and default settings otherwise.
+    pub fn for_tests(test: &str) -> anyhow::Result<Self> {
+        let dir =
+            std::env::temp_dir().join(format!("video-archiver-{}-{}", test, std::process::id()));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+
+        let mut config = crate::config::Config::default();
+        config.storage.dir = dir.join("archive");
+        config.tus.dir = dir.join("tus");
+        config.hls.work_dir = dir.join("hls");
+        let config = SharedConfig::new(config);
+        let current = config.current();
+
+        let storage = crate::storage::from_config(&current.storage)?;
+        let catalog = Catalog::open(":memory:")?;
+        Ok(Self {
+            fixity: Arc::new(Scrubber::new(
+                storage.clone(),
+                catalog.clone(),
+                config.clone(),
+            )),
+            jobs: Arc::new(JobQueue::new(catalog.clone(), config.clone())),
+            tus: Arc::new(TusStore::new(&current.tus.dir)?),
+            storage,
+            catalog,
+            config,
+            commit_lock: Default::default(),
+        })
+    }
+}
@@ -0,0 +1,45 @@
+use super::StorageBackend;
+use crate::utils::digest::{Algorithm, DigestingReader, Digests};
+
+/// File contents are stored once, by SHA-256, at `.blobs/ab/cd/abcd...`.
+///
+/// Two levels of fan-out keep any one directory small on the local backend.
+pub const BLOBS_PREFIX: &str = ".blobs/";
+
+/// Computed for every blob: SHA-256 names it, MD5 is kept for clients that only speak
+/// `Content-MD5`.
+pub const BLOB_DIGESTS: [Algorithm; 2] = [Algorithm::Sha256, Algorithm::Md5];
+
+/// Storage key of the blob with the given hex SHA-256.
+pub fn blob_key(sha256: &str) -> String {
+    let fan_out = |range| sha256.get(range).unwrap_or("00");
+    format!(
+        "{}{}/{}/{}",
+        BLOBS_PREFIX,
+        fan_out(0..2),
+        fan_out(2..4),
+        sha256
+    )
+}
+
+/// Digests of a stored object, read back in full.
+pub async fn hash_object(storage: &dyn StorageBackend, key: &str) -> anyhow::Result<Digests> {
+    let reader = storage.get_range_stream(key, None).await?;
+    let mut reader = DigestingReader::new(reader, BLOB_DIGESTS)?;
+    tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+    reader.finish()
+}
+
+/// Move the object at `key` into blob storage, or just delete it if an identical blob is already
+/// stored. Returns its digests.
+pub async fn adopt(storage: &dyn StorageBackend, key: &str) -> anyhow::Result<Digests> {
+    let digests = hash_object(storage, key).await?;
+    let destination = blob_key(&digests.require_hex(Algorithm::Sha256)?);
+
+    if storage.stat(&destination).await?.is_some() {
+        storage.delete(key).await?;
+    } else {
+        storage.rename(key, &destination).await?;
+    }
+    Ok(digests)
+}
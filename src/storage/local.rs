@@ -0,0 +1,127 @@
+use super::{ByteReader, ObjectMeta, StorageBackend};
+use crate::utils::range::ByteRange;
+use async_trait::async_trait;
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::PathBuf,
+    time::UNIX_EPOCH,
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+/// Objects stored as plain files under a root directory.
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    async fn create_parent(&self, key: &str) -> std::io::Result<()> {
+        match self.path(key).parent() {
+            Some(parent) => fs::create_dir_all(parent).await,
+            None => Ok(()),
+        }
+    }
+}
+
+fn object_meta(key: String, metadata: &std::fs::Metadata) -> ObjectMeta {
+    ObjectMeta {
+        key,
+        size: metadata.len(),
+        modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    async fn put_stream(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> anyhow::Result<u64> {
+        self.create_parent(key).await?;
+        let mut file = File::create(self.path(key)).await?;
+        let written = tokio::io::copy(reader, &mut file).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+        Ok(written)
+    }
+
+    async fn get_range_stream(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<ByteReader> {
+        let mut file = File::open(self.path(key)).await?;
+        match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Ok(Box::new(file.take(range.len())))
+            }
+            None => Ok(Box::new(file)),
+        }
+    }
+
+    async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>> {
+        match fs::metadata(self.path(key)).await {
+            Ok(metadata) if metadata.is_file() => Ok(Some(object_meta(key.to_string(), &metadata))),
+            Ok(_) => Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectMeta>> {
+        let mut entries = match fs::read_dir(self.path(prefix)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut objects = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            if let Ok(file_name) = entry.file_name().into_string() {
+                objects.push(object_meta(format!("{}{}", prefix, file_name), &metadata));
+            }
+        }
+        Ok(objects)
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        fs::remove_file(self.path(key)).await?;
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        self.create_parent(to).await?;
+        let destination = self.path(to);
+        fs::rename(self.path(from), &destination).await?;
+
+        // Persist the directory entry too, so the rename survives a crash
+        if let Some(parent) = destination.parent() {
+            if let Ok(dir) = File::open(parent).await {
+                dir.sync_all().await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
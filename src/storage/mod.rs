@@ -0,0 +1,99 @@
+pub mod blobs;
+mod local;
+mod s3;
+pub mod staging;
+
+pub use local::LocalBackend;
+pub use s3::S3Backend;
+
+use crate::{
+    config::{StorageConfig, StorageKind},
+    utils::range::ByteRange,
+};
+use async_trait::async_trait;
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub type ByteReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// What a backend knows about a stored object.
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Where archived bytes live.
+///
+/// Keys are `/`-separated paths relative to the archive root. User-visible files are single-component
+/// keys that have already been through [`crate::models::StorageKey`]; the archive's own bookkeeping
+/// lives under dot-prefixed directories such as `.staging/` and `.blobs/`.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Store everything `reader` yields under `key`, replacing any existing object. Returns the byte count.
+    async fn put_stream(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> anyhow::Result<u64>;
+
+    /// Read `range` of the object at `key`, or all of it when `range` is `None`.
+    async fn get_range_stream(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<ByteReader>;
+
+    /// `None` when no object exists at `key`.
+    async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>>;
+
+    /// Objects directly under `prefix` (which is empty or ends in `/`), not descending further.
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectMeta>>;
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+
+    /// Move an object, replacing anything at `to`. Atomic on the local backend.
+    async fn rename(&self, from: &str, to: &str) -> anyhow::Result<()>;
+
+    /// A filesystem path to the object at `key`, for external tools that need to seek in it.
+    /// `None` when the backend has no such thing.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// The backend the `[storage]` section of the config asks for: `backend` is `local` (the
+/// default, rooted at `dir`) or `s3`, set up from `[storage.s3]`.
+pub fn from_config(config: &StorageConfig) -> anyhow::Result<Arc<dyn StorageBackend>> {
+    match config.backend {
+        StorageKind::Local => Ok(Arc::new(LocalBackend::new(&config.dir)?)),
+        StorageKind::S3 => Ok(Arc::new(S3Backend::from_config(&config.s3)?)),
+    }
+}
+
+/// Read at most `limit` bytes from the start of the object at `key`.
+pub async fn read_head(
+    storage: &dyn StorageBackend,
+    key: &str,
+    limit: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let Some(meta) = storage.stat(key).await? else {
+        anyhow::bail!("no object at '{}'", key);
+    };
+    if meta.size == 0 || limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    let range = ByteRange {
+        start: 0,
+        end: meta.size.min(limit) - 1,
+    };
+    let mut head = Vec::with_capacity(range.len() as usize);
+    storage
+        .get_range_stream(key, Some(range))
+        .await?
+        .read_to_end(&mut head)
+        .await?;
+    Ok(head)
+}
@@ -0,0 +1,228 @@
+use super::{ByteReader, ObjectMeta, StorageBackend};
+use crate::{config::S3Config, utils::range::ByteRange};
+use async_trait::async_trait;
+use s3::{creds::Credentials, error::S3Error, Bucket, Region};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncRead, DuplexStream, ReadBuf},
+    sync::oneshot,
+};
+
+/// Buffer between the S3 response and the HTTP response body.
+const TRANSFER_BUFFER: usize = 256 * 1024;
+
+/// Objects stored in an S3-compatible bucket (AWS, MinIO, ...), optionally under a key prefix.
+///
+/// Renames are server-side copies followed by a delete, so they are subject to S3's 5 GB
+/// single-request copy limit; [`crate::config::MAX_S3_UPLOAD_SIZE`] keeps uploads within it.
+pub struct S3Backend {
+    bucket: Box<Bucket>,
+    prefix: String,
+}
+
+impl S3Backend {
+    pub fn from_config(config: &S3Config) -> anyhow::Result<Self> {
+        let bucket_name = config
+            .bucket
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("no S3 bucket configured"))?;
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config.region.parse()?,
+        };
+        let credentials = Credentials::new(
+            config.access_key_id.as_deref(),
+            config.secret_access_key.as_deref(),
+            None,
+            None,
+            None,
+        )?;
+
+        Self::new(bucket_name, region, credentials, config.prefix.clone())
+    }
+
+    pub fn new(
+        bucket_name: &str,
+        region: Region,
+        credentials: Credentials,
+        prefix: Option<String>,
+    ) -> anyhow::Result<Self> {
+        // MinIO and most self-hosted stores only speak path-style addressing
+        let path_style = matches!(region, Region::Custom { .. });
+        let mut bucket = Bucket::new(bucket_name, region, credentials)?;
+        if path_style {
+            bucket = bucket.with_path_style();
+        }
+
+        let prefix = match prefix {
+            Some(prefix) => format!("{}/", prefix.trim_end_matches('/')),
+            None => String::new(),
+        };
+
+        Ok(Self { bucket, prefix })
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn object_meta(&self, path: String, size: u64, modified: Option<SystemTime>) -> ObjectMeta {
+        ObjectMeta {
+            key: path
+                .strip_prefix(&self.prefix)
+                .map(str::to_string)
+                .unwrap_or(path),
+            size,
+            modified: modified.unwrap_or(UNIX_EPOCH),
+        }
+    }
+}
+
+fn is_not_found(err: &S3Error) -> bool {
+    matches!(err, S3Error::HttpFailWithBody(404, _))
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    async fn put_stream(
+        &self,
+        key: &str,
+        reader: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> anyhow::Result<u64> {
+        let response = self
+            .bucket
+            .put_object_stream(reader, self.path(key))
+            .await?;
+        Ok(response.uploaded_bytes() as u64)
+    }
+
+    async fn get_range_stream(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+    ) -> anyhow::Result<ByteReader> {
+        let (mut writer, reader) = tokio::io::duplex(TRANSFER_BUFFER);
+        let (outcome_tx, outcome) = oneshot::channel();
+        let bucket = self.bucket.clone();
+        let path = self.path(key);
+
+        tokio::spawn(async move {
+            let (start, end) = match range {
+                Some(range) => (range.start, Some(range.end)),
+                None => (0, None),
+            };
+            let result = bucket
+                .get_object_range_to_writer(&path, start, end, &mut writer)
+                .await
+                .map(drop)
+                .map_err(|err| err.to_string());
+            let _ = outcome_tx.send(result);
+        });
+
+        Ok(Box::new(TransferReader { reader, outcome }))
+    }
+
+    async fn stat(&self, key: &str) -> anyhow::Result<Option<ObjectMeta>> {
+        let path = self.path(key);
+        match self.bucket.head_object(&path).await {
+            Ok((head, _)) => {
+                let modified = head
+                    .last_modified
+                    .as_deref()
+                    .and_then(|date| httpdate::parse_http_date(date).ok());
+                let size = head.content_length.unwrap_or_default().max(0) as u64;
+                Ok(Some(self.object_meta(path, size, modified)))
+            }
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectMeta>> {
+        let pages = self
+            .bucket
+            .list(self.path(prefix), Some(String::from("/")))
+            .await?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| {
+                let modified = parse_iso8601(&object.last_modified);
+                self.object_meta(object.key, object.size, modified)
+            })
+            .collect())
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.bucket.delete_object(self.path(key)).await?;
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        self.bucket
+            .copy_object_internal(self.path(from), self.path(to))
+            .await?;
+        self.bucket.delete_object(self.path(from)).await?;
+        Ok(())
+    }
+}
+
+/// Reads the download side of a spawned transfer, turning a failed transfer into a read error
+/// instead of a silently truncated body.
+struct TransferReader {
+    reader: DuplexStream,
+    outcome: oneshot::Receiver<Result<(), String>>,
+}
+
+impl AsyncRead for TransferReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.reader).poll_read(cx, buf))?;
+
+        if buf.filled().len() > filled || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        // End of stream: only a clean finish from the transfer task counts as EOF
+        match ready!(Pin::new(&mut self.outcome).poll(cx)) {
+            Ok(Ok(())) => Poll::Ready(Ok(())),
+            Ok(Err(message)) => Poll::Ready(Err(io::Error::other(message))),
+            Err(_) => Poll::Ready(Err(io::Error::other("S3 transfer task ended unexpectedly"))),
+        }
+    }
+}
+
+/// Parse the `2006-01-02T15:04:05.000Z` timestamps used in S3 listings.
+fn parse_iso8601(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.trim_end_matches('Z').split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let time = time.split('.').next()?;
+    let mut time = time.splitn(3, ':').map(str::parse::<u64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    // Days since the epoch for a proleptic Gregorian date (Howard Hinnant's algorithm)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3_600 + minute * 60 + second))
+}
@@ -0,0 +1,106 @@
+use super::StorageBackend;
+use log::{info, warn};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Uploads are written here first and only renamed into place once complete.
+pub const STAGING_PREFIX: &str = ".staging/";
+
+/// Staged objects untouched for this long belong to an upload that will never finish.
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An in-progress upload in the staging area.
+///
+/// Dropping it without calling [`StagedUpload::commit`] (an error, or the client going away
+/// mid-request) removes the staged object in the background.
+pub struct StagedUpload {
+    storage: Arc<dyn StorageBackend>,
+    key: String,
+    committed: bool,
+}
+
+impl StagedUpload {
+    pub fn new(storage: Arc<dyn StorageBackend>) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let key = format!(
+            "{}{:x}-{:x}-{:x}.part",
+            STAGING_PREFIX,
+            nanos,
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+
+        Self {
+            storage,
+            key,
+            committed: false,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Atomically move the staged object to `destination`.
+    pub async fn commit(mut self, destination: &str) -> anyhow::Result<()> {
+        self.storage.rename(&self.key, destination).await?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Remove the staged object now, e.g. because an identical blob is already stored.
+    pub async fn discard(mut self) -> anyhow::Result<()> {
+        self.committed = true;
+        self.storage.delete(&self.key).await
+    }
+}
+
+impl Drop for StagedUpload {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        let storage = self.storage.clone();
+        let key = std::mem::take(&mut self.key);
+        tokio::spawn(async move {
+            match storage.stat(&key).await {
+                Ok(Some(_)) => match storage.delete(&key).await {
+                    Ok(()) => info!("Discarded incomplete upload {}", key),
+                    Err(err) => warn!("Failed to discard incomplete upload {}: {}", key, err),
+                },
+                Ok(None) => {}
+                Err(err) => warn!("Failed to check incomplete upload {}: {}", key, err),
+            }
+        });
+    }
+}
+
+/// Remove staged objects left behind by uploads that were interrupted by a crash or restart.
+///
+/// Only objects older than `stale_after` are touched, so another instance sharing the same
+/// storage keeps its in-flight uploads.
+pub async fn sweep(storage: &dyn StorageBackend, stale_after: Duration) -> anyhow::Result<usize> {
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for object in storage.list(STAGING_PREFIX).await? {
+        let age = now.duration_since(object.modified).unwrap_or_default();
+        if age >= stale_after {
+            storage.delete(&object.key).await?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
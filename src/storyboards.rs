@@ -0,0 +1,293 @@
+//! Storyboards for scrubbing previews: a frame every few seconds, tiled into JPEG sprite sheets,
+//! and a WebVTT track mapping each stretch of the video to its tile.
+//!
+//! They depend only on a file's contents, so they are kept per blob at
+//! `.storyboards/<sha256>/`: names sharing contents share a storyboard, and new contents get a
+//! new one.
+
+use crate::{
+    config::{SharedConfig, StoryboardsConfig},
+    models::ArchiveEntry,
+    storage::{blobs::blob_key, StorageBackend},
+    thumbnails::{feed, ffmpeg_command, spawn_ffmpeg},
+};
+use image::{codecs::jpeg::JpegEncoder, imageops, ImageEncoder, RgbImage};
+use log::debug;
+use std::{
+    fmt::Write,
+    io::{Cursor, ErrorKind},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub const STORYBOARDS_PREFIX: &str = ".storyboards/";
+
+/// Decoding a whole video, even just its keyframes, takes a while.
+const TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+const JPEG_QUALITY: u8 = 75;
+
+/// Most frames taken from one video; the track stops at the last of them.
+const MAX_FRAMES: usize = 5000;
+
+fn storyboard_dir(sha256: &str) -> String {
+    format!("{}{}/", STORYBOARDS_PREFIX, sha256)
+}
+
+/// Storage key of the WebVTT track for the contents `sha256`. Written last, so a storyboard
+/// with a track is complete.
+pub fn track_key(sha256: &str) -> String {
+    format!("{}storyboard.vtt", storyboard_dir(sha256))
+}
+
+/// Storage key of sprite sheet `sheet` for the contents `sha256`.
+pub fn sheet_key(sha256: &str, sheet: usize) -> String {
+    format!("{}{}.jpg", storyboard_dir(sha256), sheet)
+}
+
+/// Delete the storyboard of the contents `sha256`.
+pub async fn remove(storage: &dyn StorageBackend, sha256: &str) -> anyhow::Result<()> {
+    for object in storage.list(&storyboard_dir(sha256)).await? {
+        storage.delete(&object.key).await?;
+    }
+    Ok(())
+}
+
+/// How frames are tiled.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    interval_secs: f64,
+    tile_width: u32,
+    tile_height: u32,
+    columns: u32,
+    rows: u32,
+}
+
+impl Layout {
+    /// Tiles of `config`'s width, shaped like the video of `entry` as it displays. `None` if
+    /// `entry` isn't a video.
+    fn new(config: &StoryboardsConfig, entry: &ArchiveEntry) -> Option<Self> {
+        let (mut width, mut height) = (entry.width?, entry.height?);
+        if entry.rotation.unwrap_or_default() % 180 != 0 {
+            (width, height) = (height, width);
+        }
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let tile_height = (config.tile_width as f64 * height as f64 / width as f64).round();
+
+        Some(Self {
+            interval_secs: config.interval_secs,
+            tile_width: config.tile_width,
+            tile_height: (tile_height as u32).max(1),
+            columns: config.columns,
+            rows: config.rows,
+        })
+    }
+
+    fn per_sheet(&self) -> usize {
+        (self.columns * self.rows) as usize
+    }
+
+    fn frame_len(&self) -> usize {
+        self.tile_width as usize * self.tile_height as usize * 3
+    }
+
+    /// The sheet frame `n` is on, and the top left corner of its tile there.
+    fn place(&self, n: usize) -> (usize, u32, u32) {
+        let index = (n % self.per_sheet()) as u32;
+        (
+            n / self.per_sheet(),
+            index % self.columns * self.tile_width,
+            index / self.columns * self.tile_height,
+        )
+    }
+}
+
+/// `secs` as a WebVTT timestamp, `hh:mm:ss.ttt`.
+fn timestamp(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// The WebVTT track for `frames` frames. Sheets are referenced relative to the track, as
+/// `storyboard/<sheet>.jpg`.
+fn track(layout: &Layout, frames: usize, duration_ms: Option<i64>) -> String {
+    let duration = duration_ms.map(|duration_ms| duration_ms as f64 / 1000.0);
+    let mut track = String::from("WEBVTT\n");
+    for n in 0..frames {
+        let start = n as f64 * layout.interval_secs;
+        let end = match duration {
+            Some(duration) if duration > start => duration.min(start + layout.interval_secs),
+            _ => start + layout.interval_secs,
+        };
+        let (sheet, x, y) = layout.place(n);
+        let _ = write!(
+            track,
+            "\n{} --> {}\nstoryboard/{}.jpg#xywh={},{},{},{}\n",
+            timestamp(start),
+            timestamp(end),
+            sheet,
+            x,
+            y,
+            layout.tile_width,
+            layout.tile_height
+        );
+    }
+    track
+}
+
+/// Tile `frames`, the frames of one sheet, into a JPEG. Sheets with fewer frames than fit are
+/// cropped to them.
+fn compose(layout: &Layout, frames: &[RgbImage]) -> anyhow::Result<Vec<u8>> {
+    let columns = layout.columns.min(frames.len() as u32);
+    let rows = (frames.len() as u32).div_ceil(layout.columns);
+    let mut sheet = RgbImage::new(columns * layout.tile_width, rows * layout.tile_height);
+    for (n, frame) in frames.iter().enumerate() {
+        let (_, x, y) = layout.place(n);
+        imageops::replace(&mut sheet, frame, x as i64, y as i64);
+    }
+
+    let mut data = vec![];
+    JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY).write_image(
+        &sheet,
+        sheet.width(),
+        sheet.height(),
+        image::ColorType::Rgb8,
+    )?;
+    Ok(data)
+}
+
+async fn store_sheet(
+    storage: &dyn StorageBackend,
+    sha256: &str,
+    sheet: usize,
+    layout: Layout,
+    frames: Vec<RgbImage>,
+) -> anyhow::Result<()> {
+    let data = tokio::task::spawn_blocking(move || compose(&layout, &frames)).await??;
+    storage
+        .put_stream(&sheet_key(sha256, sheet), &mut Cursor::new(data))
+        .await?;
+    Ok(())
+}
+
+/// Read raw RGB frames from `frames` and store them as sprite sheets as each fills up. Returns
+/// how many frames there were.
+async fn store_sheets(
+    storage: &dyn StorageBackend,
+    sha256: &str,
+    layout: Layout,
+    mut frames: impl AsyncRead + Unpin,
+) -> anyhow::Result<usize> {
+    let mut count = 0;
+    let mut sheet = vec![];
+    loop {
+        let mut frame = vec![0; layout.frame_len()];
+        match frames.read_exact(&mut frame).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+        let frame = RgbImage::from_raw(layout.tile_width, layout.tile_height, frame)
+            .ok_or_else(|| anyhow::anyhow!("frame buffer of the wrong size"))?;
+        sheet.push(frame);
+        count += 1;
+
+        if sheet.len() == layout.per_sheet() {
+            let (n, _, _) = layout.place(count - 1);
+            store_sheet(storage, sha256, n, layout, std::mem::take(&mut sheet)).await?;
+        }
+    }
+    if !sheet.is_empty() {
+        let (n, _, _) = layout.place(count - 1);
+        store_sheet(storage, sha256, n, layout, sheet).await?;
+    }
+    Ok(count)
+}
+
+/// Make the storyboard of `entry`'s contents, unless it has one or isn't a video. Returns whether
+/// one was made.
+pub async fn generate(
+    storage: &dyn StorageBackend,
+    config: &SharedConfig,
+    entry: &ArchiveEntry,
+) -> anyhow::Result<bool> {
+    let current = config.current();
+    let (config, ffmpeg) = (&current.storyboards, &current.thumbnails.ffmpeg);
+    if !config.enabled || ffmpeg.is_empty() {
+        return Ok(false);
+    }
+    let (Some(sha256), Some(layout)) = (&entry.sha256, Layout::new(config, entry)) else {
+        return Ok(false);
+    };
+    if storage.stat(&track_key(sha256)).await?.is_some() {
+        return Ok(false);
+    }
+    // Whatever an interrupted run left behind
+    remove(storage, sha256).await?;
+
+    let key = blob_key(sha256);
+    // Decoding only keyframes is many times faster, and close enough for a preview
+    let mut command = ffmpeg_command(ffmpeg, storage, &key, &["-skip_frame", "nokey"]);
+    command
+        .args(["-an", "-sn", "-dn"])
+        .arg("-vf")
+        .arg(format!(
+            "fps=1/{},scale={}:{}",
+            layout.interval_secs, layout.tile_width, layout.tile_height
+        ))
+        .args(["-frames:v", &MAX_FRAMES.to_string()])
+        .args(["-pix_fmt", "rgb24", "-f", "rawvideo", "pipe:1"]);
+    let Some(mut child) = spawn_ffmpeg(&mut command)? else {
+        return Ok(false);
+    };
+
+    let Some(stdout) = child.stdout.take() else {
+        anyhow::bail!("ffmpeg has no stdout");
+    };
+    let mut stderr = child.stderr.take();
+    let feed = feed(child.stdin.take(), storage, &key);
+    let run = async {
+        let mut errors = vec![];
+        let (fed, frames, _) =
+            tokio::join!(feed, store_sheets(storage, sha256, layout, stdout), async {
+                if let Some(stderr) = &mut stderr {
+                    let _ = stderr.read_to_end(&mut errors).await;
+                }
+            });
+        (fed, frames, child.wait().await, errors)
+    };
+    let (fed, frames, status, errors) = tokio::time::timeout(TIMEOUT, run)
+        .await
+        .map_err(|_| anyhow::anyhow!("ffmpeg timed out"))?;
+
+    let frames = frames?;
+    if !status?.success() || frames == 0 {
+        debug!(
+            "ffmpeg made no storyboard of {}: {}",
+            entry.file_name,
+            String::from_utf8_lossy(&errors).trim()
+        );
+        remove(storage, sha256).await?;
+        return Ok(false);
+    }
+    fed?;
+
+    let track = track(&layout, frames, entry.duration_ms);
+    storage
+        .put_stream(&track_key(sha256), &mut Cursor::new(track.into_bytes()))
+        .await?;
+    // Deleted meanwhile, and its storyboard with it had it been there
+    if storage.stat(&key).await?.is_none() {
+        remove(storage, sha256).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
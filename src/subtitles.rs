@@ -0,0 +1,282 @@
+//! Subtitles and transcripts attached to videos, one track per language. Tracks are uploaded as
+//! WebVTT or SubRip and kept in the catalog as plain-text cues, which is what search indexes and
+//! what is served back as WebVTT.
+
+use std::fmt::Write;
+
+/// Largest track accepted, in bytes.
+pub const MAX_SUBTITLE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Most cues kept from one track.
+const MAX_CUES: usize = 100_000;
+
+/// One timed line of a track, markup removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub text: String,
+}
+
+/// Whether `language` looks like a BCP 47 tag, e.g. `en` or `pt-BR`.
+pub fn valid_language(language: &str) -> bool {
+    (1..=35).contains(&language.len())
+        && language.split('-').all(|part| {
+            (1..=8).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+/// A WebVTT (`00:01:02.500`, hours optional) or SubRip (`00:01:02,500`) timestamp, in
+/// milliseconds.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let number = |digits: &str| -> Option<i64> {
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    let (rest, millis) = value.split_once(['.', ','])?;
+    if millis.len() != 3 {
+        return None;
+    }
+    let millis = number(millis)?;
+    let parts: Vec<i64> = rest.split(':').map(number).collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => (0, minutes, seconds),
+        _ => return None,
+    };
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    hours
+        .checked_mul(3_600_000)?
+        .checked_add(minutes * 60_000 + seconds * 1000 + millis)
+}
+
+/// `text` without tags such as `<i>` or `<v Speaker>`, and with the common entities decoded.
+fn strip_markup(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// The cues of a WebVTT or SubRip track, in order of their start. Blocks without a timing line,
+/// such as WebVTT's header, notes and styles, are skipped.
+pub fn parse(track: &str) -> Result<Vec<Cue>, String> {
+    let track = track.strip_prefix('\u{feff}').unwrap_or(track);
+    let track = track.replace("\r\n", "\n").replace('\r', "\n");
+    let mut cues = vec![];
+    for block in track.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+
+        let (start, end) = timing.split_once("-->").unwrap_or_default();
+        // WebVTT cue settings follow the end time
+        let end = end.split_whitespace().next().unwrap_or_default();
+        let (Some(start_ms), Some(end_ms)) = (parse_timestamp(start.trim()), parse_timestamp(end))
+        else {
+            return Err(format!("invalid cue timing: {}", timing.trim()));
+        };
+        if end_ms < start_ms {
+            return Err(format!("cue ends before it starts: {}", timing.trim()));
+        }
+
+        let text: Vec<String> = lines
+            .map(|line| strip_markup(line).trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        if text.is_empty() {
+            continue;
+        }
+        if cues.len() == MAX_CUES {
+            return Err(format!("more than {} cues", MAX_CUES));
+        }
+        cues.push(Cue {
+            start_ms,
+            end_ms,
+            text: text.join("\n"),
+        });
+    }
+    if cues.is_empty() {
+        return Err(String::from("no cues found, expected WebVTT or SubRip"));
+    }
+    cues.sort_by_key(|cue| cue.start_ms);
+    Ok(cues)
+}
+
+/// `millis` as a WebVTT timestamp, `hh:mm:ss.ttt`.
+fn timestamp(millis: i64) -> String {
+    let millis = millis.max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// A WebVTT track of `cues`.
+pub fn to_webvtt(cues: &[Cue]) -> String {
+    let mut track = String::from("WEBVTT\n");
+    for cue in cues {
+        let text = cue
+            .text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        let _ = write!(
+            track,
+            "\n{} --> {}\n{}\n",
+            timestamp(cue.start_ms),
+            timestamp(cue.end_ms),
+            text
+        );
+    }
+    track
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(start_ms: i64, end_ms: i64, text: &str) -> Cue {
+        Cue {
+            start_ms,
+            end_ms,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_subrip() {
+        let track = "1\r\n00:00:01,000 --> 00:00:02,500\r\nHello\r\nthere\r\n\r\n\
+                     2\r\n00:01:02,003 --> 01:00:00,000\r\n<i>Bye</i>\r\n";
+        assert_eq!(
+            parse(track),
+            Ok(vec![
+                cue(1000, 2500, "Hello\nthere"),
+                cue(62_003, 3_600_000, "Bye")
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_webvtt() {
+        let track = "\u{feff}WEBVTT - with a title\n\n\
+                     NOTE a comment\n\n\
+                     STYLE\n::cue { color: red }\n\n\
+                     intro\n00:05.000 --> 00:06.000 align:start line:0\n<v Ann>Later</v>\n\n\
+                     00:00:01.000 --> 00:00:02.000\nFirst &amp; foremost\n\n\
+                     00:00:03.000 --> 00:00:04.000\n\n";
+        assert_eq!(
+            parse(track),
+            Ok(vec![
+                cue(1000, 2000, "First & foremost"),
+                cue(5000, 6000, "Later")
+            ])
+        );
+    }
+
+    #[test]
+    fn reads_either_separator() {
+        for timestamp in ["00:01:02.345", "00:01:02,345", "01:02.345", "01:02,345"] {
+            assert_eq!(parse_timestamp(timestamp), Some(62_345), "{}", timestamp);
+        }
+        assert_eq!(parse_timestamp("100:00:00.000"), Some(360_000_000));
+    }
+
+    #[test]
+    fn rejects_bad_timestamps() {
+        for timestamp in [
+            "",
+            "00:00:00",
+            "00:00:00.00",
+            "00:00:00.0000",
+            "00:60:00.000",
+            "00:00:60.000",
+            "0:0:0:0.000",
+            "00:00:01.-12",
+            "00:00:01.+12",
+            "-1:00:00.000",
+            "00:-1:00.000",
+            "+1:00:00.000",
+            "00:00: 1.000",
+            "aa:00:00.000",
+            "999999999999999999:00:00.000",
+            "99999999999999999999:00:00.000",
+        ] {
+            assert_eq!(parse_timestamp(timestamp), None, "{:?}", timestamp);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_timings() {
+        assert!(parse("00:00:02.000 --> 00:00:01.000\nBackwards").is_err());
+        assert!(parse("00:00:01.000 --> soon\nNever").is_err());
+        assert!(parse("999999999999999999:00:00.000 --> 999999999999999999:00:01.000\nx").is_err());
+        assert!(parse("WEBVTT\n\nNOTE nothing here").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn strips_markup() {
+        assert_eq!(strip_markup("<i>a</i> <b>b</b> <c.yellow>c</c>"), "a b c");
+        assert_eq!(strip_markup("<v.loud Ann Lee>Hi</v>"), "Hi");
+        assert_eq!(
+            strip_markup("1 &lt; 2 &amp;&amp; 3 &gt; 2"),
+            "1 < 2 && 3 > 2"
+        );
+        assert_eq!(strip_markup("a&nbsp;b &amp;lt;"), "a b &lt;");
+        assert_eq!(strip_markup("<00:00:01.000>karaoke"), "karaoke");
+    }
+
+    #[test]
+    fn writes_webvtt() {
+        let cues = vec![
+            cue(1000, 2500, "Fish & <chips>"),
+            cue(3_723_004, 3_723_005, "two\nlines"),
+        ];
+        let track = to_webvtt(&cues);
+        assert_eq!(
+            track,
+            "WEBVTT\n\n00:00:01.000 --> 00:00:02.500\nFish &amp; &lt;chips&gt;\n\n\
+             01:02:03.004 --> 01:02:03.005\ntwo\nlines\n"
+        );
+        assert_eq!(parse(&track), Ok(cues));
+        assert_eq!(to_webvtt(&[]), "WEBVTT\n");
+    }
+
+    #[test]
+    fn checks_languages() {
+        for language in ["en", "pt-BR", "zh-Hant-TW", "x-klingon"] {
+            assert!(valid_language(language), "{}", language);
+        }
+        for language in [
+            "",
+            "-",
+            "en-",
+            "en_US",
+            "en US",
+            "toolongsubtag",
+            &"a-".repeat(18),
+        ] {
+            assert!(!valid_language(language), "{:?}", language);
+        }
+    }
+}
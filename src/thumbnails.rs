@@ -0,0 +1,289 @@
+//! Posters and thumbnails: a frame grabbed from each video with ffmpeg (or an image a client
+//! uploads instead), scaled with `image` to every configured width and kept as JPEG and WebP.
+
+use crate::{
+    models::{ArchiveEntry, Poster, ThumbnailFormat},
+    state::AppState,
+    storage::{blobs::blob_key, StorageBackend},
+};
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage, ImageEncoder,
+};
+use log::{debug, info, warn};
+use std::{
+    io::{Cursor, ErrorKind},
+    process::Stdio,
+    time::Duration,
+};
+use tokio::process::{Child, ChildStdin, Command};
+
+/// Thumbnails of video `id` live at `.thumbnails/<id>/<width>.<ext>`.
+pub const THUMBNAILS_PREFIX: &str = ".thumbnails/";
+
+/// Grabbing a frame seeks and decodes a little; piping the file in over a slow backend is what
+/// takes time.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Largest poster accepted from a client, in either dimension.
+const MAX_POSTER_DIMENSION: u32 = 16384;
+
+/// Most memory decoding a poster may take.
+const MAX_POSTER_ALLOC: u64 = 512 * 1024 * 1024;
+
+fn thumbnails_dir(id: i64) -> String {
+    format!("{}{}/", THUMBNAILS_PREFIX, id)
+}
+
+/// Storage key of the thumbnail of video `id` at `width` pixels.
+pub fn thumbnail_key(id: i64, width: u32, format: ThumbnailFormat) -> String {
+    format!("{}{}.{}", thumbnails_dir(id), width, format.extension())
+}
+
+/// Why a client's poster was refused.
+#[derive(Debug)]
+pub struct InvalidPoster(pub String);
+
+impl std::fmt::Display for InvalidPoster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidPoster {}
+
+/// Decode an image in any format `image` knows, within limits that keep a hostile file from
+/// exhausting memory.
+fn decode(data: &[u8]) -> Result<DynamicImage, InvalidPoster> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_POSTER_DIMENSION);
+    limits.max_image_height = Some(MAX_POSTER_DIMENSION);
+    limits.max_alloc = Some(MAX_POSTER_ALLOC);
+
+    let mut reader = Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|err| InvalidPoster(err.to_string()))?;
+    reader.limits(limits);
+    reader
+        .decode()
+        .map_err(|err| InvalidPoster(format!("not a usable image: {}", err)))
+}
+
+/// `image` scaled to each of `sizes` wide, encoded in every [`ThumbnailFormat`]. Images are
+/// never scaled up.
+fn render(
+    image: &DynamicImage,
+    sizes: &[u32],
+) -> anyhow::Result<Vec<(u32, ThumbnailFormat, Vec<u8>)>> {
+    let mut rendered = vec![];
+    for &width in sizes {
+        let scaled = if image.width() > width {
+            image.resize(width, u32::MAX, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+        let rgb = scaled.to_rgb8();
+
+        for format in ThumbnailFormat::ALL {
+            let mut data = vec![];
+            match format {
+                ThumbnailFormat::Jpeg => JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY)
+                    .write_image(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8)?,
+                ThumbnailFormat::Webp => WebPEncoder::new_lossless(&mut data).write_image(
+                    &rgb,
+                    rgb.width(),
+                    rgb.height(),
+                    image::ColorType::Rgb8,
+                )?,
+            }
+            rendered.push((width, format, data));
+        }
+    }
+    Ok(rendered)
+}
+
+/// Replace the thumbnails of video `id` with renditions of `poster`.
+async fn store(
+    storage: &dyn StorageBackend,
+    id: i64,
+    poster: DynamicImage,
+    sizes: Vec<u32>,
+) -> anyhow::Result<()> {
+    let rendered = tokio::task::spawn_blocking(move || render(&poster, &sizes)).await??;
+
+    remove(storage, id).await?;
+    for (width, format, data) in rendered {
+        let key = thumbnail_key(id, width, format);
+        storage.put_stream(&key, &mut Cursor::new(data)).await?;
+    }
+    Ok(())
+}
+
+/// Delete every thumbnail of video `id`.
+pub async fn remove(storage: &dyn StorageBackend, id: i64) -> anyhow::Result<()> {
+    for object in storage.list(&thumbnails_dir(id)).await? {
+        storage.delete(&object.key).await?;
+    }
+    Ok(())
+}
+
+/// Where to grab the poster frame: `offset_secs` in, or halfway through shorter videos.
+fn frame_offset(offset_secs: f64, duration_ms: Option<i64>) -> f64 {
+    match duration_ms {
+        Some(duration_ms) if duration_ms > 0 => offset_secs.min(duration_ms as f64 / 2000.0),
+        _ => offset_secs,
+    }
+}
+
+/// An `ffmpeg` command reading the object at `key`, with `input_args` ahead of its input: by
+/// path when the backend has one, otherwise piped in through [`feed`].
+pub fn ffmpeg_command(
+    ffmpeg: &str,
+    storage: &dyn StorageBackend,
+    key: &str,
+    input_args: &[&str],
+) -> Command {
+    let mut command = Command::new(ffmpeg);
+    command
+        .args(["-v", "error", "-nostdin"])
+        .args(input_args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    match storage.local_path(key) {
+        Some(path) => command.arg("-i").arg(path).stdin(Stdio::null()),
+        None => command.args(["-i", "pipe:0"]).stdin(Stdio::piped()),
+    };
+    command
+}
+
+/// Start `command`, or `None` if ffmpeg isn't installed.
+pub fn spawn_ffmpeg(command: &mut Command) -> anyhow::Result<Option<Child>> {
+    match command.spawn() {
+        Ok(child) => Ok(Some(child)),
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            warn!(
+                "ffmpeg not found at '{}', skipping",
+                command.as_std().get_program().to_string_lossy()
+            );
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Pipe the object at `key` into ffmpeg, if it reads from `stdin`.
+pub async fn feed(
+    stdin: Option<ChildStdin>,
+    storage: &dyn StorageBackend,
+    key: &str,
+) -> anyhow::Result<()> {
+    let Some(mut stdin) = stdin else {
+        return Ok(());
+    };
+    let mut reader = storage.get_range_stream(key, None).await?;
+    // ffmpeg stops reading once it has what it needs
+    match tokio::io::copy(&mut reader, &mut stdin).await {
+        Err(err) if err.kind() != ErrorKind::BrokenPipe => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Run `ffmpeg` for one frame, as PNG, `offset` seconds into the object at `key`. Returns `None`
+/// if ffmpeg isn't installed or finds no frame there.
+async fn grab_frame(
+    ffmpeg: &str,
+    storage: &dyn StorageBackend,
+    key: &str,
+    offset: f64,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut command = ffmpeg_command(ffmpeg, storage, key, &["-ss", &format!("{:.3}", offset)]);
+    command.args([
+        "-frames:v",
+        "1",
+        "-f",
+        "image2pipe",
+        "-c:v",
+        "png",
+        "pipe:1",
+    ]);
+    let Some(mut child) = spawn_ffmpeg(&mut command)? else {
+        return Ok(None);
+    };
+
+    let feed = feed(child.stdin.take(), storage, key);
+    let (fed, output) = tokio::time::timeout(TIMEOUT, async {
+        tokio::join!(feed, child.wait_with_output())
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("ffmpeg timed out"))?;
+    let output = output?;
+    if !output.status.success() || output.stdout.is_empty() {
+        debug!(
+            "ffmpeg found no frame in {}: {}",
+            key,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return Ok(None);
+    }
+    fed?;
+
+    Ok(Some(output.stdout))
+}
+
+/// Generate the poster and thumbnails of a freshly archived (and probed) video, unless a client
+/// has set a poster of its own. Returns the updated entry, or `None` if nothing was generated.
+pub async fn generate(
+    state: &AppState,
+    entry: &ArchiveEntry,
+) -> anyhow::Result<Option<ArchiveEntry>> {
+    let config = state.config.current().thumbnails.clone();
+    let storage = state.storage.as_ref();
+    let Some(sha256) = &entry.sha256 else {
+        return Ok(None);
+    };
+    match entry.poster {
+        Some(Poster::Custom) => return Ok(None),
+        // Any thumbnails there are show contents the file had before
+        _ => remove(storage, entry.id).await?,
+    }
+    if config.ffmpeg.is_empty() || entry.width.is_none() {
+        return Ok(None);
+    }
+
+    let offset = frame_offset(config.offset_secs, entry.duration_ms);
+    let Some(frame) = grab_frame(&config.ffmpeg, storage, &blob_key(sha256), offset).await? else {
+        return Ok(None);
+    };
+
+    store(storage, entry.id, decode(&frame)?, config.sizes).await?;
+    debug!("Generated thumbnails of {}", entry.file_name);
+    state
+        .catalog
+        .set_poster(entry.id, sha256, Some(Poster::Generated))
+        .await
+}
+
+/// Make the image in `data` the poster of `entry`, replacing any generated one.
+pub async fn set_custom(
+    state: &AppState,
+    entry: &ArchiveEntry,
+    data: &[u8],
+) -> anyhow::Result<Option<ArchiveEntry>> {
+    let Some(sha256) = &entry.sha256 else {
+        return Ok(None);
+    };
+    let poster = decode(data)?;
+    let sizes = state.config.current().thumbnails.sizes.clone();
+
+    store(state.storage.as_ref(), entry.id, poster, sizes).await?;
+    info!("Set a custom poster for {}", entry.file_name);
+    state
+        .catalog
+        .set_poster(entry.id, sha256, Some(Poster::Custom))
+        .await
+}
@@ -0,0 +1,211 @@
+use crate::catalog::{self, Catalog};
+use log::{info, warn};
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::fs;
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,creation-with-upload,termination,expiration";
+
+/// Uploads that see no activity for this long are discarded.
+pub const EXPIRE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often expired uploads are swept.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// On-disk home for partially received tus uploads.
+///
+/// The received bytes always live on local disk, whatever the archive backend, since object
+/// stores can't append. The file length is the authoritative upload offset, so progress
+/// survives restarts without any extra bookkeeping.
+pub struct TusStore {
+    dir: PathBuf,
+    locks: Mutex<HashSet<String>>,
+}
+
+/// Held while a request, or the archiving of a received upload, is modifying an upload;
+/// released on drop.
+pub struct UploadLock {
+    store: Arc<TusStore>,
+    id: String,
+}
+
+impl TusStore {
+    pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            locks: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// A fresh, unguessable upload id; whoever knows it can write to the upload.
+    pub fn new_id() -> anyhow::Result<String> {
+        let mut bytes = [0u8; 16];
+        openssl::rand::rand_bytes(&mut bytes)?;
+        Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// Upload ids are generated by [`TusStore::new_id`]; anything else can't name an upload.
+    pub fn is_valid_id(id: &str) -> bool {
+        id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.bin", id))
+    }
+
+    pub async fn create(&self, id: &str) -> std::io::Result<()> {
+        fs::File::create(self.path(id)).await?.sync_all().await
+    }
+
+    /// Bytes received so far.
+    pub async fn offset(&self, id: &str) -> std::io::Result<u64> {
+        Ok(fs::metadata(self.path(id)).await?.len())
+    }
+
+    pub async fn remove(&self, id: &str) -> std::io::Result<()> {
+        match fs::remove_file(self.path(id)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// `None` if another request is already working on this upload.
+    pub fn lock(self: &Arc<Self>, id: &str) -> Option<UploadLock> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.insert(id.to_string()).then(|| UploadLock {
+            store: self.clone(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Drop uploads that have passed their expiry, along with their data.
+    pub async fn sweep_expired(self: &Arc<Self>, catalog: &Catalog) -> anyhow::Result<usize> {
+        let now = catalog::unix_seconds(SystemTime::now());
+        let mut removed = 0;
+
+        for id in catalog.expired_uploads(now).await? {
+            let Some(_lock) = self.lock(&id) else {
+                continue;
+            };
+            self.remove(&id).await?;
+            catalog.delete_upload(&id).await?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        let mut locks = self.store.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.remove(&self.id);
+    }
+}
+
+/// Periodically sweep expired uploads for the lifetime of the server.
+pub fn spawn_sweeper(store: Arc<TusStore>, catalog: Catalog) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match store.sweep_expired(&catalog).await {
+                Ok(0) => {}
+                Ok(removed) => info!("Expired {} resumable upload(s)", removed),
+                Err(err) => warn!("Failed to sweep expired uploads: {}", err),
+            }
+        }
+    });
+}
+
+/// Parse an `Upload-Metadata` header: comma-separated `key base64value` pairs, value optional.
+pub fn parse_metadata(header: &str) -> Option<Vec<(String, Option<String>)>> {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.split(' ');
+            let key = parts.next()?.to_string();
+            let value = match parts.next() {
+                Some(encoded) if !encoded.is_empty() => {
+                    let decoded = openssl::base64::decode_block(encoded).ok()?;
+                    Some(String::from_utf8(decoded).ok()?)
+                }
+                _ => None,
+            };
+            if key.is_empty() || parts.next().is_some() {
+                return None;
+            }
+            Some((key, value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(key: &str, value: Option<&str>) -> (String, Option<String>) {
+        (key.to_string(), value.map(str::to_string))
+    }
+
+    #[test]
+    fn parses_metadata() {
+        for (header, expected) in [
+            ("", vec![]),
+            (" , ,", vec![]),
+            (
+                "filename dmlkZW8ubXA0",
+                vec![pair("filename", Some("video.mp4"))],
+            ),
+            (
+                "filename dmlkZW8ubXA0,is_public, on_conflict cmVuYW1l",
+                vec![
+                    pair("filename", Some("video.mp4")),
+                    pair("is_public", None),
+                    pair("on_conflict", Some("rename")),
+                ],
+            ),
+            ("empty ", vec![pair("empty", None)]),
+            ("name w6l0w6k=", vec![pair("name", Some("été"))]),
+        ] {
+            assert_eq!(parse_metadata(header), Some(expected), "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_metadata() {
+        for header in [
+            // Not base64
+            "filename video.mp4",
+            "filename dmlkZW8ubXA0!",
+            // Not UTF-8
+            "filename /w==",
+            // Too many fields
+            "filename dmlkZW8ubXA0 dmlkZW8ubXA0",
+            "filename dmlkZW8ubXA0 x",
+            "filename  dmlkZW8ubXA0",
+        ] {
+            assert_eq!(parse_metadata(header), None, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn generates_valid_ids() {
+        let id = TusStore::new_id().unwrap();
+        assert!(TusStore::is_valid_id(&id), "{:?}", id);
+        assert_ne!(id, TusStore::new_id().unwrap());
+        for id in ["", "../etc/passwd", &"g".repeat(32), &"a".repeat(31)] {
+            assert!(!TusStore::is_valid_id(id), "{:?}", id);
+        }
+    }
+}
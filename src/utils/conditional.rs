@@ -0,0 +1,247 @@
+use crate::storage::ObjectMeta;
+use axum::http::{header, HeaderMap, HeaderValue};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Archived files are immutable once written, but a name can be re-uploaded, so caches must revalidate.
+pub const CACHE_CONTROL: &str = "public, no-cache";
+
+/// Cache validators for a stored file (RFC 7232 §2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    /// Strong entity tag, quoted.
+    pub etag: String,
+    /// Modification time truncated to whole seconds, as HTTP dates carry no more precision.
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    /// Derive validators from the object size and modification time.
+    ///
+    /// The mtime is encoded at nanosecond precision so a same-size rewrite within one second still changes the tag.
+    pub fn from_meta(meta: &ObjectMeta) -> Self {
+        let since_epoch = meta.modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        Self {
+            etag: format!(
+                "\"{:x}-{:x}{:08x}\"",
+                meta.size,
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos()
+            ),
+            last_modified: UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()),
+        }
+    }
+
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Ok(last_modified) =
+            HeaderValue::from_str(&httpdate::fmt_http_date(self.last_modified))
+        {
+            headers.insert(header::LAST_MODIFIED, last_modified);
+        }
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static(CACHE_CONTROL),
+        );
+    }
+
+    /// Whether a GET/HEAD should be answered with 304 Not Modified (RFC 7232 §6, steps 3 and 4).
+    pub fn is_not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+            // If-Modified-Since is ignored whenever If-None-Match is present.
+            return if_none_match.trim() == "*"
+                || entity_tags(if_none_match).any(|tag| weak_eq(tag, &self.etag));
+        }
+
+        header_str(headers, header::IF_MODIFIED_SINCE)
+            .and_then(|value| httpdate::parse_http_date(value).ok())
+            .is_some_and(|since| self.last_modified <= since)
+    }
+
+    /// Whether a `Range` header may be honoured given any `If-Range` precondition (RFC 7233 §3.2).
+    ///
+    /// A mismatch means the client holds a stale partial copy, so the full representation is sent instead.
+    pub fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let Some(if_range) = header_str(headers, header::IF_RANGE).map(str::trim) else {
+            return true;
+        };
+
+        if if_range.starts_with('"') {
+            // If-Range requires the strong comparison function.
+            if_range == self.etag
+        } else if if_range.starts_with("W/") {
+            false
+        } else {
+            httpdate::parse_http_date(if_range).is_ok_and(|date| date == self.last_modified)
+        }
+    }
+}
+
+/// Whether an `If-Match` precondition holds for a resource whose current entity tag is `etag`
+/// (RFC 7232 §3.1); it does if there is none. Only strong tags match.
+pub fn if_match(headers: &HeaderMap, etag: &str) -> bool {
+    let Some(if_match) = header_str(headers, header::IF_MATCH) else {
+        return true;
+    };
+    if_match.trim() == "*" || entity_tags(if_match).any(|tag| tag == etag)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|tag| !tag.is_empty())
+}
+
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators() -> Validators {
+        Validators::from_meta(&ObjectMeta {
+            key: String::from("a.mp4"),
+            size: 0x1234,
+            modified: UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000),
+        })
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn derives_validators_from_metadata() {
+        let validators = validators();
+        assert_eq!(validators.etag, "\"1234-6553f1001dcd6500\"");
+        // Whole seconds only
+        assert_eq!(
+            validators.last_modified,
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+
+        let mut response = HeaderMap::new();
+        validators.insert_headers(&mut response);
+        assert_eq!(response[header::ETAG], validators.etag.as_str());
+        assert_eq!(
+            response[header::LAST_MODIFIED],
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+        assert_eq!(response[header::CACHE_CONTROL], CACHE_CONTROL);
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let validators = validators();
+        let etag = validators.etag.clone();
+        let weak = format!("W/{}", etag);
+        for (value, expected) in [
+            (etag.as_str(), true),
+            (weak.as_str(), true),
+            ("*", true),
+            (" * ", true),
+            ("\"other\"", false),
+            ("W/\"other\"", false),
+            ("", false),
+        ] {
+            let request = headers(&[(header::IF_NONE_MATCH, value)]);
+            assert_eq!(
+                validators.is_not_modified(&request),
+                expected,
+                "{:?}",
+                value
+            );
+        }
+
+        let list = format!("\"a\", {} , W/\"b\"", weak);
+        assert!(validators.is_not_modified(&headers(&[(header::IF_NONE_MATCH, &list)])));
+    }
+
+    #[test]
+    fn if_modified_since_compares_dates() {
+        let validators = validators();
+        for (value, expected) in [
+            ("Tue, 14 Nov 2023 22:13:20 GMT", true),
+            ("Wed, 15 Nov 2023 00:00:00 GMT", true),
+            ("Tue, 14 Nov 2023 22:13:19 GMT", false),
+            ("yesterday", false),
+            ("", false),
+        ] {
+            let request = headers(&[(header::IF_MODIFIED_SINCE, value)]);
+            assert_eq!(
+                validators.is_not_modified(&request),
+                expected,
+                "{:?}",
+                value
+            );
+        }
+        assert!(!validators.is_not_modified(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let validators = validators();
+        let fresh = "Wed, 15 Nov 2023 00:00:00 GMT";
+        let stale = "Mon, 13 Nov 2023 00:00:00 GMT";
+        assert!(!validators.is_not_modified(&headers(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, fresh),
+        ])));
+        assert!(validators.is_not_modified(&headers(&[
+            (header::IF_NONE_MATCH, &validators.etag),
+            (header::IF_MODIFIED_SINCE, stale),
+        ])));
+    }
+
+    #[test]
+    fn if_range_compares_strongly() {
+        let validators = validators();
+        let weak = format!("W/{}", validators.etag);
+        for (value, expected) in [
+            (validators.etag.as_str(), true),
+            (weak.as_str(), false),
+            ("\"other\"", false),
+            ("Tue, 14 Nov 2023 22:13:20 GMT", true),
+            // Dates must match exactly, not just be later
+            ("Wed, 15 Nov 2023 00:00:00 GMT", false),
+            ("not a date", false),
+        ] {
+            let request = headers(&[(header::IF_RANGE, value)]);
+            assert_eq!(
+                validators.if_range_matches(&request),
+                expected,
+                "{:?}",
+                value
+            );
+        }
+        assert!(validators.if_range_matches(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        let etag = "\"7\"";
+        for (value, expected) in [
+            ("\"7\"", true),
+            ("*", true),
+            ("\"6\", \"7\"", true),
+            ("W/\"7\"", false),
+            ("\"6\"", false),
+            ("7", false),
+            ("", false),
+        ] {
+            let request = headers(&[(header::IF_MATCH, value)]);
+            assert_eq!(if_match(&request, etag), expected, "{:?}", value);
+        }
+        assert!(if_match(&HeaderMap::new(), etag));
+    }
+}
@@ -0,0 +1,539 @@
+use crate::error::AppError;
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    http::{HeaderMap, HeaderName},
+};
+use futures_util::Stream;
+use openssl::{
+    base64,
+    hash::{Hasher, MessageDigest},
+};
+use std::{
+    collections::BTreeMap,
+    future, io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+pub const CONTENT_DIGEST: HeaderName = HeaderName::from_static("content-digest");
+pub const REPR_DIGEST: HeaderName = HeaderName::from_static("repr-digest");
+pub const CONTENT_MD5: HeaderName = HeaderName::from_static("content-md5");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Algorithm {
+    Sha256,
+    Sha512,
+    Md5,
+}
+
+impl Algorithm {
+    /// The name used in the RFC 9530 hash algorithm registry.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha-256",
+            Self::Sha512 => "sha-512",
+            Self::Md5 => "md5",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha-256" => Some(Self::Sha256),
+            "sha-512" => Some(Self::Sha512),
+            "md5" => Some(Self::Md5),
+            _ => None,
+        }
+    }
+
+    fn message_digest(self) -> MessageDigest {
+        match self {
+            Self::Sha256 => MessageDigest::sha256(),
+            Self::Sha512 => MessageDigest::sha512(),
+            Self::Md5 => MessageDigest::md5(),
+        }
+    }
+}
+
+/// Digests a client sent along with some content.
+#[derive(Debug, Clone, Default)]
+pub struct Expected(Vec<(Algorithm, Vec<u8>)>);
+
+impl Expected {
+    /// Collect `Content-Digest`, `Repr-Digest` (RFC 9530) and `Content-MD5` from `headers`.
+    ///
+    /// Algorithms we don't implement are ignored, as the RFC allows, but a header that names only
+    /// unsupported algorithms is refused rather than silently verifying nothing.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let mut expected = vec![];
+
+        for name in [CONTENT_DIGEST, REPR_DIGEST] {
+            for value in headers.get_all(&name) {
+                let value = value
+                    .to_str()
+                    .map_err(|_| invalid(&name, "not valid ASCII"))?;
+                let parsed = parse_dictionary(value).map_err(|reason| invalid(&name, reason))?;
+                if parsed.is_empty() {
+                    return Err(invalid(
+                        &name,
+                        "no supported algorithm, expected sha-256 or sha-512",
+                    ));
+                }
+                expected.extend(parsed);
+            }
+        }
+
+        if let Some(value) = headers.get(CONTENT_MD5) {
+            let digest = value
+                .to_str()
+                .ok()
+                .and_then(|value| base64::decode_block(value.trim()).ok())
+                .filter(|digest| digest.len() == 16)
+                .ok_or_else(|| invalid(&CONTENT_MD5, "expected a base64 MD5 digest"))?;
+            expected.push((Algorithm::Md5, digest));
+        }
+
+        Ok(Self(expected))
+    }
+
+    pub fn algorithms(&self) -> impl Iterator<Item = Algorithm> + '_ {
+        self.0.iter().map(|(algorithm, _)| *algorithm)
+    }
+
+    /// Check `computed` against every expected digest. Returns the algorithms that were verified.
+    pub fn verify(&self, what: &str, computed: &Digests) -> Result<Vec<Algorithm>, AppError> {
+        let mut verified = vec![];
+        for (algorithm, expected) in &self.0 {
+            match computed.get(*algorithm) {
+                Some(actual) if actual == expected.as_slice() => verified.push(*algorithm),
+                Some(actual) => {
+                    return Err(AppError::DigestMismatch(format!(
+                        "{} {} digest is {}, the client sent {}",
+                        what,
+                        algorithm.name(),
+                        base64::encode_block(actual),
+                        base64::encode_block(expected)
+                    )))
+                }
+                None => {
+                    return Err(AppError::Internal(anyhow::anyhow!(
+                        "{} digest was not computed",
+                        algorithm.name()
+                    )))
+                }
+            }
+        }
+        verified.sort();
+        verified.dedup();
+        Ok(verified)
+    }
+}
+
+fn invalid(header: &HeaderName, reason: &str) -> AppError {
+    AppError::InvalidDigest(format!("invalid {} header: {}", header.as_str(), reason))
+}
+
+/// Parse an RFC 8941 dictionary of byte sequences, e.g. `sha-256=:base64:, sha-512=:base64:`,
+/// keeping the algorithms we support.
+fn parse_dictionary(value: &str) -> Result<Vec<(Algorithm, Vec<u8>)>, &'static str> {
+    let mut digests = vec![];
+
+    for member in value.split(',').map(str::trim).filter(|m| !m.is_empty()) {
+        // Parameters carry nothing we use
+        let member = member.split(';').next().unwrap_or_default();
+        let (key, value) = member
+            .split_once('=')
+            .ok_or("expected algorithm=:digest:")?;
+        let encoded = value
+            .trim()
+            .strip_prefix(':')
+            .and_then(|value| value.strip_suffix(':'))
+            .ok_or("digests must be byte sequences, :base64:")?;
+        let digest = base64::decode_block(encoded).map_err(|_| "digest is not valid base64")?;
+
+        if let Some(algorithm) = Algorithm::from_name(key.trim()) {
+            digests.push((algorithm, digest));
+        }
+    }
+
+    Ok(digests)
+}
+
+/// Digests computed over some content.
+#[derive(Debug, Clone, Default)]
+pub struct Digests(BTreeMap<Algorithm, Vec<u8>>);
+
+impl Digests {
+    pub fn get(&self, algorithm: Algorithm) -> Option<&[u8]> {
+        self.0.get(&algorithm).map(Vec::as_slice)
+    }
+
+    pub fn hex(&self, algorithm: Algorithm) -> Option<String> {
+        self.get(algorithm).map(hex)
+    }
+
+    /// Like [`Digests::hex`], for digests that were certainly computed.
+    pub fn require_hex(&self, algorithm: Algorithm) -> anyhow::Result<String> {
+        self.hex(algorithm)
+            .ok_or_else(|| anyhow::anyhow!("{} digest was not computed", algorithm.name()))
+    }
+
+    /// Every digest, hex-encoded and keyed by algorithm name.
+    pub fn to_hex_map(&self) -> BTreeMap<&'static str, String> {
+        self.0
+            .iter()
+            .map(|(algorithm, digest)| (algorithm.name(), hex(digest)))
+            .collect()
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A `Repr-Digest` header value for a hex SHA-256, e.g. one from the catalog.
+pub fn repr_digest(sha256_hex: &str) -> Option<String> {
+    let bytes = (0..sha256_hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(sha256_hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    (bytes.len() == 32).then(|| format!("sha-256=:{}:", base64::encode_block(&bytes)))
+}
+
+type Hashers = Vec<(Algorithm, Hasher)>;
+
+fn hashers(algorithms: impl IntoIterator<Item = Algorithm>) -> anyhow::Result<Hashers> {
+    let mut algorithms: Vec<Algorithm> = algorithms.into_iter().collect();
+    algorithms.sort();
+    algorithms.dedup();
+
+    algorithms
+        .into_iter()
+        .map(|algorithm| Ok((algorithm, Hasher::new(algorithm.message_digest())?)))
+        .collect()
+}
+
+fn update(hashers: &mut Hashers, data: &[u8]) -> io::Result<()> {
+    for (_, hasher) in hashers {
+        hasher.update(data).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+fn finish(hashers: Hashers) -> anyhow::Result<Digests> {
+    hashers
+        .into_iter()
+        .map(|(algorithm, mut hasher)| Ok((algorithm, hasher.finish()?.to_vec())))
+        .collect::<anyhow::Result<_>>()
+        .map(Digests)
+}
+
+/// Computes digests of everything read through it.
+pub struct DigestingReader<R> {
+    inner: R,
+    hashers: Hashers,
+}
+
+impl<R> DigestingReader<R> {
+    pub fn new(inner: R, algorithms: impl IntoIterator<Item = Algorithm>) -> anyhow::Result<Self> {
+        Ok(Self {
+            inner,
+            hashers: hashers(algorithms)?,
+        })
+    }
+
+    pub fn finish(self) -> anyhow::Result<Digests> {
+        finish(self.hashers)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DigestingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let this = &mut *self;
+            update(&mut this.hashers, &buf.filled()[filled..])?;
+        }
+        poll
+    }
+}
+
+/// Computes digests of a request body that something else (e.g. a multipart parser) consumes
+/// through [`DigestingBody::stream`].
+///
+/// That consumer may stop before the end of the body, so [`DigestingBody::finish`] reads whatever
+/// it left behind before the digests are final.
+#[derive(Clone)]
+pub struct DigestingBody(Arc<Mutex<(Body, Hashers)>>);
+
+impl DigestingBody {
+    pub fn new(
+        body: Body,
+        algorithms: impl IntoIterator<Item = Algorithm>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self(Arc::new(Mutex::new((body, hashers(algorithms)?)))))
+    }
+
+    fn poll_data(&self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        let mut guard = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let (body, hashers) = &mut *guard;
+        match Pin::new(body).poll_data(cx) {
+            Poll::Ready(Some(Ok(data))) => Poll::Ready(Some(update(hashers, &data).map(|()| data))),
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(io::Error::other(err)))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// The body's data, digested as it is read.
+    pub fn stream(&self) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+        let this = self.clone();
+        futures_util::stream::poll_fn(move |cx| this.poll_data(cx))
+    }
+
+    /// Read the rest of the body, up to `limit` bytes, and return the digests of all of it.
+    pub async fn finish(self, limit: usize) -> Result<Digests, AppError> {
+        let mut remaining = limit;
+        while let Some(data) = future::poll_fn(|cx| self.poll_data(cx)).await {
+            remaining = remaining
+                .checked_sub(data?.len())
+                .ok_or_else(|| AppError::BadRequest(String::from("unexpected data after body")))?;
+        }
+
+        let mut guard = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(finish(std::mem::take(&mut guard.1))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use futures_util::StreamExt;
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn digest(algorithm: Algorithm, data: &[u8]) -> Vec<u8> {
+        openssl::hash::hash(algorithm.message_digest(), data)
+            .unwrap()
+            .to_vec()
+    }
+
+    fn encoded(algorithm: Algorithm, data: &[u8]) -> String {
+        base64::encode_block(&digest(algorithm, data))
+    }
+
+    fn computed(data: &[u8]) -> Digests {
+        Digests(
+            [Algorithm::Sha256, Algorithm::Sha512, Algorithm::Md5]
+                .into_iter()
+                .map(|algorithm| (algorithm, digest(algorithm, data)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_dictionaries() {
+        let sha256 = encoded(Algorithm::Sha256, b"hello");
+        let sha512 = encoded(Algorithm::Sha512, b"hello");
+        for (value, expected) in [
+            (format!("sha-256=:{}:", sha256), vec![Algorithm::Sha256]),
+            (
+                format!("sha-512=:{}:, sha-256=:{}:", sha512, sha256),
+                vec![Algorithm::Sha512, Algorithm::Sha256],
+            ),
+            (
+                format!(" sha-256 = :{}: ;foo=1;bar ,", sha256),
+                vec![Algorithm::Sha256],
+            ),
+            (
+                format!("unixsum=:AAAA:, sha-256=:{}:", sha256),
+                vec![Algorithm::Sha256],
+            ),
+            (String::from("unixsum=:AAAA:, id-sha-256=:AAAA:"), vec![]),
+            (String::new(), vec![]),
+        ] {
+            let parsed = parse_dictionary(&value).unwrap();
+            assert_eq!(
+                parsed.iter().map(|(a, _)| *a).collect::<Vec<_>>(),
+                expected,
+                "{:?}",
+                value
+            );
+        }
+
+        let parsed = parse_dictionary(&format!("sha-256=:{}:;a=b", sha256)).unwrap();
+        assert_eq!(parsed[0].1, digest(Algorithm::Sha256, b"hello"));
+    }
+
+    #[test]
+    fn rejects_malformed_dictionaries() {
+        for value in [
+            "sha-256",
+            "sha-256=abc",
+            "sha-256=:abc",
+            "sha-256=\"abc\"",
+            "sha-256=:not base64!:",
+            "sha-256=:AAAA:, md5",
+        ] {
+            assert!(parse_dictionary(value).is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn collects_digest_headers() {
+        let sha256 = format!("sha-256=:{}:", encoded(Algorithm::Sha256, b"hello"));
+        let sha512 = format!("sha-512=:{}:", encoded(Algorithm::Sha512, b"hello"));
+        let md5 = encoded(Algorithm::Md5, b"hello");
+        for (pairs, expected) in [
+            (vec![], vec![]),
+            (
+                vec![(CONTENT_DIGEST, sha256.as_str())],
+                vec![Algorithm::Sha256],
+            ),
+            (
+                vec![(REPR_DIGEST, sha512.as_str())],
+                vec![Algorithm::Sha512],
+            ),
+            (vec![(CONTENT_MD5, md5.as_str())], vec![Algorithm::Md5]),
+            (
+                vec![
+                    (CONTENT_MD5, md5.as_str()),
+                    (REPR_DIGEST, sha512.as_str()),
+                    (CONTENT_DIGEST, sha256.as_str()),
+                ],
+                vec![Algorithm::Sha256, Algorithm::Sha512, Algorithm::Md5],
+            ),
+        ] {
+            let expected_digests = Expected::from_headers(&headers(&pairs)).unwrap();
+            assert_eq!(
+                expected_digests.algorithms().collect::<Vec<_>>(),
+                expected,
+                "{:?}",
+                pairs
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_digest_headers() {
+        let short_md5 = base64::encode_block(&[0; 8]);
+        for pairs in [
+            vec![(CONTENT_DIGEST, "unixsum=:AAAA:")],
+            vec![(REPR_DIGEST, "id-sha-512=:AAAA:")],
+            vec![(CONTENT_DIGEST, "sha-256=:***:")],
+            vec![(CONTENT_DIGEST, "sha-256")],
+            vec![(CONTENT_MD5, "not base64")],
+            vec![(CONTENT_MD5, short_md5.as_str())],
+        ] {
+            assert!(
+                matches!(
+                    Expected::from_headers(&headers(&pairs)),
+                    Err(AppError::InvalidDigest(_))
+                ),
+                "{:?}",
+                pairs
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_digests() {
+        let sha256 = format!("sha-256=:{}:", encoded(Algorithm::Sha256, b"hello"));
+        let md5 = encoded(Algorithm::Md5, b"hello");
+        let expected = Expected::from_headers(&headers(&[
+            (CONTENT_DIGEST, sha256.as_str()),
+            (REPR_DIGEST, sha256.as_str()),
+            (CONTENT_MD5, md5.as_str()),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            expected.verify("upload", &computed(b"hello")).unwrap(),
+            vec![Algorithm::Sha256, Algorithm::Md5]
+        );
+        assert!(matches!(
+            expected.verify("upload", &computed(b"hullo")),
+            Err(AppError::DigestMismatch(_))
+        ));
+        // Nothing expected, nothing to verify
+        assert_eq!(
+            Expected::default()
+                .verify("upload", &computed(b"hello"))
+                .unwrap(),
+            vec![]
+        );
+        // Computing too little is our mistake, not the client's
+        assert!(matches!(
+            expected.verify("upload", &Digests::default()),
+            Err(AppError::Internal(_))
+        ));
+    }
+
+    fn chunked(chunks: &[&'static str]) -> Body {
+        let chunks: Vec<io::Result<Bytes>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+            .collect();
+        Body::wrap_stream(futures_util::stream::iter(chunks))
+    }
+
+    #[tokio::test]
+    async fn digests_what_the_consumer_left() {
+        let algorithms = [Algorithm::Sha256, Algorithm::Md5];
+        let body = DigestingBody::new(chunked(&["hel", "lo ", "world"]), algorithms).unwrap();
+
+        // The consumer stops after the first chunk
+        let first = body.stream().next().await.unwrap().unwrap();
+        assert_eq!(first, &b"hel"[..]);
+
+        let digests = body.finish(16).await.unwrap();
+        assert_eq!(
+            digests.get(Algorithm::Sha256).unwrap(),
+            digest(Algorithm::Sha256, b"hello world")
+        );
+        assert_eq!(
+            digests.get(Algorithm::Md5).unwrap(),
+            digest(Algorithm::Md5, b"hello world")
+        );
+        assert_eq!(digests.get(Algorithm::Sha512), None);
+    }
+
+    #[tokio::test]
+    async fn limits_what_finish_reads() {
+        let body =
+            DigestingBody::new(chunked(&["hel", "lo ", "world"]), [Algorithm::Sha256]).unwrap();
+        body.stream().next().await.unwrap().unwrap();
+        // Exactly the rest is fine, one byte less isn't
+        assert!(body.clone().finish(8).await.is_ok());
+
+        let body =
+            DigestingBody::new(chunked(&["hel", "lo ", "world"]), [Algorithm::Sha256]).unwrap();
+        body.stream().next().await.unwrap().unwrap();
+        assert!(matches!(body.finish(7).await, Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn encodes_repr_digests() {
+        let sha256 = digest(Algorithm::Sha256, b"hello");
+        assert_eq!(
+            repr_digest(&hex(&sha256)),
+            Some(format!("sha-256=:{}:", base64::encode_block(&sha256)))
+        );
+        for value in ["", "abc", "zz", &hex(&[0; 31])] {
+            assert_eq!(repr_digest(value), None, "{:?}", value);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use log::LevelFilter;
+use crate::config::LogConfig;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::Appender;
 use log4rs::config::Config;
@@ -6,37 +6,39 @@ use log4rs::config::Logger;
 use log4rs::config::Root;
 use log4rs::encode::pattern::PatternEncoder;
 
+pub use log4rs::Handle;
+
 /// Initialize logging factilities.
-pub fn initialize() {
-    let conf = Config::builder()
-        .appender(
-            Appender::builder().build(
-                "stdout",
-                Box::new(
-                    ConsoleAppender::builder()
-                        .encoder(Box::new(PatternEncoder::new(
-                            "{h([{d(%H:%M:%S)}] {l:>6}: {m})}{n}",
-                        )))
-                        .build(),
-                ),
+pub fn initialize(log: &LogConfig) -> anyhow::Result<Handle> {
+    Ok(log4rs::init_config(build(log)?)?)
+}
+
+/// Swap in new log levels without restarting.
+pub fn reconfigure(handle: &Handle, log: &LogConfig) -> anyhow::Result<()> {
+    handle.set_config(build(log)?);
+    Ok(())
+}
+
+fn build(log: &LogConfig) -> anyhow::Result<Config> {
+    let builder = Config::builder().appender(
+        Appender::builder().build(
+            "stdout",
+            Box::new(
+                ConsoleAppender::builder()
+                    .encoder(Box::new(PatternEncoder::new(
+                        "{h([{d(%H:%M:%S)}] {l:>6}: {m})}{n}",
+                    )))
+                    .build(),
             ),
-        )
-        .logger(Logger::builder().build("h2", LevelFilter::Info))
-        .logger(Logger::builder().build("hyper", LevelFilter::Info))
-        .logger(Logger::builder().build("mio", LevelFilter::Info))
-        .logger(Logger::builder().build("rdkafka", LevelFilter::Info))
-        .logger(Logger::builder().build("rustls", LevelFilter::Info))
-        .logger(Logger::builder().build("scylla", LevelFilter::Info))
-        .logger(Logger::builder().build("tokio_tungstenite", LevelFilter::Info))
-        .logger(Logger::builder().build("trust_dns_proto", LevelFilter::Info))
-        .logger(Logger::builder().build("trust_dns_resolver", LevelFilter::Info))
-        .logger(Logger::builder().build("tungstenite", LevelFilter::Info))
-        .logger(Logger::builder().build("twilight_gateway", LevelFilter::Info))
-        .logger(Logger::builder().build("twilight_http", LevelFilter::Info))
-        .logger(Logger::builder().build("twilight_model", LevelFilter::Info))
-        .logger(Logger::builder().build("want", LevelFilter::Info))
-        .build(Root::builder().appender("stdout").build(LevelFilter::Debug))
-        .expect("failed to build log4rs configuration");
+        ),
+    );
+
+    let builder = log
+        .module_levels()?
+        .into_iter()
+        .fold(builder, |builder, (module, level)| {
+            builder.logger(Logger::builder().build(module, level))
+        });
 
-    log4rs::init_config(conf).expect("failed to initialize log4rs");
+    Ok(builder.build(Root::builder().appender("stdout").build(log.root_level()?))?)
 }
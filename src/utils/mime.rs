@@ -0,0 +1,177 @@
+use crate::{
+    mp4::FileType,
+    storage::{self, StorageBackend},
+};
+use std::path::Path;
+
+/// How much of a file is inspected when sniffing; enough to reach the Matroska `DocType`.
+pub const SNIFF_LEN: usize = 512;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Detect a MIME type from a file's leading bytes, falling back to its extension.
+pub fn detect(head: &[u8], file_name: &str) -> &'static str {
+    sniff(head)
+        .or_else(|| from_extension(file_name))
+        .unwrap_or(OCTET_STREAM)
+}
+
+/// Read the head of the stored object `key` and [`detect`] its type, falling back to the
+/// extension of `file_name`.
+pub async fn detect_object(
+    storage: &dyn StorageBackend,
+    key: &str,
+    file_name: &str,
+) -> anyhow::Result<&'static str> {
+    let head = storage::read_head(storage, key, SNIFF_LEN as u64).await?;
+    Ok(detect(&head, file_name))
+}
+
+/// Identify a container by its magic bytes.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    if let Some(box_type) = head.get(4..8) {
+        match box_type {
+            b"ftyp" => return FileType::from_head(head).map(|ftyp| ftyp.content_type()),
+            // Pre-ftyp QuickTime files open straight into one of these atoms
+            b"moov" | b"mdat" | b"wide" | b"free" | b"skip" | b"pnot" => {
+                return Some("video/quicktime")
+            }
+            _ => {}
+        }
+    }
+
+    if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        return Some(ebml_doc_type(head));
+    }
+
+    if head.starts_with(b"RIFF") {
+        return match head.get(8..12)? {
+            b"AVI " => Some("video/x-msvideo"),
+            b"WAVE" => Some("audio/wav"),
+            b"WEBP" => Some("image/webp"),
+            _ => None,
+        };
+    }
+
+    if head.starts_with(b"OggS") {
+        return Some(ogg_codec(head));
+    }
+
+    // MPEG transport streams repeat the sync byte every 188-byte packet
+    if head.first() == Some(&0x47) && head.get(188) == Some(&0x47) {
+        return Some("video/mp2t");
+    }
+
+    let signatures: &[(&[u8], &str)] = &[
+        (&[0x00, 0x00, 0x01, 0xBA], "video/mpeg"),
+        (&[0x00, 0x00, 0x01, 0xB3], "video/mpeg"),
+        (b"FLV\x01", "video/x-flv"),
+        (
+            &[0x30, 0x26, 0xB2, 0x75, 0x8E, 0x66, 0xCF, 0x11],
+            "video/x-ms-asf",
+        ),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (&[0xFF, 0xD8, 0xFF], "image/jpeg"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+    ];
+    if let Some((_, mime)) = signatures.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(mime);
+    }
+
+    sniff_text(head)
+}
+
+/// Tell WebM apart from generic Matroska by the `DocType` element (ID 0x4282) in the EBML header.
+fn ebml_doc_type(head: &[u8]) -> &'static str {
+    let doc_type = head
+        .windows(2)
+        .position(|w| w == [0x42, 0x82])
+        .and_then(|i| head.get(i + 3..))
+        .unwrap_or_default();
+
+    if doc_type.starts_with(b"webm") {
+        "video/webm"
+    } else {
+        "video/x-matroska"
+    }
+}
+
+/// The first Ogg page carries the identification header of the first logical stream.
+fn ogg_codec(head: &[u8]) -> &'static str {
+    let contains = |needle: &[u8]| head.windows(needle.len()).any(|w| w == needle);
+
+    if contains(b"\x80theora") || contains(b"\x80kate") {
+        "video/ogg"
+    } else if contains(b"\x01vorbis") || contains(b"OpusHead") || contains(b"\x7fFLAC") {
+        "audio/ogg"
+    } else {
+        "application/ogg"
+    }
+}
+
+fn sniff_text(head: &[u8]) -> Option<&'static str> {
+    let text = std::str::from_utf8(head)
+        .or_else(|err| std::str::from_utf8(&head[..err.valid_up_to()]))
+        .ok()?;
+    let text = text.trim_start_matches('\u{feff}');
+
+    if text.starts_with("WEBVTT") {
+        return Some("text/vtt");
+    }
+    if text.starts_with("[Script Info]") {
+        return Some("text/x-ssa");
+    }
+
+    // SubRip: a cue counter followed by a timing line
+    let mut lines = text.trim_start().lines();
+    let counter = lines.next()?.trim();
+    let timing = lines.next()?;
+    if !counter.is_empty() && counter.bytes().all(|b| b.is_ascii_digit()) && timing.contains("-->")
+    {
+        return Some("application/x-subrip");
+    }
+
+    None
+}
+
+pub fn from_extension(file_name: &str) -> Option<&'static str> {
+    let extension = Path::new(file_name)
+        .extension()?
+        .to_str()?
+        .to_ascii_lowercase();
+
+    let mime = match extension.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "mov" | "qt" => "video/quicktime",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "avi" => "video/x-msvideo",
+        "ts" | "m2ts" | "mts" => "video/mp2t",
+        "mpg" | "mpeg" => "video/mpeg",
+        "ogv" => "video/ogg",
+        "flv" => "video/x-flv",
+        "wmv" | "asf" => "video/x-ms-asf",
+        "3gp" => "video/3gpp",
+        "3g2" => "video/3gpp2",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "vtt" => "text/vtt",
+        "srt" => "application/x-subrip",
+        "ass" | "ssa" => "text/x-ssa",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "heic" => "image/heic",
+        _ => return None,
+    };
+
+    Some(mime)
+}
@@ -1 +1,6 @@
+pub mod conditional;
+pub mod digest;
 pub mod logger;
+pub mod mime;
+pub mod range;
+pub mod request_id;
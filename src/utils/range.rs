@@ -0,0 +1,306 @@
+use crate::storage::StorageBackend;
+use axum::body::{Bytes, StreamBody};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio_util::io::ReaderStream;
+
+/// Upper bound on the number of ranges honoured in a single request.
+const MAX_RANGES: usize = 64;
+
+pub type ByteStream = Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>>;
+
+/// An inclusive byte range, as it appears in `Content-Range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, complete_length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, complete_length)
+    }
+}
+
+/// Outcome of evaluating a `Range` header against a representation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// No usable `Range` header; serve the full representation.
+    Full,
+    /// One or more satisfiable ranges, sorted and coalesced.
+    Partial(Vec<ByteRange>),
+    /// Syntactically valid, but none of the ranges overlap the representation.
+    Unsatisfiable,
+}
+
+/// Evaluate a `Range` header value against a representation of `length` bytes (RFC 7233 §2.1).
+///
+/// Headers with an unknown unit or invalid syntax are ignored, as the RFC requires.
+pub fn parse(header: &str, length: u64) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+    let mut any_spec = false;
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        any_spec = true;
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        let range = if first.is_empty() {
+            // suffix-byte-range-spec: the final `last` bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            if suffix == 0 || length == 0 {
+                None
+            } else {
+                Some(ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                })
+            }
+        } else {
+            let Ok(start) = first.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = if last.is_empty() {
+                u64::MAX
+            } else {
+                match last.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return RangeRequest::Full,
+                }
+            };
+            (start < length).then(|| ByteRange {
+                start,
+                end: end.min(length - 1),
+            })
+        };
+
+        ranges.extend(range);
+        if ranges.len() > MAX_RANGES {
+            return RangeRequest::Full;
+        }
+    }
+
+    if ranges.is_empty() {
+        // A range set needs at least one range to be valid at all
+        return if !any_spec {
+            RangeRequest::Full
+        } else {
+            RangeRequest::Unsatisfiable
+        };
+    }
+
+    RangeRequest::Partial(coalesce(ranges))
+}
+
+/// Sort ranges and merge any that overlap or touch, so a client can't make us send the same bytes twice.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Stream `range` of the object at `key` without buffering it; nothing is opened until the body is polled.
+pub fn object_range_stream(
+    storage: Arc<dyn StorageBackend>,
+    key: String,
+    range: Option<ByteRange>,
+) -> ByteStream {
+    stream::once(async move {
+        let reader = storage
+            .get_range_stream(&key, range)
+            .await
+            .map_err(std::io::Error::other)?;
+        Ok::<_, std::io::Error>(ReaderStream::new(reader))
+    })
+    .try_flatten()
+    .boxed()
+}
+
+/// A `multipart/byteranges` body (RFC 7233 §4.1) together with its exact length.
+pub struct MultipartRanges {
+    pub content_type: String,
+    pub content_length: u64,
+    pub body: StreamBody<ByteStream>,
+}
+
+pub fn multipart_byteranges(
+    storage: Arc<dyn StorageBackend>,
+    key: &str,
+    ranges: &[ByteRange],
+    part_content_type: &str,
+    complete_length: u64,
+) -> MultipartRanges {
+    let boundary = boundary();
+    let mut parts: Vec<ByteStream> = Vec::with_capacity(ranges.len() * 2 + 1);
+    let mut content_length = 0;
+
+    for range in ranges {
+        let head = Bytes::from(format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary,
+            part_content_type,
+            range.content_range(complete_length)
+        ));
+        content_length += head.len() as u64 + range.len();
+        parts.push(stream::once(async move { Ok(head) }).boxed());
+        parts.push(object_range_stream(
+            storage.clone(),
+            key.to_string(),
+            Some(*range),
+        ));
+    }
+
+    let tail = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+    content_length += tail.len() as u64;
+    parts.push(stream::once(async move { Ok(tail) }).boxed());
+
+    MultipartRanges {
+        content_type: format!("multipart/byteranges; boundary={}", boundary),
+        content_length,
+        body: StreamBody::new(stream::iter(parts).flatten().boxed()),
+    }
+}
+
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:032x}", nanos ^ ((std::process::id() as u128) << 64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    fn partial(ranges: &[(u64, u64)]) -> RangeRequest {
+        RangeRequest::Partial(ranges.iter().map(|&(s, e)| range(s, e)).collect())
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse("bytes=0-0", 100), partial(&[(0, 0)]));
+        assert_eq!(parse("bytes=10-19", 100), partial(&[(10, 19)]));
+        assert_eq!(parse(" bytes= 10 - 19 ", 100), partial(&[(10, 19)]));
+        // Open-ended, and an end past EOF, stop at the last byte
+        assert_eq!(parse("bytes=90-", 100), partial(&[(90, 99)]));
+        assert_eq!(parse("bytes=90-1000", 100), partial(&[(90, 99)]));
+        assert_eq!(parse("bytes=99-99", 100), partial(&[(99, 99)]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse("bytes=-10", 100), partial(&[(90, 99)]));
+        assert_eq!(parse("bytes=-100", 100), partial(&[(0, 99)]));
+        // Longer than the representation: all of it
+        assert_eq!(parse("bytes=-1000", 100), partial(&[(0, 99)]));
+        assert_eq!(parse("bytes=-0", 100), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn rejects_ranges_past_eof() {
+        assert_eq!(parse("bytes=100-", 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=100-200", 100), RangeRequest::Unsatisfiable);
+        // Satisfiable ones are kept even if others aren't
+        assert_eq!(parse("bytes=100-200,0-9", 100), partial(&[(0, 9)]));
+    }
+
+    #[test]
+    fn zero_length_representations_satisfy_nothing() {
+        assert_eq!(parse("bytes=0-0", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-10", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for header in [
+            "",
+            "bytes",
+            "bytes=",
+            "bytes=,",
+            "items=0-9",
+            "Bytes=0-9",
+            "bytes=a-9",
+            "bytes=0-b",
+            "bytes=9-0",
+            "bytes=0",
+            "bytes=--5",
+            "bytes=0-9,x",
+            "bytes=-",
+            "bytes=18446744073709551616-",
+        ] {
+            assert_eq!(parse(header, 100), RangeRequest::Full, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn coalesces_overlapping_and_adjacent_ranges() {
+        assert_eq!(parse("bytes=0-9,5-14", 100), partial(&[(0, 14)]));
+        assert_eq!(parse("bytes=0-9,10-19", 100), partial(&[(0, 19)]));
+        assert_eq!(parse("bytes=20-29,0-9", 100), partial(&[(0, 9), (20, 29)]));
+        assert_eq!(parse("bytes=0-9,11-19", 100), partial(&[(0, 9), (11, 19)]));
+        assert_eq!(parse("bytes=0-99,10-19", 100), partial(&[(0, 99)]));
+        assert_eq!(parse("bytes=-10,50-", 100), partial(&[(50, 99)]));
+        assert_eq!(
+            coalesce(vec![
+                range(5, 5),
+                range(0, 4),
+                range(u64::MAX - 1, u64::MAX)
+            ]),
+            vec![range(0, 5), range(u64::MAX - 1, u64::MAX)]
+        );
+    }
+
+    #[test]
+    fn caps_the_number_of_ranges() {
+        let specs = |n: u64| {
+            (0..n)
+                .map(|i| format!("{}-{}", i * 2, i * 2))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        match parse(&format!("bytes={}", specs(MAX_RANGES as u64)), 1000) {
+            RangeRequest::Partial(ranges) => assert_eq!(ranges.len(), MAX_RANGES),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            parse(&format!("bytes={}", specs(MAX_RANGES as u64 + 1)), 1000),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn describes_ranges() {
+        assert_eq!(range(0, 0).len(), 1);
+        assert_eq!(range(10, 19).len(), 10);
+        assert_eq!(range(10, 19).content_range(100), "bytes 10-19/100");
+    }
+}